@@ -1,5 +1,5 @@
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 
 #[derive(Debug, Serialize)]
 pub struct OrchestratorDetector {
@@ -44,6 +44,8 @@ struct DetectionResult {
     detection_type: String,
     detection: String,
     detector_id: String,
+    // Detector backends disagree on what to call the confidence; responses always use `score`
+    #[serde(alias = "confidence", alias = "probability")]
     score: f64,
 }
 
@@ -65,6 +67,263 @@ pub struct Detections {
     output: Option<Vec<OutputDetection>>,
 }
 
+impl Detections {
+    fn results_mut(&mut self) -> impl Iterator<Item = &mut Vec<DetectionResult>> {
+        let input = self.input.iter_mut().flatten().map(|d| &mut d.results);
+        let output = self.output.iter_mut().flatten().map(|d| &mut d.results);
+        input.chain(output).flatten()
+    }
+
+    /// Whether anything was detected. The orchestrator sends a detections object even when
+    /// nothing was found, with empty or null `results`, so its presence alone means nothing.
+    pub fn has_hits(&self) -> bool {
+        self.has_input_hits() || self.has_output_hits()
+    }
+
+    /// Whether any input (prompt) entry carries results.
+    pub fn has_input_hits(&self) -> bool {
+        self.input
+            .iter()
+            .flatten()
+            .any(|d| d.results.as_ref().is_some_and(|r| !r.is_empty()))
+    }
+
+    /// Whether any output (generated content) entry carries results.
+    pub fn has_output_hits(&self) -> bool {
+        self.output
+            .iter()
+            .flatten()
+            .any(|d| d.results.as_ref().is_some_and(|r| !r.is_empty()))
+    }
+
+    /// Drops results scoring below the threshold of the detector that produced them, keyed by
+    /// detector id, so low-confidence hits are treated as if they had not been reported.
+    pub fn drop_below_thresholds(&mut self, thresholds: &HashMap<String, f64>) {
+        if thresholds.is_empty() {
+            return;
+        }
+        for results in self.results_mut() {
+            results.retain(|r| {
+                thresholds
+                    .get(&r.detector_id)
+                    .is_none_or(|threshold| r.score >= *threshold)
+            });
+        }
+    }
+
+    /// Total number of detection results across all input and output entries.
+    pub fn result_count(&mut self) -> usize {
+        self.results_mut().map(|results| results.len()).sum()
+    }
+
+    /// Keeps only the `max` highest-scoring results across all entries, preserving their order.
+    pub fn truncate_results(&mut self, max: usize) {
+        let mut scores: Vec<(usize, f64)> = self
+            .results_mut()
+            .flat_map(|results| results.iter().map(|r| r.score).collect::<Vec<_>>())
+            .enumerate()
+            .collect();
+        if scores.len() <= max {
+            return;
+        }
+        scores.sort_by(|a, b| b.1.total_cmp(&a.1));
+        let keep: HashSet<usize> = scores.into_iter().take(max).map(|(i, _)| i).collect();
+
+        let mut index = 0;
+        for results in self.results_mut() {
+            results.retain(|_| {
+                index += 1;
+                keep.contains(&(index - 1))
+            });
+        }
+    }
+}
+
+/// Replaces each `(start, end)` character span of `content` with `mask` in a single pass.
+/// Spans may come in any order; overlapping or adjacent spans share a single mask, empty spans
+/// are ignored and spans running past the end of the content are clipped to it.
+pub fn apply_redactions(content: &str, spans: &[(usize, usize)], mask: &str) -> String {
+    let mut spans: Vec<(usize, usize)> = spans.iter().copied().filter(|(s, e)| s < e).collect();
+    spans.sort_unstable();
+    let mut merged: Vec<(usize, usize)> = Vec::with_capacity(spans.len());
+    for (start, end) in spans {
+        match merged.last_mut() {
+            Some(last) if start <= last.1 => last.1 = last.1.max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+
+    // Merged spans are strictly increasing, so char offsets can be mapped to byte offsets by
+    // walking the content once
+    let mut boundaries = content
+        .char_indices()
+        .map(|(i, _)| i)
+        .chain(std::iter::once(content.len()))
+        .enumerate();
+    let mut byte_offset = |char_offset: usize| {
+        boundaries
+            .find(|&(c, _)| c == char_offset)
+            .map_or(content.len(), |(_, b)| b)
+    };
+
+    let mut output = String::with_capacity(content.len());
+    let mut copied = 0;
+    for (start, end) in merged {
+        let start = byte_offset(start);
+        if start == content.len() {
+            break;
+        }
+        output.push_str(&content[copied..start]);
+        output.push_str(mask);
+        copied = byte_offset(end);
+    }
+    output.push_str(&content[copied..]);
+    output
+}
+
+/// Digest of a response's detections for clients that don't want to walk the raw arrays.
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+pub struct DetectionSummary {
+    pub input_count: usize,
+    pub output_count: usize,
+    /// `input`, `output` or `input_and_output`, depending on which side was flagged
+    pub blocked_by: Option<String>,
+    pub detection_types: Vec<String>,
+}
+
+impl DetectionSummary {
+    /// Folds another summary into this one, e.g. to cover every chunk of a stream.
+    pub fn add(&mut self, other: DetectionSummary) {
+        self.input_count += other.input_count;
+        self.output_count += other.output_count;
+        self.blocked_by = match (self.input_count > 0, self.output_count > 0) {
+            (false, false) => None,
+            (true, false) => Some("input".to_string()),
+            (false, true) => Some("output".to_string()),
+            (true, true) => Some("input_and_output".to_string()),
+        };
+        self.detection_types.extend(other.detection_types);
+        self.detection_types.sort();
+        self.detection_types.dedup();
+    }
+}
+
+impl Detections {
+    pub fn summary(&self) -> DetectionSummary {
+        let input: Vec<&DetectionResult> = self
+            .input
+            .iter()
+            .flatten()
+            .flat_map(|d| d.results.iter().flatten())
+            .collect();
+        let output: Vec<&DetectionResult> = self
+            .output
+            .iter()
+            .flatten()
+            .flat_map(|d| d.results.iter().flatten())
+            .collect();
+        let blocked_by = match (input.is_empty(), output.is_empty()) {
+            (true, true) => None,
+            (false, true) => Some("input"),
+            (true, false) => Some("output"),
+            (false, false) => Some("input_and_output"),
+        };
+        let mut detection_types: Vec<String> = input
+            .iter()
+            .chain(output.iter())
+            .map(|r| r.detection_type.clone())
+            .collect();
+        detection_types.sort();
+        detection_types.dedup();
+        DetectionSummary {
+            input_count: input.len(),
+            output_count: output.len(),
+            blocked_by: blocked_by.map(str::to_string),
+            detection_types,
+        }
+    }
+
+    fn top(&self) -> Option<&DetectionResult> {
+        let input = self.input.iter().flatten().map(|d| &d.results);
+        let output = self.output.iter().flatten().map(|d| &d.results);
+        input
+            .chain(output)
+            .flatten()
+            .flatten()
+            .max_by(|a, b| a.score.total_cmp(&b.score))
+    }
+
+    /// Detector id and score of the highest scoring result, if any.
+    pub fn top_result(&self) -> Option<(&str, f64)> {
+        self.top().map(|r| (r.detector_id.as_str(), r.score))
+    }
+
+    /// Detection type of the highest scoring result, if any.
+    pub fn top_detection_type(&self) -> Option<&str> {
+        self.top().map(|r| r.detection_type.as_str())
+    }
+
+    /// Ids of the detectors behind every input and output result.
+    pub fn flagged_detectors(&self) -> impl Iterator<Item = &str> {
+        let input = self.input.iter().flatten().map(|d| &d.results);
+        let output = self.output.iter().flatten().map(|d| &d.results);
+        input
+            .chain(output)
+            .flatten()
+            .flatten()
+            .map(|r| r.detector_id.as_str())
+    }
+
+    /// Number of results from each detector, by detector id.
+    pub fn counts_by_detector(&self) -> BTreeMap<String, usize> {
+        let mut counts = BTreeMap::new();
+        for detector_id in self.flagged_detectors() {
+            *counts.entry(detector_id.to_string()).or_insert(0) += 1;
+        }
+        counts
+    }
+
+    /// Spans of the output detections for the given choice from detectors selected by `redact`.
+    pub fn output_spans(
+        &self,
+        choice_index: u32,
+        redact: impl Fn(&str) -> bool,
+    ) -> Vec<(usize, usize)> {
+        self.output
+            .iter()
+            .flatten()
+            .filter(|d| d.choice_index == choice_index)
+            .flat_map(|d| d.results.iter().flatten())
+            .filter(|r| redact(&r.detector_id))
+            .filter_map(|r| Some((r.start.as_u64()? as usize, r.end as usize)))
+            .collect()
+    }
+
+    /// Earliest start offset among the output detections for the given choice, if any.
+    pub fn earliest_output_start(&self, choice_index: u32) -> Option<usize> {
+        self.output
+            .iter()
+            .flatten()
+            .filter(|d| d.choice_index == choice_index)
+            .flat_map(|d| d.results.iter().flatten())
+            .filter_map(|r| r.start.as_u64())
+            .min()
+            .map(|start| start as usize)
+    }
+}
+
+/// Cuts `content` at the character offset `start`, appending `notice` if there is one.
+fn truncate_chars(content: &mut String, start: usize, notice: Option<&str>) {
+    let byte_offset = content
+        .char_indices()
+        .nth(start)
+        .map_or(content.len(), |(i, _)| i);
+    content.truncate(byte_offset);
+    if let Some(notice) = notice {
+        content.push_str(notice);
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct OrchestratorResponse {
     id: String,
@@ -77,6 +336,209 @@ pub struct OrchestratorResponse {
     usage: serde_json::Value,
     pub detections: Option<Detections>,
     pub warnings: Option<Vec<HashMap<String, String>>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detection_summary: Option<DetectionSummary>,
+}
+
+impl OrchestratorResponse {
+    /// Replaces the spans of output detections from detectors selected by `redact` with
+    /// `placeholder`; overlapping or adjacent spans share a single placeholder. Offsets are
+    /// character offsets, as in truncation. Returns whether anything was redacted.
+    pub fn redact_output_detections(
+        &mut self,
+        placeholder: &str,
+        redact: impl Fn(&str) -> bool,
+    ) -> bool {
+        let Some(detections) = &self.detections else {
+            return false;
+        };
+        let mut redacted = false;
+        for choice in self.choices.iter_mut() {
+            let spans = detections.output_spans(choice.index, &redact);
+            if spans.is_empty() {
+                continue;
+            }
+            let content = &mut choice.message.content;
+            let output = apply_redactions(content, &spans, placeholder);
+            if output != *content {
+                *content = output;
+                redacted = true;
+            }
+        }
+        redacted
+    }
+
+    /// Cuts each choice's content at the start of its first output detection, dropping the
+    /// detected span and everything after it. Offsets are character (not byte) offsets into the
+    /// content. Choices without output detections are left as they are. Returns whether any
+    /// choice was truncated.
+    pub fn truncate_at_output_detections(&mut self, notice: Option<&str>) -> bool {
+        let Some(detections) = &self.detections else {
+            return false;
+        };
+        let mut truncated = false;
+        for choice in self.choices.iter_mut() {
+            if let Some(start) = detections.earliest_output_start(choice.index) {
+                truncate_chars(&mut choice.message.content, start, notice);
+                truncated = true;
+            }
+        }
+        truncated
+    }
+
+    /// Re-frames a complete response as the sequence of chunks a streaming request would have
+    /// produced: content is split on word boundaries, and the final chunk of each choice carries
+    /// its finish reason. Usage, detections and warnings are attached to the last chunk.
+    pub fn into_streaming_responses(self) -> Vec<StreamingResponse> {
+        let mut chunks = Vec::new();
+        for choice in self.choices {
+            let mut role = Some(choice.message.role);
+            let words: Vec<&str> = choice
+                .message
+                .content
+                .split_inclusive(char::is_whitespace)
+                .collect();
+            for word in words {
+                chunks.push(StreamingChoice {
+                    index: choice.index,
+                    delta: StreamingDelta {
+                        content: Some(word.to_string()),
+                        role: role.take(),
+                        tool_calls: None,
+                    },
+                    logprobs: None,
+                    finish_reason: None,
+                    stop_reason: None,
+                });
+            }
+            chunks.push(StreamingChoice {
+                index: choice.index,
+                delta: StreamingDelta {
+                    content: None,
+                    role: role.take(),
+                    tool_calls: choice.message.tool_calls,
+                },
+                logprobs: None,
+                finish_reason: Some(choice.finish_reason),
+                stop_reason: None,
+            });
+        }
+
+        let mut responses: Vec<StreamingResponse> = chunks
+            .into_iter()
+            .map(|choice| StreamingResponse {
+                id: self.id.clone(),
+                object: String::from("chat.completion.chunk"),
+                created: self.created,
+                model: self.model.clone(),
+                choices: vec![choice],
+                usage: None,
+                detections: None,
+                warnings: None,
+            })
+            .collect();
+        if let Some(last) = responses.last_mut() {
+            last.usage = Some(self.usage);
+            last.detections = self.detections;
+            last.warnings = self.warnings;
+        }
+        responses
+    }
+}
+
+/// A choice of the legacy text completions API, which carries `text` rather than a message.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CompletionChoice {
+    pub finish_reason: Option<String>,
+    pub index: u32,
+    pub text: String,
+    pub logprobs: Option<serde_json::Value>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CompletionResponse {
+    id: String,
+    pub choices: Vec<CompletionChoice>,
+    created: u64,
+    model: String,
+    system_fingerprint: Option<String>,
+    object: Option<String>,
+    usage: serde_json::Value,
+    pub detections: Option<Detections>,
+    pub warnings: Option<Vec<HashMap<String, String>>>,
+}
+
+impl CompletionResponse {
+    /// Replaces the text of every choice with `text`, or adds a single choice when there was no
+    /// generation, as with input detections.
+    pub fn replace_choices(&mut self, text: &str) {
+        if self.choices.is_empty() {
+            self.choices.push(CompletionChoice {
+                finish_reason: None,
+                index: 0,
+                text: String::new(),
+                logprobs: None,
+            });
+        }
+        for choice in self.choices.iter_mut() {
+            choice.text = text.to_string();
+            choice.finish_reason = Some("stop".to_string());
+            choice.logprobs = None;
+        }
+    }
+}
+
+// Detection-only structures for the orchestrator's chat detection endpoint
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ChatDetectionResult {
+    pub detection_type: String,
+    pub detection: String,
+    pub detector_id: Option<String>,
+    #[serde(alias = "confidence", alias = "probability")]
+    pub score: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ChatDetectionResponse {
+    pub detections: Vec<ChatDetectionResult>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct Verdict {
+    pub blocked: bool,
+    pub reasons: Vec<VerdictReason>,
+    pub max_score: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct VerdictReason {
+    pub detector_id: Option<String>,
+    pub detection_type: String,
+    pub detection: String,
+    pub score: f64,
+}
+
+impl Verdict {
+    /// Reduces detections to a yes/no verdict. Only detections scoring at least `threshold`
+    /// count as reasons to block.
+    pub fn from_detections(detections: Vec<ChatDetectionResult>, threshold: f64) -> Self {
+        let max_score = detections.iter().map(|d| d.score).fold(0.0, f64::max);
+        let reasons: Vec<VerdictReason> = detections
+            .into_iter()
+            .filter(|d| d.score >= threshold)
+            .map(|d| VerdictReason {
+                detector_id: d.detector_id,
+                detection_type: d.detection_type,
+                detection: d.detection,
+                score: d.score,
+            })
+            .collect();
+        Verdict {
+            blocked: !reasons.is_empty(),
+            reasons,
+            max_score,
+        }
+    }
 }
 
 // Streaming response structures
@@ -107,3 +569,354 @@ pub struct StreamingResponse {
     pub detections: Option<Detections>,
     pub warnings: Option<Vec<HashMap<String, String>>>,
 }
+
+impl StreamingResponse {
+    /// Streaming counterpart of [`OrchestratorResponse::redact_output_detections`]: spans are
+    /// offsets into the content of this chunk's delta.
+    pub fn redact_output_detections(
+        &mut self,
+        placeholder: &str,
+        redact: impl Fn(&str) -> bool,
+    ) -> bool {
+        let Some(detections) = &self.detections else {
+            return false;
+        };
+        let mut redacted = false;
+        for choice in self.choices.iter_mut() {
+            let spans = detections.output_spans(choice.index, &redact);
+            let Some(content) = choice.delta.content.as_mut().filter(|_| !spans.is_empty()) else {
+                continue;
+            };
+            let output = apply_redactions(content, &spans, placeholder);
+            if output != *content {
+                *content = output;
+                redacted = true;
+            }
+        }
+        redacted
+    }
+
+    /// Streaming counterpart of [`OrchestratorResponse::truncate_at_output_detections`]: the
+    /// delta content of each flagged choice is cut at its first output detection, which ends the
+    /// choice, so its finish reason becomes `stop`.
+    pub fn truncate_at_output_detections(&mut self, notice: Option<&str>) -> bool {
+        let Some(detections) = &self.detections else {
+            return false;
+        };
+        let mut truncated = false;
+        for choice in self.choices.iter_mut() {
+            if let Some(start) = detections.earliest_output_start(choice.index) {
+                truncate_chars(choice.delta.content.get_or_insert_default(), start, notice);
+                choice.finish_reason = Some("stop".to_string());
+                choice.stop_reason = None;
+                truncated = true;
+            }
+        }
+        truncated
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn result(score: f64) -> serde_json::Value {
+        json!({
+            "start": 0,
+            "end": 1,
+            "text": "a",
+            "detection_type": "pii",
+            "detection": "EmailAddress",
+            "detector_id": "regex",
+            "score": score,
+        })
+    }
+
+    fn chat_detection(detector_id: &str, score: f64) -> ChatDetectionResult {
+        ChatDetectionResult {
+            detection_type: "pii".to_string(),
+            detection: "EmailAddress".to_string(),
+            detector_id: Some(detector_id.to_string()),
+            score,
+        }
+    }
+
+    #[test]
+    fn test_verdict_from_detections() {
+        let verdict = Verdict::from_detections(
+            vec![chat_detection("regex", 0.9), chat_detection("hap", 0.3)],
+            0.5,
+        );
+        assert!(verdict.blocked);
+        assert_eq!(verdict.max_score, 0.9);
+        assert_eq!(verdict.reasons.len(), 1);
+        assert_eq!(verdict.reasons[0].detector_id.as_deref(), Some("regex"));
+
+        let verdict = Verdict::from_detections(vec![chat_detection("hap", 0.3)], 0.5);
+        assert!(!verdict.blocked);
+        assert_eq!(verdict.max_score, 0.3);
+
+        let verdict = Verdict::from_detections(vec![], 0.0);
+        assert!(!verdict.blocked);
+        assert_eq!(verdict.max_score, 0.0);
+    }
+
+    fn response_with_content(content: &str, output: serde_json::Value) -> OrchestratorResponse {
+        serde_json::from_value(json!({
+            "id": "1",
+            "choices": [{
+                "finish_reason": "stop",
+                "index": 0,
+                "logprobs": null,
+                "message": {
+                    "content": content, "refusal": null, "role": "assistant",
+                    "tool_calls": null, "audio": null
+                }
+            }],
+            "created": 1,
+            "model": "m",
+            "service_tier": null,
+            "system_fingerprint": null,
+            "object": "chat.completion",
+            "usage": {},
+            "detections": {"input": null, "output": output},
+            "warnings": null
+        }))
+        .unwrap()
+    }
+
+    fn result_at(start: u64) -> serde_json::Value {
+        let mut result = result(1.0);
+        result["start"] = json!(start);
+        result
+    }
+
+    #[test]
+    fn test_detection_summary() {
+        let mut input_result = result(0.5);
+        input_result["detection_type"] = json!("jailbreak");
+        let input = json!([{"message_index": 0, "results": [input_result]}]);
+        let output = json!([{"choice_index": 0, "results": [result(0.5), result(0.7)]}]);
+        let cases = [
+            (
+                input.clone(),
+                json!(null),
+                1,
+                0,
+                Some("input"),
+                vec!["jailbreak"],
+            ),
+            (
+                json!(null),
+                output.clone(),
+                0,
+                2,
+                Some("output"),
+                vec!["pii"],
+            ),
+            (
+                input,
+                output,
+                1,
+                2,
+                Some("input_and_output"),
+                vec!["jailbreak", "pii"],
+            ),
+            (json!(null), json!(null), 0, 0, None, vec![]),
+        ];
+        for (input, output, input_count, output_count, blocked_by, detection_types) in cases {
+            let detections: Detections =
+                serde_json::from_value(json!({"input": input, "output": output})).unwrap();
+            assert_eq!(
+                detections.summary(),
+                DetectionSummary {
+                    input_count,
+                    output_count,
+                    blocked_by: blocked_by.map(str::to_string),
+                    detection_types: detection_types.into_iter().map(str::to_string).collect(),
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn test_score_aliases() {
+        for key in ["score", "confidence", "probability"] {
+            let mut value = result(0.0);
+            value.as_object_mut().unwrap().remove("score");
+            value[key] = json!(0.75);
+            let parsed: DetectionResult = serde_json::from_value(value).unwrap();
+            assert_eq!(parsed.score, 0.75);
+            assert_eq!(serde_json::to_value(&parsed).unwrap()["score"], 0.75);
+        }
+
+        let parsed: ChatDetectionResult = serde_json::from_value(json!({
+            "detection_type": "pii",
+            "detection": "EmailAddress",
+            "detector_id": null,
+            "confidence": 0.5,
+        }))
+        .unwrap();
+        assert_eq!(parsed.score, 0.5);
+    }
+
+    #[test]
+    fn test_truncate_at_output_detections() {
+        let mut response = response_with_content(
+            "Sure, héllo at a@b.com and c@d.com",
+            json!([{"choice_index": 0, "results": [result_at(27), result_at(15)]}]),
+        );
+        assert!(response.truncate_at_output_detections(Some("[truncated]")));
+        assert_eq!(
+            response.choices[0].message.content,
+            "Sure, héllo at [truncated]"
+        );
+    }
+
+    #[test]
+    fn test_truncate_without_output_detections() {
+        let mut response = response_with_content("Sure", serde_json::Value::Null);
+        assert!(!response.truncate_at_output_detections(None));
+        assert_eq!(response.choices[0].message.content, "Sure");
+
+        let mut response = response_with_content(
+            "Sure",
+            json!([{"choice_index": 1, "results": [result_at(0)]}]),
+        );
+        assert!(!response.truncate_at_output_detections(None));
+        assert_eq!(response.choices[0].message.content, "Sure");
+    }
+
+    fn result_span(detector_id: &str, start: u64, end: u64) -> serde_json::Value {
+        let mut result = result_at(start);
+        result["end"] = json!(end);
+        result["detector_id"] = json!(detector_id);
+        result
+    }
+
+    #[test]
+    fn test_has_hits() {
+        let detections =
+            |value: serde_json::Value| -> Detections { serde_json::from_value(value).unwrap() };
+        let empty = detections(json!({"input": [], "output": []}));
+        assert!(!empty.has_hits());
+        let null_results = detections(json!({
+            "input": [{"message_index": 0, "results": null}],
+            "output": [{"choice_index": 0, "results": []}]
+        }));
+        assert!(!null_results.has_hits());
+
+        let input = detections(json!({
+            "input": [{"message_index": 0, "results": [result(0.9)]}],
+            "output": null
+        }));
+        assert!(input.has_hits() && input.has_input_hits() && !input.has_output_hits());
+        let output = detections(json!({
+            "input": null,
+            "output": [{"choice_index": 0, "results": [result(0.9)]}]
+        }));
+        assert!(output.has_hits() && !output.has_input_hits() && output.has_output_hits());
+    }
+
+    #[test]
+    fn test_drop_below_thresholds() {
+        let scored = |detector_id: &str, score: f64| {
+            let mut result = result(score);
+            result["detector_id"] = json!(detector_id);
+            result
+        };
+        let mut detections: Detections = serde_json::from_value(json!({
+            "input": [{"message_index": 0, "results": [scored("hap", 0.2), scored("other", 0.1)]}],
+            "output": [{"choice_index": 0, "results": [
+                scored("regex", 0.9), scored("regex", 0.3), scored("hap", 0.5)
+            ]}]
+        }))
+        .unwrap();
+        let thresholds = HashMap::from([("regex".to_string(), 0.8), ("hap".to_string(), 0.5)]);
+
+        detections.drop_below_thresholds(&thresholds);
+
+        // Detectors without a threshold keep every result, and a score at the threshold counts
+        let kept: Vec<&str> = detections.flagged_detectors().collect();
+        assert_eq!(kept, ["other", "regex", "hap"]);
+        assert_eq!(detections.top_result(), Some(("regex", 0.9)));
+
+        let mut low: Detections = serde_json::from_value(json!({
+            "input": null,
+            "output": [{"choice_index": 0, "results": [scored("regex", 0.3)]}]
+        }))
+        .unwrap();
+        low.drop_below_thresholds(&thresholds);
+        assert!(!low.has_hits());
+    }
+
+    #[test]
+    fn test_apply_redactions() {
+        let content = "call 555-1234 or mail a@b.c";
+        assert_eq!(apply_redactions(content, &[], "#"), content);
+        assert_eq!(
+            apply_redactions(content, &[(22, 27), (5, 13)], "#"),
+            "call # or mail #"
+        );
+        // Overlapping and adjacent spans share one mask
+        assert_eq!(
+            apply_redactions(content, &[(5, 9), (7, 13), (13, 16)], "#"),
+            "call # mail a@b.c"
+        );
+        // Empty spans are ignored and spans past the end are clipped
+        assert_eq!(
+            apply_redactions(content, &[(3, 3), (22, 40), (50, 60)], "#"),
+            "call 555-1234 or mail #"
+        );
+    }
+
+    #[test]
+    fn test_apply_redactions_multibyte() {
+        assert_eq!(
+            apply_redactions("héllo wörld ✓", &[(12, 13), (1, 2), (7, 8)], "*"),
+            "h*llo w*rld *"
+        );
+    }
+
+    #[test]
+    fn test_redact_output_detections() {
+        // "é" is one character, so offsets are character offsets
+        let mut response = response_with_content(
+            "mail é a@b.com or c@d.com, call 555",
+            json!([{"choice_index": 0, "results": [
+                result_span("regex", 7, 14),
+                result_span("regex", 18, 25),
+                result_span("regex", 20, 22),
+                result_span("phone", 32, 35),
+            ]}]),
+        );
+        assert!(response.redact_output_detections("[REDACTED]", |id| id == "regex"));
+        assert_eq!(
+            response.choices[0].message.content,
+            "mail é [REDACTED] or [REDACTED], call 555"
+        );
+        assert!(!response.redact_output_detections("[REDACTED]", |_| false));
+    }
+
+    #[test]
+    fn test_truncate_results_keeps_highest_scores() {
+        let mut detections: Detections = serde_json::from_value(json!({
+            "input": [{"message_index": 0, "results": [result(0.1), result(0.9)]}],
+            "output": [
+                {"choice_index": 0, "results": [result(0.5), result(0.7)]},
+                {"choice_index": 1, "results": null},
+            ],
+        }))
+        .unwrap();
+        assert_eq!(detections.result_count(), 4);
+
+        detections.truncate_results(2);
+
+        assert_eq!(detections.result_count(), 2);
+        let json = serde_json::to_value(&detections).unwrap();
+        assert_eq!(json["input"][0]["results"][0]["score"], 0.9);
+        assert_eq!(json["output"][0]["results"][0]["score"], 0.7);
+        assert_eq!(json["output"][1]["results"], serde_json::Value::Null);
+    }
+}
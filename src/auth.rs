@@ -0,0 +1,58 @@
+use std::sync::Arc;
+
+use axum::extract::{Request, State};
+use axum::http::{header, HeaderMap, HeaderValue};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+
+use crate::error::ApiError;
+
+pub const API_KEY_HEADER: &str = "x-api-key";
+
+/// Lets a request through only when it presents one of `api_keys`, either as a bearer token in
+/// the `authorization` header or in `x-api-key`. Anything else is answered with a 401 before it
+/// can reach the orchestrator.
+pub async fn require_api_key(
+    State(api_keys): State<Arc<Vec<String>>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if presented_keys(request.headers()).any(|key| is_known(&api_keys, key)) {
+        return next.run(request).await;
+    }
+    tracing::info!("Rejected request without a valid API key");
+    (
+        [(header::WWW_AUTHENTICATE, "Bearer")],
+        ApiError::unauthorized("a valid API key is required").with_code("invalid_api_key"),
+    )
+        .into_response()
+}
+
+fn presented_keys(headers: &HeaderMap) -> impl Iterator<Item = &str> {
+    let bearer = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    let api_key = headers
+        .get(API_KEY_HEADER)
+        .and_then(|value| value.to_str().ok());
+    bearer.into_iter().chain(api_key)
+}
+
+/// Whether an `authorization` header value presents one of `api_keys` as a bearer token. Such a
+/// header only authenticated the client to the gateway and is not forwarded to the orchestrator.
+pub fn is_gateway_credential(api_keys: &[String], value: &HeaderValue) -> bool {
+    value
+        .to_str()
+        .ok()
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|key| is_known(api_keys, key))
+}
+
+/// Keys are compared in constant time, so response times give away nothing about how much of a
+/// key was right.
+fn is_known(api_keys: &[String], key: &str) -> bool {
+    api_keys.iter().any(|known| {
+        known.len() == key.len() && openssl::memcmp::eq(known.as_bytes(), key.as_bytes())
+    })
+}
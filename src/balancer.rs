@@ -0,0 +1,52 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Spreads requests across the configured orchestrator replicas in turn. Each request gets every
+/// replica, starting with the next one in the rotation, so the rest can be tried in order if it
+/// cannot be reached.
+#[derive(Debug)]
+pub struct Balancer {
+    base_urls: Vec<String>,
+    next: AtomicUsize,
+}
+
+impl Balancer {
+    pub fn new(base_urls: Vec<String>) -> Self {
+        Balancer {
+            base_urls,
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn base_urls(&self) -> &[String] {
+        &self.base_urls
+    }
+
+    /// The URL of `path` on every replica, in the order to try them for one request.
+    pub fn urls(&self, path: &str) -> Vec<String> {
+        let count = self.base_urls.len();
+        let start = match count {
+            0 => 0,
+            _ => self.next.fetch_add(1, Ordering::Relaxed) % count,
+        };
+        (0..count)
+            .map(|offset| format!("{}{}", self.base_urls[(start + offset) % count], path))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_urls_rotate() {
+        let balancer = Balancer::new(vec!["http://a".to_string(), "http://b".to_string()]);
+        assert_eq!(balancer.urls("/chat"), ["http://a/chat", "http://b/chat"]);
+        assert_eq!(balancer.urls("/chat"), ["http://b/chat", "http://a/chat"]);
+        assert_eq!(balancer.urls("/chat"), ["http://a/chat", "http://b/chat"]);
+
+        let single = Balancer::new(vec!["http://a".to_string()]);
+        assert_eq!(single.urls("/chat"), ["http://a/chat"]);
+        assert_eq!(single.urls("/chat"), ["http://a/chat"]);
+    }
+}
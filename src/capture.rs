@@ -0,0 +1,178 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde_json::{json, Value};
+
+use crate::config::CaptureConfig;
+
+const REDACTED: &str = "[REDACTED]";
+
+/// Returns true for roughly `sampling_rate` of calls.
+pub fn should_capture(capture: &CaptureConfig) -> bool {
+    if capture.sampling_rate <= 0.0 {
+        return false;
+    }
+    // RandomState is seeded randomly per instance, which is enough for sampling without pulling
+    // in a dedicated RNG
+    let sample = RandomState::new().build_hasher().finish() as f64 / u64::MAX as f64;
+    sample < capture.sampling_rate
+}
+
+/// Writes a request/response pair for `route` into the capture directory. Failures are logged
+/// rather than returned, as capture must never affect the request being served.
+pub async fn write_capture(
+    capture: &CaptureConfig,
+    route: &str,
+    mut request: Value,
+    mut response: Value,
+) {
+    if capture.redact_content {
+        redact_messages(&mut request["messages"]);
+        if let Some(choices) = response["choices"].as_array_mut() {
+            for choice in choices {
+                redact_content(&mut choice["message"]);
+            }
+        }
+    }
+
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    let extension = if capture.compress { "json.gz" } else { "json" };
+    let path =
+        PathBuf::from(&capture.capture_dir).join(format!("{}-{}.{}", timestamp, route, extension));
+    let record = json!({
+        "route": route,
+        "request": request,
+        "response": response,
+    })
+    .to_string();
+    let contents = if capture.compress {
+        match gzip(record.as_bytes()) {
+            Ok(compressed) => compressed,
+            Err(e) => {
+                tracing::error!("Failed to compress capture: {}", e);
+                return;
+            }
+        }
+    } else {
+        record.into_bytes()
+    };
+
+    if let Err(e) = tokio::fs::create_dir_all(&capture.capture_dir).await {
+        tracing::error!(
+            "Failed to create capture dir {}: {}",
+            capture.capture_dir,
+            e
+        );
+        return;
+    }
+    match tokio::fs::write(&path, contents).await {
+        Ok(()) => tracing::debug!("Captured request to {}", path.display()),
+        Err(e) => tracing::error!("Failed to write capture {}: {}", path.display(), e),
+    }
+}
+
+fn gzip(data: &[u8]) -> std::io::Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data)?;
+    encoder.finish()
+}
+
+fn redact_messages(messages: &mut Value) {
+    if let Some(messages) = messages.as_array_mut() {
+        for message in messages {
+            redact_content(message);
+        }
+    }
+}
+
+fn redact_content(message: &mut Value) {
+    if let Some(content) = message.get_mut("content") {
+        if !content.is_null() {
+            *content = Value::String(REDACTED.to_string());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn capture_config(dir: &str, redact_content: bool) -> CaptureConfig {
+        CaptureConfig {
+            capture_dir: dir.to_string(),
+            sampling_rate: 1.0,
+            redact_content,
+            compress: false,
+        }
+    }
+
+    #[test]
+    fn test_should_capture_bounds() {
+        let mut capture = capture_config("unused", false);
+        assert!(should_capture(&capture));
+        capture.sampling_rate = 0.0;
+        assert!(!should_capture(&capture));
+    }
+
+    #[tokio::test]
+    async fn test_write_capture_redacts_content() {
+        let dir = std::env::temp_dir().join("gateway-capture-redact-test");
+        let _ = std::fs::remove_dir_all(&dir);
+        let capture = capture_config(dir.to_str().unwrap(), true);
+
+        write_capture(
+            &capture,
+            "pii",
+            json!({"model": "m", "messages": [{"role": "user", "content": "my ssn is 123"}]}),
+            json!({"choices": [{"message": {"role": "assistant", "content": "ok"}}]}),
+        )
+        .await;
+
+        let files: Vec<_> = std::fs::read_dir(&dir).unwrap().collect();
+        assert_eq!(files.len(), 1);
+        let contents = std::fs::read_to_string(files[0].as_ref().unwrap().path()).unwrap();
+        let record: Value = serde_json::from_str(&contents).unwrap();
+        assert_eq!(record["route"], "pii");
+        assert_eq!(record["request"]["model"], "m");
+        assert_eq!(record["request"]["messages"][0]["content"], REDACTED);
+        assert_eq!(
+            record["response"]["choices"][0]["message"]["content"],
+            REDACTED
+        );
+    }
+
+    #[tokio::test]
+    async fn test_write_capture_compressed() {
+        use flate2::read::GzDecoder;
+        use std::io::Read;
+
+        let dir = std::env::temp_dir().join("gateway-capture-compress-test");
+        let _ = std::fs::remove_dir_all(&dir);
+        let mut capture = capture_config(dir.to_str().unwrap(), false);
+        capture.compress = true;
+
+        write_capture(&capture, "pii", json!({"model": "m"}), json!({"id": "1"})).await;
+
+        let path = std::fs::read_dir(&dir)
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap()
+            .path();
+        assert!(path.to_str().unwrap().ends_with("-pii.json.gz"));
+        let mut contents = String::new();
+        GzDecoder::new(std::fs::File::open(path).unwrap())
+            .read_to_string(&mut contents)
+            .unwrap();
+        let record: Value = serde_json::from_str(&contents).unwrap();
+        assert_eq!(record["response"]["id"], "1");
+    }
+}
@@ -0,0 +1,65 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+
+use futures::future::{BoxFuture, Shared};
+use futures::FutureExt;
+use serde_json::Value;
+
+use crate::error::ApiError;
+use crate::request_log::{Decision, Outcome};
+
+/// The shared response and what came of the orchestrator call, for each caller to log.
+type CoalescedResult = (Result<Value, ApiError>, Outcome);
+
+/// Single-flight map for identical in-flight requests: the first caller for a key starts the
+/// work and every caller arriving before it finishes awaits the same result. The work runs on
+/// its own task so it is not cancelled when the first caller disconnects.
+#[derive(Default)]
+pub struct Coalescer {
+    in_flight: Mutex<HashMap<String, Shared<BoxFuture<'static, CoalescedResult>>>>,
+}
+
+impl std::fmt::Debug for Coalescer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Coalescer")
+            .field("in_flight", &self.in_flight.lock().unwrap().len())
+            .finish()
+    }
+}
+
+impl Coalescer {
+    pub async fn run<F>(self: &Arc<Self>, key: String, work: F) -> CoalescedResult
+    where
+        F: Future<Output = CoalescedResult> + Send + 'static,
+    {
+        let shared = {
+            let mut in_flight = self.in_flight.lock().unwrap();
+            if let Some(shared) = in_flight.get(&key) {
+                tracing::debug!("Joining in-flight request");
+                shared.clone()
+            } else {
+                let coalescer = self.clone();
+                let task_key = key.clone();
+                let task = tokio::spawn(async move {
+                    let result = work.await;
+                    coalescer.in_flight.lock().unwrap().remove(&task_key);
+                    result
+                });
+                let shared = task
+                    .map(|joined| {
+                        joined.unwrap_or_else(|e| {
+                            let mut outcome = Outcome::default();
+                            outcome.record(Decision::Error, None);
+                            (Err(ApiError::internal(e.to_string())), outcome)
+                        })
+                    })
+                    .boxed()
+                    .shared();
+                in_flight.insert(key, shared.clone());
+                shared
+            }
+        };
+        shared.await
+    }
+}
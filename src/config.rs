@@ -1,32 +1,361 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::fs;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 
-use serde::Deserialize;
+use serde::{Deserialize, Deserializer, Serialize};
+
+use crate::error::ConfigError;
 
 #[derive(Debug, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct GatewayConfig {
+    #[serde(default)]
+    pub server: ServerConfig,
     #[serde(default)]
     pub orchestrator: OrchestratorConfig,
     pub detectors: Vec<DetectorConfig>,
     pub routes: Vec<RouteConfig>,
+    #[serde(default = "default_request_id_header")]
+    pub request_id_header: String,
+    #[serde(default)]
+    pub inject_gateway_metadata: bool,
+    #[serde(default)]
+    pub capture: Option<CaptureConfig>,
+    #[serde(default)]
+    pub watchdog: Option<WatchdogConfig>,
+    #[serde(default)]
+    pub max_detection_results: Option<usize>,
+    #[serde(default)]
+    pub sse_initial_keep_alive: bool,
+    #[serde(default)]
+    pub forwarded_headers: ForwardedHeadersConfig,
+    #[serde(default)]
+    pub listener_tls: Option<ListenerTlsConfig>,
+    #[serde(default)]
+    pub orchestrator_headers: HashMap<String, String>,
+    #[serde(default)]
+    pub malformed_frame_policy: MalformedFramePolicy,
+    #[serde(default)]
+    pub tenant_claim: Option<TenantClaimConfig>,
+    #[serde(default)]
+    pub stream_reconnect_attempts: u32,
+    #[serde(default = "default_stream_reconnect_backoff_ms")]
+    pub stream_reconnect_backoff_ms: u64,
+    #[serde(default)]
+    pub retry_budget: Option<RetryBudgetConfig>,
+    #[serde(default)]
+    pub inbound_timeout_ms: Option<u64>,
+    #[serde(default)]
+    pub allowed_orchestrator_hosts: Option<Vec<String>>,
+    #[serde(default = "default_shutdown_grace_period_seconds")]
+    pub shutdown_grace_period_seconds: u64,
+    /// How long to wait for further SIGHUPs before reloading, so a burst reloads once
+    #[serde(default = "default_reload_debounce_ms")]
+    pub reload_debounce_ms: u64,
+    /// Replaces message content with `[redacted]` in logged payloads
+    #[serde(default)]
+    pub redact_logs: bool,
+    /// Keys clients must present to use the routes; the routes are open when empty
+    #[serde(default)]
+    pub api_keys: Vec<String>,
+}
+
+fn default_shutdown_grace_period_seconds() -> u64 {
+    30
+}
+
+fn default_reload_debounce_ms() -> u64 {
+    500
+}
+
+fn default_stream_reconnect_backoff_ms() -> u64 {
+    100
+}
+
+fn default_request_id_header() -> String {
+    "x-request-id".to_string()
+}
+
+impl Default for GatewayConfig {
+    fn default() -> Self {
+        GatewayConfig {
+            server: ServerConfig::default(),
+            orchestrator: OrchestratorConfig::default(),
+            detectors: Vec::new(),
+            routes: Vec::new(),
+            request_id_header: default_request_id_header(),
+            inject_gateway_metadata: false,
+            capture: None,
+            watchdog: None,
+            max_detection_results: None,
+            sse_initial_keep_alive: false,
+            forwarded_headers: ForwardedHeadersConfig::default(),
+            listener_tls: None,
+            orchestrator_headers: HashMap::new(),
+            malformed_frame_policy: MalformedFramePolicy::default(),
+            tenant_claim: None,
+            stream_reconnect_attempts: 0,
+            stream_reconnect_backoff_ms: default_stream_reconnect_backoff_ms(),
+            retry_budget: None,
+            inbound_timeout_ms: None,
+            allowed_orchestrator_hosts: None,
+            shutdown_grace_period_seconds: default_shutdown_grace_period_seconds(),
+            reload_debounce_ms: default_reload_debounce_ms(),
+            redact_logs: false,
+            api_keys: Vec::new(),
+        }
+    }
 }
 
+/// Address the gateway listens on. The `HOST` and `HTTP_PORT` environment variables take
+/// precedence over both fields.
+#[derive(Debug, Deserialize, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct ServerConfig {
+    #[serde(default)]
+    pub host: Option<IpAddr>,
+    #[serde(default)]
+    pub port: Option<u16>,
+}
+
+const DEFAULT_HOST: IpAddr = IpAddr::V4(Ipv4Addr::UNSPECIFIED);
+const DEFAULT_PORT: u16 = 8090;
+
+/// Upper limit on retries or stream reconnects of a single request; with doubling backoff, more
+/// would wait for ever.
+pub(crate) const MAX_RETRIES: u32 = 10;
+
 #[derive(Debug, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct OrchestratorConfig {
-    pub host: String,
+    /// One host, or several replicas that requests are spread across. A host may carry its own
+    /// port as `host:port`, which takes precedence over `port`.
+    #[serde(deserialize_with = "one_or_many_hosts")]
+    pub host: Vec<String>,
     pub port: Option<u16>,
+    #[serde(default = "default_health_path")]
+    pub health_path: String,
+    /// Path of the models list proxied from each route's `/v1/models`
+    #[serde(default = "default_models_path")]
+    pub models_path: String,
+    #[serde(default)]
+    pub api_path: Option<String>,
+    #[serde(default)]
+    pub min_tls_version: Option<String>,
+    #[serde(default)]
+    pub tls: OrchestratorTlsConfig,
+    /// Default time limit for orchestrator requests, overridable per route
+    #[serde(default)]
+    pub timeout_seconds: Option<u64>,
+    /// Times a non-streaming request is retried after a connection error or a 502, 503 or 504
+    #[serde(default)]
+    pub max_retries: u32,
+    /// Delay before the first retry, doubling for each one after it
+    #[serde(default = "default_retry_backoff_ms")]
+    pub backoff_ms: u64,
+}
+
+/// Files used for TLS towards the orchestrator. A missing client certificate or key means the
+/// orchestrator is reached over plain HTTP; a missing CA means only system roots are trusted.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct OrchestratorTlsConfig {
+    #[serde(default = "default_orchestrator_cert_path")]
+    pub cert_path: String,
+    #[serde(default = "default_orchestrator_key_path")]
+    pub key_path: String,
+    #[serde(default = "default_orchestrator_ca_path")]
+    pub ca_path: String,
+}
+
+fn default_orchestrator_cert_path() -> String {
+    "/etc/tls/private/tls.crt".to_string()
+}
+
+fn default_orchestrator_key_path() -> String {
+    "/etc/tls/private/tls.key".to_string()
+}
+
+fn default_orchestrator_ca_path() -> String {
+    "/etc/tls/ca/service-ca.crt".to_string()
+}
+
+impl Default for OrchestratorTlsConfig {
+    fn default() -> Self {
+        OrchestratorTlsConfig {
+            cert_path: default_orchestrator_cert_path(),
+            key_path: default_orchestrator_key_path(),
+            ca_path: default_orchestrator_ca_path(),
+        }
+    }
+}
+
+/// Parses a configured minimum TLS version. TLS 1.3 cannot be required because the native-tls
+/// backend has no way to enforce it.
+pub fn parse_tls_version(version: &str) -> Result<reqwest::tls::Version, String> {
+    match version {
+        "1.0" => Ok(reqwest::tls::Version::TLS_1_0),
+        "1.1" => Ok(reqwest::tls::Version::TLS_1_1),
+        "1.2" => Ok(reqwest::tls::Version::TLS_1_2),
+        "1.3" => Err("TLS 1.3 cannot be enforced by the native-tls backend".to_string()),
+        other => Err(format!("expected one of 1.0, 1.1 or 1.2, got '{}'", other)),
+    }
+}
+
+fn one_or_many_hosts<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Hosts {
+        One(String),
+        Many(Vec<String>),
+    }
+    match Hosts::deserialize(deserializer) {
+        Ok(Hosts::One(host)) => Ok(vec![host]),
+        Ok(Hosts::Many(hosts)) => Ok(hosts),
+        Err(_) => Err(serde::de::Error::custom(
+            "expected a host or a list of hosts",
+        )),
+    }
+}
+
+/// Splits a trailing `:port` off a configured orchestrator host. Bare IPv6 addresses are left
+/// whole; they need brackets to carry a port, as in a URL.
+pub fn split_host_port(host: &str) -> (&str, Option<u16>) {
+    match host.rsplit_once(':') {
+        Some((name, port)) if !name.contains(':') || name.ends_with(']') => match port.parse() {
+            Ok(port) => (name, Some(port)),
+            Err(_) => (host, None),
+        },
+        _ => (host, None),
+    }
+}
+
+fn default_retry_backoff_ms() -> u64 {
+    100
+}
+
+fn default_health_path() -> String {
+    "/health".to_string()
+}
+
+fn default_models_path() -> String {
+    "/v1/models".to_string()
 }
 
 impl Default for OrchestratorConfig {
     fn default() -> Self {
         OrchestratorConfig {
-            host: "localhost".to_string(),
+            host: vec!["localhost".to_string()],
             port: Some(8032),
+            health_path: default_health_path(),
+            models_path: default_models_path(),
+            api_path: None,
+            min_tls_version: None,
+            tls: OrchestratorTlsConfig::default(),
+            timeout_seconds: None,
+            max_retries: 0,
+            backoff_ms: default_retry_backoff_ms(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct CaptureConfig {
+    pub capture_dir: String,
+    #[serde(default)]
+    pub sampling_rate: f64,
+    #[serde(default)]
+    pub redact_content: bool,
+    #[serde(default)]
+    pub compress: bool,
+}
+
+/// Token bucket shared by all requests that limits how many orchestrator retries may be made.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct RetryBudgetConfig {
+    pub retries_per_second: f64,
+    #[serde(default = "default_retry_budget_burst")]
+    pub burst: u32,
+}
+
+fn default_retry_budget_burst() -> u32 {
+    10
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct WatchdogConfig {
+    #[serde(default = "default_watchdog_interval_ms")]
+    pub interval_ms: u64,
+    #[serde(default = "default_watchdog_stall_threshold_ms")]
+    pub stall_threshold_ms: u64,
+}
+
+fn default_watchdog_interval_ms() -> u64 {
+    1000
+}
+
+fn default_watchdog_stall_threshold_ms() -> u64 {
+    5000
+}
+
+/// JWT claim carrying the caller's tenant, forwarded to the orchestrator as `header`.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct TenantClaimConfig {
+    pub claim: String,
+    #[serde(default = "default_tenant_header")]
+    pub header: String,
+    #[serde(default)]
+    pub hs256_secret: Option<String>,
+}
+
+fn default_tenant_header() -> String {
+    "x-tenant-id".to_string()
+}
+
+/// Certificate and key (PEM) the gateway serves HTTPS with. Without it the gateway listens on
+/// plain HTTP.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ListenerTlsConfig {
+    pub cert_path: String,
+    pub key_path: String,
+}
+
+/// Caps on the client headers forwarded to the orchestrator. Headers beyond either limit are
+/// dropped.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ForwardedHeadersConfig {
+    #[serde(default = "default_max_forwarded_headers")]
+    pub max_count: usize,
+    #[serde(default = "default_max_forwarded_header_bytes")]
+    pub max_bytes: usize,
+}
+
+fn default_max_forwarded_headers() -> usize {
+    64
+}
+
+fn default_max_forwarded_header_bytes() -> usize {
+    16 * 1024
+}
+
+impl Default for ForwardedHeadersConfig {
+    fn default() -> Self {
+        ForwardedHeadersConfig {
+            max_count: default_max_forwarded_headers(),
+            max_bytes: default_max_forwarded_header_bytes(),
         }
     }
 }
 
 #[derive(Debug, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct DetectorConfig {
     pub name: String,
     #[serde(default)]
@@ -34,6 +363,9 @@ pub struct DetectorConfig {
     pub input: bool,
     pub output: bool,
     pub detector_params: Option<serde_json::Value>,
+    /// Minimum score for this detector's results to count; lower scoring results are dropped
+    #[serde(default)]
+    pub threshold: Option<f64>,
 }
 
 impl DetectorConfig {
@@ -46,22 +378,415 @@ impl DetectorConfig {
 }
 
 #[derive(Debug, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct RouteConfig {
     pub name: String,
+    /// Disabled routes are validated but not served
+    #[serde(default = "default_route_enabled")]
+    pub enabled: bool,
     pub detectors: Vec<String>,
     pub fallback_message: Option<String>,
+    /// Fallback messages by `detection_type`, used in place of `fallback_message` when set
+    #[serde(default)]
+    pub fallback_messages: HashMap<String, String>,
+    #[serde(default)]
+    pub buffered_streaming: bool,
+    #[serde(default)]
+    pub buffer_upstream_stream: bool,
+    #[serde(default)]
+    pub streaming_detection_mode: Option<StreamingDetectionMode>,
+    #[serde(default)]
+    pub detect_last_message_only: bool,
+    #[serde(default)]
+    pub allow_detector_selection: bool,
+    #[serde(default)]
+    pub expose_verdict: bool,
+    #[serde(default)]
+    pub verdict_threshold: Option<f64>,
+    #[serde(default)]
+    pub action: DetectionAction,
+    #[serde(default)]
+    pub truncation_notice: Option<String>,
+    #[serde(default)]
+    pub api_path: Option<String>,
+    #[serde(default)]
+    pub skip_detection_if: Option<SkipDetectionConfig>,
+    #[serde(default)]
+    pub coalesce_identical_requests: bool,
+    #[serde(default)]
+    pub param_bounds: Option<ParamBoundsConfig>,
+    #[serde(default)]
+    pub return_detection_summary: bool,
+    #[serde(default)]
+    pub detector_groups: Vec<DetectorGroupConfig>,
+    #[serde(default)]
+    pub detectors_merge_policy: DetectorsMergePolicy,
+    #[serde(default)]
+    pub detections_trailer: bool,
+    #[serde(default)]
+    pub content_pointer: Option<String>,
+    #[serde(default)]
+    pub isolated_client: bool,
+    #[serde(default)]
+    pub timeout_seconds: Option<u64>,
+    /// Models clients may request on this route; any model when unset
+    #[serde(default)]
+    pub allowed_models: Option<Vec<String>>,
+    /// System message put at the front of every conversation on this route
+    #[serde(default)]
+    pub system_prompt: Option<String>,
+    /// Upper limit on `max_tokens`, also sent when the client leaves it out
+    #[serde(default)]
+    pub max_tokens_cap: Option<u32>,
+    /// Adds an `x-detections` header counting the results of each detector
+    #[serde(default)]
+    pub expose_detections: bool,
+    /// Runs only the input detectors on chat completions requests, returning their detections
+    #[serde(default)]
+    pub detection_only: bool,
 }
 
+fn default_route_enabled() -> bool {
+    true
+}
 
-pub fn read_config(path: &str) -> GatewayConfig {
-    let result = fs::read_to_string(path).expect(&format!("could not read file: {}", path));
+impl Default for RouteConfig {
+    fn default() -> Self {
+        RouteConfig {
+            name: String::new(),
+            enabled: default_route_enabled(),
+            detectors: Vec::new(),
+            fallback_message: None,
+            fallback_messages: HashMap::new(),
+            buffered_streaming: false,
+            buffer_upstream_stream: false,
+            streaming_detection_mode: None,
+            detect_last_message_only: false,
+            allow_detector_selection: false,
+            expose_verdict: false,
+            verdict_threshold: None,
+            action: DetectionAction::default(),
+            truncation_notice: None,
+            api_path: None,
+            skip_detection_if: None,
+            coalesce_identical_requests: false,
+            param_bounds: None,
+            return_detection_summary: false,
+            detector_groups: Vec::new(),
+            detectors_merge_policy: DetectorsMergePolicy::default(),
+            detections_trailer: false,
+            content_pointer: None,
+            isolated_client: false,
+            timeout_seconds: None,
+            allowed_models: None,
+            system_prompt: None,
+            max_tokens_cap: None,
+            expose_detections: false,
+            detection_only: false,
+        }
+    }
+}
 
-    let mut cfg: GatewayConfig = serde_yml::from_str(&result).expect("failed to read in yaml config");
-    cfg.detectors = cfg.detectors.into_iter().map(|d| d.with_server_default()).collect();
-    cfg
+/// How the route's detectors are combined with a `detectors` object sent by the client.
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum DetectorsMergePolicy {
+    /// Replace the client's detectors with the route's
+    #[default]
+    Overwrite,
+    /// Add the client's detectors to the route's; the route's detectors and params always win
+    Merge,
+    /// Reject requests that carry their own detectors
+    Reject,
 }
 
-pub fn validate_registered_detectors(gateway_cfg: &GatewayConfig) {
+/// A set of the route's detectors that share an action when they flag something.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct DetectorGroupConfig {
+    pub detectors: Vec<String>,
+    pub action: GroupAction,
+}
+
+/// Actions for detector groups, ordered from least to most severe.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum GroupAction {
+    /// Log the detection and return the response unchanged
+    Monitor,
+    /// Replace the detected output spans
+    Redact,
+    /// Apply the route's `action`, as for a detector outside any group
+    Block,
+}
+
+/// Bounds that client supplied sampling parameters are clamped into before forwarding.
+#[derive(Debug, Deserialize, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct ParamBoundsConfig {
+    #[serde(default)]
+    pub temperature: Option<BoundsConfig>,
+    #[serde(default)]
+    pub top_p: Option<BoundsConfig>,
+    #[serde(default)]
+    pub max_tokens: Option<u64>,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, Default)]
+#[serde(deny_unknown_fields)]
+pub struct BoundsConfig {
+    #[serde(default)]
+    pub min: Option<f64>,
+    #[serde(default)]
+    pub max: Option<f64>,
+}
+
+/// Rules under which a request is sent to the orchestrator without any detectors.
+#[derive(Debug, Deserialize, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct SkipDetectionConfig {
+    #[serde(default)]
+    pub max_chars: Option<usize>,
+}
+
+/// What the streaming path does with an orchestrator frame that is not a valid chunk.
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum MalformedFramePolicy {
+    /// Forward the frame to the client unchanged
+    #[default]
+    Passthrough,
+    /// Skip the frame
+    Drop,
+    /// Send an error event and end the stream
+    Error,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum DetectionAction {
+    #[default]
+    Fallback,
+    Truncate,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum StreamingDetectionMode {
+    Token,
+    Sentence,
+    Message,
+}
+
+/// Resolves the listen address from the `HOST` and `HTTP_PORT` environment variables, then the
+/// `server` section, then the defaults of `0.0.0.0:8090`.
+pub fn resolve_bind_address(
+    server: &ServerConfig,
+    host_env: Option<String>,
+    port_env: Option<String>,
+) -> Result<SocketAddr, String> {
+    let host = match host_env {
+        Some(host) => host
+            .parse()
+            .map_err(|e| format!("HOST '{}' is not a valid IP address: {}", host, e))?,
+        None => server.host.unwrap_or(DEFAULT_HOST),
+    };
+    let port = match port_env {
+        Some(port) => port
+            .parse()
+            .map_err(|e| format!("HTTP_PORT '{}' is not a valid port: {}", port, e))?,
+        None => server.port.unwrap_or(DEFAULT_PORT),
+    };
+    Ok(SocketAddr::new(host, port))
+}
+
+/// Picks the config file: an explicit path, else `config.<env>.yaml` in `config_dir` when an
+/// environment is named, which must then exist, else `config.yaml` in `config_dir`.
+pub fn resolve_config_path(
+    explicit_path: Option<String>,
+    env_name: Option<String>,
+    config_dir: &str,
+) -> Result<String, ConfigError> {
+    if let Some(path) = explicit_path {
+        return Ok(path);
+    }
+    match env_name {
+        Some(env_name) => {
+            let path = format!(
+                "{}/config.{}.yaml",
+                config_dir.trim_end_matches('/'),
+                env_name
+            );
+            if fs::metadata(&path).is_err() {
+                return Err(ConfigError::NotFound { path });
+            }
+            Ok(path)
+        }
+        None => Ok(format!("{}/config.yaml", config_dir.trim_end_matches('/'))),
+    }
+}
+
+/// Deserializes a YAML config, reporting the path of the offending entry (e.g.
+/// `routes[2].detectors`) on failure.
+pub fn parse_config(
+    yaml: &str,
+) -> Result<GatewayConfig, serde_path_to_error::Error<serde_yml::Error>> {
+    serde_path_to_error::deserialize(serde_yml::Deserializer::from_str(yaml))
+}
+
+pub fn read_config(path: &str) -> Result<GatewayConfig, ConfigError> {
+    let result = fs::read_to_string(path).map_err(|e| match e.kind() {
+        io::ErrorKind::NotFound => ConfigError::NotFound {
+            path: path.to_string(),
+        },
+        _ => ConfigError::Io {
+            path: path.to_string(),
+            source: e,
+        },
+    })?;
+
+    let result = interpolate_env(&result, |var| std::env::var(var).ok()).map_err(|message| {
+        ConfigError::Env {
+            path: path.to_string(),
+            message,
+        }
+    })?;
+
+    let mut cfg = parse_config(&result).map_err(|e| ConfigError::Parse {
+        path: path.to_string(),
+        key: e.path().to_string(),
+        source: e.into_inner(),
+    })?;
+    cfg.detectors = cfg
+        .detectors
+        .into_iter()
+        .map(|d| d.with_server_default())
+        .collect();
+    Ok(cfg)
+}
+
+/// Replaces every `${NAME}` in the config text with the variable looked up by `lookup`, and
+/// every `${NAME:-default}` with `default` when the variable is unset or empty, so deployments
+/// can inject values and keep secrets out of the file. Only names made of letters, digits and
+/// underscores are variables; other `${...}` text, like the request placeholders in
+/// `detector_params`, is left for later. Comments are left as they are.
+///
+/// Values may only fill in scalars: one containing a line break is rejected, as is any that
+/// changes the keys or nesting of the config, so the environment cannot add config entries.
+fn interpolate_env(value: &str, lookup: impl Fn(&str) -> Option<String>) -> Result<String, String> {
+    let mut resolved = String::new();
+    for (index, line) in value.split_inclusive('\n').enumerate() {
+        let (content, comment) = line.split_at(comment_start(line).unwrap_or(line.len()));
+        resolved.push_str(&interpolate_line(content, index + 1, &lookup)?);
+        resolved.push_str(comment);
+    }
+    if let (Ok(before), Ok(after)) = (
+        serde_yml::from_str::<serde_yml::Value>(value),
+        serde_yml::from_str::<serde_yml::Value>(&resolved),
+    ) {
+        if shape(before) != shape(after) {
+            return Err(
+                "environment variables may only set values, not add or replace config keys"
+                    .to_string(),
+            );
+        }
+    }
+    Ok(resolved)
+}
+
+fn interpolate_line(
+    line: &str,
+    line_number: usize,
+    lookup: &impl Fn(&str) -> Option<String>,
+) -> Result<String, String> {
+    let mut resolved = String::new();
+    let mut rest = line;
+    while let Some(start) = rest.find("${") {
+        resolved.push_str(&rest[..start]);
+        let placeholder = &rest[start + 2..];
+        let Some(end) = placeholder.find('}') else {
+            resolved.push_str(&rest[start..]);
+            return Ok(resolved);
+        };
+        let (name, default) = match placeholder[..end].split_once(":-") {
+            Some((name, default)) => (name, Some(default)),
+            None => (&placeholder[..end], None),
+        };
+        if !is_env_name(name) {
+            resolved.push_str("${");
+            rest = placeholder;
+            continue;
+        }
+        match (lookup(name), default) {
+            (Some(found), _) if found.contains(['\n', '\r']) => {
+                return Err(format!(
+                    "environment variable {} contains a line break (line {})",
+                    name, line_number
+                ));
+            }
+            (Some(found), Some(default)) if found.is_empty() => resolved.push_str(default),
+            (Some(found), _) => resolved.push_str(&found),
+            (None, Some(default)) => resolved.push_str(default),
+            (None, None) => {
+                return Err(format!(
+                    "environment variable {} is not set (line {})",
+                    name, line_number
+                ));
+            }
+        }
+        rest = &placeholder[end + 1..];
+    }
+    resolved.push_str(rest);
+    Ok(resolved)
+}
+
+/// Byte offset of the `#` starting a comment on a YAML line: one at the start of the line or
+/// after whitespace, outside of quoted scalars.
+fn comment_start(line: &str) -> Option<usize> {
+    let mut quote = None;
+    let mut previous = ' ';
+    for (i, c) in line.char_indices() {
+        match (quote, c) {
+            (None, '#') if previous.is_whitespace() => return Some(i),
+            (None, '\'' | '"')
+                if previous.is_whitespace() || matches!(previous, '[' | '{' | ',') =>
+            {
+                quote = Some(c)
+            }
+            (Some(open), _) if c == open => quote = None,
+            _ => {}
+        }
+        previous = c;
+    }
+    None
+}
+
+/// The keys and nesting of a YAML document, with every scalar replaced by null.
+fn shape(value: serde_yml::Value) -> serde_yml::Value {
+    use serde_yml::Value;
+    match value {
+        Value::Mapping(mapping) => Value::Mapping(
+            mapping
+                .into_iter()
+                .map(|(key, value)| (key, shape(value)))
+                .collect(),
+        ),
+        Value::Sequence(sequence) => Value::Sequence(sequence.into_iter().map(shape).collect()),
+        Value::Tagged(tagged) => shape(tagged.value),
+        _ => Value::Null,
+    }
+}
+
+fn is_env_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Checks the config for mistakes that would only surface at request time, describing each one.
+pub fn validate_config(gateway_cfg: &GatewayConfig) -> Result<(), String> {
     let detector_names: Vec<&String> = gateway_cfg
         .detectors
         .iter()
@@ -69,7 +794,38 @@ pub fn validate_registered_detectors(gateway_cfg: &GatewayConfig) {
         .collect();
 
     let mut issues = Vec::new();
+    if gateway_cfg.api_keys.iter().any(String::is_empty) {
+        issues.push("- api_keys must not contain an empty key".to_string());
+    }
+    for detector in &gateway_cfg.detectors {
+        if let Some(threshold) = detector.threshold {
+            if !(0.0..=1.0).contains(&threshold) {
+                issues.push(format!(
+                    "- detector '{}' has threshold {} which is not between 0 and 1",
+                    detector.name, threshold
+                ));
+            }
+        }
+    }
+    let mut route_names = HashSet::new();
     for route in gateway_cfg.routes.iter() {
+        if !route_names.insert(&route.name) {
+            issues.push(format!(
+                "- route name '{}' is used by more than one route",
+                route.name
+            ));
+        }
+        if route.name.is_empty()
+            || !route
+                .name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+        {
+            issues.push(format!(
+                "- route name '{}' must only contain letters, digits, '-', '_' and '.'",
+                route.name
+            ));
+        }
         for detector in &route.detectors {
             if !detector_names.contains(&detector) {
                 issues.push(format!(
@@ -84,28 +840,142 @@ pub fn validate_registered_detectors(gateway_cfg: &GatewayConfig) {
         let mut seen_output = HashSet::new();
 
         for detector_name in &route.detectors {
-            if let Some(detector_cfg) = gateway_cfg.detectors.iter().find(|d| &d.name == detector_name) {
-                if detector_cfg.input {
-                    let server = detector_cfg.server.as_ref().unwrap();
-                    if !seen_input.insert(server) {
-                        issues.push(format!(
-                            "- route '{}' contains more than one input detector with server '{}'",
-                            route.name, server
-                        ));
-                    }
-                    if !seen_output.insert(server) {
+            if let Some(detector_cfg) = gateway_cfg
+                .detectors
+                .iter()
+                .find(|d| &d.name == detector_name)
+            {
+                let server = detector_cfg.server.as_ref().unwrap_or(&detector_cfg.name);
+                if detector_cfg.input && !seen_input.insert(server) {
+                    issues.push(format!(
+                        "- route '{}' contains more than one input detector with server '{}'",
+                        route.name, server
+                    ));
+                }
+                if detector_cfg.output && !seen_output.insert(server) {
+                    issues.push(format!(
+                        "- route '{}' contains more than one output detector with server '{}'",
+                        route.name, server
+                    ));
+                }
+            }
+        }
+        for group in &route.detector_groups {
+            for detector in &group.detectors {
+                if !route.detectors.contains(detector) {
+                    issues.push(format!(
+                        "- detector group in route '{}' references detector '{}' which the route does not use",
+                        route.name, detector
+                    ));
+                }
+            }
+        }
+        if let Some(pointer) = &route.content_pointer {
+            if !pointer.starts_with('/') {
+                issues.push(format!(
+                    "- route '{}' has content_pointer '{}' which is not a JSON pointer starting with '/'",
+                    route.name, pointer
+                ));
+            }
+        }
+        if let Some(bounds) = &route.param_bounds {
+            for (param, bounds) in [("temperature", bounds.temperature), ("top_p", bounds.top_p)] {
+                if let Some(BoundsConfig {
+                    min: Some(min),
+                    max: Some(max),
+                }) = bounds
+                {
+                    if min > max {
                         issues.push(format!(
-                            "- route '{}' contains more than one output detector with server '{}'",
-                            route.name, server
+                            "- route '{}' has {} min {} above max {}",
+                            route.name, param, min, max
                         ));
                     }
                 }
             }
+            // Both limit max_tokens but only the cap sets it when absent, so which one wins
+            // would not be obvious from the config
+            if bounds.max_tokens.is_some() && route.max_tokens_cap.is_some() {
+                issues.push(format!(
+                    "- route '{}' sets both max_tokens_cap and param_bounds.max_tokens, use one of them",
+                    route.name
+                ));
+            }
+        }
+    }
+    if gateway_cfg.orchestrator.port == Some(0) {
+        issues.push("- orchestrator port must not be 0".to_string());
+    }
+    if gateway_cfg.orchestrator.max_retries > MAX_RETRIES {
+        issues.push(format!(
+            "- orchestrator max_retries must be at most {}, got {}",
+            MAX_RETRIES, gateway_cfg.orchestrator.max_retries
+        ));
+    }
+    if gateway_cfg.stream_reconnect_attempts > MAX_RETRIES {
+        issues.push(format!(
+            "- stream_reconnect_attempts must be at most {}, got {}",
+            MAX_RETRIES, gateway_cfg.stream_reconnect_attempts
+        ));
+    }
+    if gateway_cfg.orchestrator.host.is_empty() {
+        issues.push("- orchestrator host must list at least one host".to_string());
+    }
+    if let Some(allowed_hosts) = &gateway_cfg.allowed_orchestrator_hosts {
+        for host in &gateway_cfg.orchestrator.host {
+            let (name, _) = split_host_port(host);
+            if !allowed_hosts
+                .iter()
+                .any(|allowed| allowed.eq_ignore_ascii_case(name))
+            {
+                issues.push(format!(
+                    "- orchestrator host '{}' is not in allowed_orchestrator_hosts",
+                    name
+                ));
+            }
+        }
+    }
+    if let Some(version) = &gateway_cfg.orchestrator.min_tls_version {
+        if let Err(e) = parse_tls_version(version) {
+            issues.push(format!("- invalid orchestrator min_tls_version: {}", e));
+        }
+    }
+    if let Some(capture) = &gateway_cfg.capture {
+        if !(0.0..=1.0).contains(&capture.sampling_rate) {
+            issues.push(format!(
+                "- capture sampling_rate must be between 0 and 1, got {}",
+                capture.sampling_rate
+            ));
+        }
+    }
+    for (name, value) in &gateway_cfg.orchestrator_headers {
+        if axum::http::HeaderName::from_bytes(name.as_bytes()).is_err()
+            || axum::http::HeaderValue::from_str(value).is_err()
+        {
+            issues.push(format!(
+                "- orchestrator header '{}' is not a valid header",
+                name
+            ));
+        }
+    }
+    if axum::http::HeaderName::from_bytes(gateway_cfg.request_id_header.as_bytes()).is_err() {
+        issues.push(format!(
+            "- request_id_header '{}' is not a valid header name",
+            gateway_cfg.request_id_header
+        ));
+    }
+    if let Some(tenant) = &gateway_cfg.tenant_claim {
+        if axum::http::HeaderName::from_bytes(tenant.header.as_bytes()).is_err() {
+            issues.push(format!(
+                "- tenant_claim header '{}' is not a valid header name",
+                tenant.header
+            ));
         }
     }
     if !issues.is_empty() {
-        panic!("Config validation failed:\n{}", issues.join("\n"));
+        return Err(format!("Config validation failed:\n{}", issues.join("\n")));
     }
+    Ok(())
 }
 
 #[cfg(test)]
@@ -113,12 +983,595 @@ mod tests {
     use super::*;
 
     #[test]
-    #[should_panic]
+    fn test_sample_config_parses() {
+        let cfg = read_config("config/config.yaml").unwrap();
+        assert_eq!(cfg.routes.len(), 2);
+    }
+
+    #[test]
+    fn test_read_config_missing_file() {
+        let e = read_config("config/does-not-exist.yaml").unwrap_err();
+        assert!(matches!(e, ConfigError::NotFound { .. }), "{:?}", e);
+        assert_eq!(
+            e.to_string(),
+            "config file not found: config/does-not-exist.yaml"
+        );
+    }
+
+    #[test]
+    fn test_read_config_io_error() {
+        // A directory exists but cannot be read as a file
+        let e = read_config("config").unwrap_err();
+        assert!(matches!(e, ConfigError::Io { .. }), "{:?}", e);
+    }
+
+    #[test]
+    fn test_read_config_malformed_yaml() {
+        let dir = std::env::temp_dir().join("gateway-config-malformed-test");
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("config.yaml");
+        let path = path.to_str().unwrap();
+        let line = |e: &ConfigError| match e {
+            ConfigError::Parse { source, .. } => source.location().map(|l| l.line()),
+            _ => panic!("expected a parse error, got {:?}", e),
+        };
+
+        fs::write(path, "detectors: []\nroutes:\n  - name: [unclosed\n").unwrap();
+        let e = read_config(path).unwrap_err();
+        assert_eq!(line(&e), Some(3));
+        assert!(e
+            .to_string()
+            .starts_with(&format!("failed to parse {}", path)));
+
+        fs::write(
+            path,
+            "detectors: []\nroutes:\n  - name: pii\n    detectors: 12\n",
+        )
+        .unwrap();
+        let e = read_config(path).unwrap_err();
+        assert_eq!(line(&e), Some(4));
+        assert!(
+            e.to_string().starts_with(&format!(
+                "failed to parse {} at 'routes[0].detectors': ",
+                path
+            )),
+            "{}",
+            e
+        );
+        assert!(e.to_string().contains("at line 4"), "{}", e);
+    }
+
+    #[test]
+    fn test_resolve_config_path_explicit_wins() {
+        let path = resolve_config_path(
+            Some("/etc/gateway.yaml".to_string()),
+            Some("prod".to_string()),
+            "config",
+        )
+        .unwrap();
+        assert_eq!(path, "/etc/gateway.yaml");
+    }
+
+    #[test]
+    fn test_resolve_bind_address() {
+        let configured = ServerConfig {
+            host: Some("127.0.0.1".parse().unwrap()),
+            port: Some(9000),
+        };
+        let env = |value: &str| Some(value.to_string());
+        let cases = [
+            (ServerConfig::default(), None, None, "0.0.0.0:8090"),
+            (configured.clone(), None, None, "127.0.0.1:9000"),
+            (configured.clone(), env("::1"), None, "[::1]:9000"),
+            (configured.clone(), None, env("9100"), "127.0.0.1:9100"),
+            (configured, env("10.0.0.1"), env("9100"), "10.0.0.1:9100"),
+            (
+                ServerConfig::default(),
+                env("10.0.0.1"),
+                None,
+                "10.0.0.1:8090",
+            ),
+            (ServerConfig::default(), None, env("9100"), "0.0.0.0:9100"),
+        ];
+        for (server, host, port, expected) in cases {
+            assert_eq!(
+                resolve_bind_address(&server, host, port).unwrap(),
+                expected.parse().unwrap()
+            );
+        }
+
+        let server = ServerConfig::default();
+        assert_eq!(
+            resolve_bind_address(&server, env("localhost"), None).unwrap_err(),
+            "HOST 'localhost' is not a valid IP address: invalid IP address syntax"
+        );
+        assert!(resolve_bind_address(&server, None, env("70000")).is_err());
+    }
+
+    #[test]
+    fn test_server_host_is_validated_on_load() {
+        let yaml = "server:\n  host: localhost\ndetectors: []\nroutes: []\n";
+        let e = parse_config(yaml).unwrap_err();
+        assert_eq!(e.path().to_string(), "server.host");
+    }
+
+    #[test]
+    fn test_resolve_config_path_default() {
+        assert_eq!(
+            resolve_config_path(None, None, "config/").unwrap(),
+            "config/config.yaml"
+        );
+    }
+
+    #[test]
+    fn test_resolve_config_path_from_env() {
+        let dir = std::env::temp_dir().join("gateway-config-env-test");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("config.staging.yaml"), "").unwrap();
+        let dir = dir.to_str().unwrap();
+
+        let path = resolve_config_path(None, Some("staging".to_string()), dir).unwrap();
+        assert_eq!(path, format!("{}/config.staging.yaml", dir));
+    }
+
+    #[test]
+    fn test_resolve_config_path_missing_env_file() {
+        let e =
+            resolve_config_path(None, Some("does-not-exist".to_string()), "config").unwrap_err();
+        assert!(matches!(e, ConfigError::NotFound { .. }), "{:?}", e);
+        assert_eq!(
+            e.to_string(),
+            "config file not found: config/config.does-not-exist.yaml"
+        );
+    }
+
+    #[test]
+    fn test_validate_max_retries() {
+        let gc = GatewayConfig {
+            orchestrator: OrchestratorConfig {
+                host: vec!["localhost".to_string()],
+                max_retries: 64,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        let e = validate_config(&gc).unwrap_err();
+        assert!(
+            e.contains("orchestrator max_retries must be at most 10, got 64"),
+            "{}",
+            e
+        );
+    }
+
+    #[test]
+    fn test_validate_request_id_header() {
+        let gc = GatewayConfig {
+            orchestrator: OrchestratorConfig {
+                host: vec!["localhost".to_string()],
+                ..Default::default()
+            },
+            request_id_header: "x request id".to_string(),
+            ..Default::default()
+        };
+
+        let e = validate_config(&gc).unwrap_err();
+        assert!(
+            e.contains("request_id_header 'x request id' is not a valid header name"),
+            "{}",
+            e
+        );
+    }
+
+    #[test]
+    fn test_validate_stream_reconnect_attempts() {
+        let gc = GatewayConfig {
+            orchestrator: OrchestratorConfig {
+                host: vec!["localhost".to_string()],
+                ..Default::default()
+            },
+            stream_reconnect_attempts: 100,
+            ..Default::default()
+        };
+
+        let e = validate_config(&gc).unwrap_err();
+        assert!(
+            e.contains("stream_reconnect_attempts must be at most 10, got 100"),
+            "{}",
+            e
+        );
+    }
+
+    #[test]
+    fn test_validate_zero_orchestrator_port() {
+        let gc = GatewayConfig {
+            orchestrator: OrchestratorConfig {
+                host: vec!["localhost".to_string()],
+                port: Some(0),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        let e = validate_config(&gc).unwrap_err();
+        assert!(e.contains("orchestrator port must not be 0"), "{}", e);
+    }
+
+    #[test]
+    fn test_validate_detector_threshold() {
+        let gc = GatewayConfig {
+            orchestrator: OrchestratorConfig {
+                host: vec!["localhost".to_string()],
+                ..Default::default()
+            },
+            detectors: vec![DetectorConfig {
+                name: "hap".to_string(),
+                server: None,
+                input: true,
+                output: true,
+                detector_params: None,
+                threshold: Some(1.5),
+            }],
+            ..Default::default()
+        };
+
+        let e = validate_config(&gc).unwrap_err();
+        assert!(
+            e.contains("detector 'hap' has threshold 1.5 which is not between 0 and 1"),
+            "{}",
+            e
+        );
+    }
+
+    #[test]
+    fn test_validate_empty_api_key() {
+        let gc = GatewayConfig {
+            orchestrator: OrchestratorConfig {
+                host: vec!["localhost".to_string()],
+                ..Default::default()
+            },
+            api_keys: vec!["key".to_string(), String::new()],
+            ..Default::default()
+        };
+
+        let e = validate_config(&gc).unwrap_err();
+        assert!(
+            e.contains("api_keys must not contain an empty key"),
+            "{}",
+            e
+        );
+    }
+
+    #[test]
+    fn test_validate_route_names() {
+        let route = |name: &str| RouteConfig {
+            name: name.to_string(),
+            ..Default::default()
+        };
+        let gc = GatewayConfig {
+            orchestrator: OrchestratorConfig {
+                host: vec!["localhost".to_string()],
+                ..Default::default()
+            },
+            routes: vec![route("pii"), route("pii"), route("pii.v2"), route("a/*b")],
+            ..Default::default()
+        };
+
+        let e = validate_config(&gc).unwrap_err();
+        assert!(
+            e.contains("route name 'pii' is used by more than one route"),
+            "{}",
+            e
+        );
+        assert!(
+            e.contains("route name 'a/*b' must only contain letters"),
+            "{}",
+            e
+        );
+        assert!(!e.contains("'pii.v2'"), "{}", e);
+    }
+
+    #[test]
+    fn test_validate_max_tokens_limits() {
+        let gc = GatewayConfig {
+            orchestrator: OrchestratorConfig {
+                host: vec!["localhost".to_string()],
+                ..Default::default()
+            },
+            routes: vec![RouteConfig {
+                name: "capped".to_string(),
+                max_tokens_cap: Some(256),
+                param_bounds: Some(ParamBoundsConfig {
+                    max_tokens: Some(1024),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+
+        let e = validate_config(&gc).unwrap_err();
+        assert!(
+            e.contains(
+                "route 'capped' sets both max_tokens_cap and param_bounds.max_tokens, use one of them"
+            ),
+            "{}",
+            e
+        );
+    }
+
+    #[test]
+    fn test_validate_invalid_min_tls_version() {
+        let gc = GatewayConfig {
+            orchestrator: OrchestratorConfig {
+                min_tls_version: Some("1.3".to_string()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        let e = validate_config(&gc).unwrap_err();
+        assert!(e.contains("invalid orchestrator min_tls_version"), "{}", e);
+    }
+
+    #[test]
+    fn test_validate_orchestrator_host_not_allowed() {
+        let gc = GatewayConfig {
+            orchestrator: OrchestratorConfig {
+                host: vec![
+                    "orchestrator.svc".to_string(),
+                    "metadata.internal:80".to_string(),
+                ],
+                ..Default::default()
+            },
+            allowed_orchestrator_hosts: Some(vec!["orchestrator.svc".to_string()]),
+            ..Default::default()
+        };
+
+        let e = validate_config(&gc).unwrap_err();
+        assert!(
+            e.contains(
+                "orchestrator host 'metadata.internal' is not in allowed_orchestrator_hosts"
+            ),
+            "{}",
+            e
+        );
+    }
+
+    #[test]
+    fn test_validate_orchestrator_host_allowed() {
+        let gc = GatewayConfig {
+            orchestrator: OrchestratorConfig {
+                host: vec![
+                    "Orchestrator.svc".to_string(),
+                    "orchestrator.svc:8033".to_string(),
+                ],
+                ..Default::default()
+            },
+            allowed_orchestrator_hosts: Some(vec!["orchestrator.svc".to_string()]),
+            ..Default::default()
+        };
+
+        validate_config(&gc).unwrap();
+    }
+
+    #[test]
+    fn test_validate_no_orchestrator_hosts() {
+        let gc = GatewayConfig {
+            orchestrator: OrchestratorConfig {
+                host: vec![],
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        let e = validate_config(&gc).unwrap_err();
+        assert!(
+            e.contains("orchestrator host must list at least one host"),
+            "{}",
+            e
+        );
+    }
+
+    #[test]
+    fn test_orchestrator_host_list() {
+        let yaml = "orchestrator:\n  host: [orchestrator-0, orchestrator-1:8033]\n\
+                    detectors: []\nroutes: []\n";
+        let cfg = parse_config(yaml).unwrap();
+        assert_eq!(
+            cfg.orchestrator.host,
+            ["orchestrator-0", "orchestrator-1:8033"]
+        );
+
+        let yaml = "orchestrator:\n  host: {name: orchestrator}\ndetectors: []\nroutes: []\n";
+        let e = parse_config(yaml).unwrap_err();
+        assert_eq!(e.path().to_string(), "orchestrator.host");
+    }
+
+    #[test]
+    fn test_split_host_port() {
+        let cases = [
+            ("orchestrator", ("orchestrator", None)),
+            ("orchestrator:8033", ("orchestrator", Some(8033))),
+            ("orchestrator:http", ("orchestrator:http", None)),
+            ("10.0.0.1:8033", ("10.0.0.1", Some(8033))),
+            ("[::1]:8033", ("[::1]", Some(8033))),
+            ("::1", ("::1", None)),
+        ];
+        for (host, expected) in cases {
+            assert_eq!(split_host_port(host), expected, "{}", host);
+        }
+    }
+
+    #[test]
+    fn test_parse_tls_version() {
+        assert_eq!(
+            parse_tls_version("1.2").unwrap(),
+            reqwest::tls::Version::TLS_1_2
+        );
+        assert!(parse_tls_version("1.3").is_err());
+        assert!(parse_tls_version("TLSv1.2").is_err());
+    }
+
+    #[test]
+    fn test_out_of_range_orchestrator_port_is_rejected() {
+        let yaml = r#"
+orchestrator:
+  host: localhost
+  port: 70000
+detectors: []
+routes: []
+"#;
+        assert!(serde_yml::from_str::<GatewayConfig>(yaml).is_err());
+    }
+
+    #[test]
+    fn test_unknown_config_key_is_rejected() {
+        let yaml = r#"
+orchestrator:
+  host: localhost
+detecctors:
+  - name: regex
+    input: true
+    output: false
+routes: []
+"#;
+        let err = serde_yml::from_str::<GatewayConfig>(yaml).unwrap_err();
+        assert!(err.to_string().contains("detecctors"), "{}", err);
+    }
+
+    #[test]
+    fn test_parse_error_includes_path() {
+        let yaml = r#"
+detectors:
+  - name: regex
+    input: true
+    output: "sometimes"
+routes:
+  - name: pii
+    detectors: []
+  - name: passthrough
+    detectors: "regex"
+"#;
+        let err = parse_config(yaml).unwrap_err();
+        assert_eq!(err.path().to_string(), "detectors[0].output");
+
+        let yaml = yaml.replace(r#"output: "sometimes""#, "output: false");
+        let err = parse_config(&yaml).unwrap_err();
+        assert_eq!(err.path().to_string(), "routes[1].detectors");
+    }
+
+    #[test]
+    fn test_unknown_nested_config_key_is_rejected() {
+        let yaml = r#"
+detectors: []
+routes:
+  - name: pii
+    detectors: []
+    fallback_mesage: "typo"
+"#;
+        assert!(serde_yml::from_str::<GatewayConfig>(yaml).is_err());
+    }
+
+    #[test]
+    fn test_interpolate_env() {
+        let lookup = |name: &str| (name == "TOKEN").then(|| "secret".to_string());
+        assert_eq!(
+            interpolate_env("Bearer ${TOKEN}", lookup).unwrap(),
+            "Bearer secret"
+        );
+        assert_eq!(
+            interpolate_env("application/json", lookup).unwrap(),
+            "application/json"
+        );
+        assert_eq!(
+            interpolate_env("port: ${PORT:-8032}", lookup).unwrap(),
+            "port: 8032"
+        );
+        assert_eq!(interpolate_env("${TOKEN:-none}", lookup).unwrap(), "secret");
+        assert_eq!(
+            interpolate_env("a: 1\n${MISSING}", lookup).unwrap_err(),
+            "environment variable MISSING is not set (line 2)"
+        );
+        // Request placeholders and anything else that isn't a variable reference are kept
+        assert_eq!(
+            interpolate_env("${request.model} ${header.x-id} ${TOKEN", lookup).unwrap(),
+            "${request.model} ${header.x-id} ${TOKEN"
+        );
+    }
+
+    #[test]
+    fn test_interpolate_env_skips_comments() {
+        let lookup = |name: &str| (name == "TOKEN").then(|| "secret".to_string());
+        let yaml = "# token: ${MISSING}\ntoken: ${TOKEN} # was ${MISSING}\nurl: \"a #${TOKEN}\"\n\
+                    message: don't # ${MISSING}\n";
+        assert_eq!(
+            interpolate_env(yaml, lookup).unwrap(),
+            "# token: ${MISSING}\ntoken: secret # was ${MISSING}\nurl: \"a #secret\"\n\
+             message: don't # ${MISSING}\n"
+        );
+    }
+
+    #[test]
+    fn test_interpolate_env_rejects_injected_config() {
+        let lookup = |name: &str| match name {
+            "MULTILINE" => Some("localhost\n  port: 1".to_string()),
+            "FLOW" => Some("{port: 1}".to_string()),
+            _ => None,
+        };
+        assert_eq!(
+            interpolate_env("orchestrator:\n  host: ${MULTILINE}\n", lookup).unwrap_err(),
+            "environment variable MULTILINE contains a line break (line 2)"
+        );
+        assert_eq!(
+            interpolate_env("orchestrator:\n  host: ${FLOW}\n", lookup).unwrap_err(),
+            "environment variables may only set values, not add or replace config keys"
+        );
+    }
+
+    #[test]
+    fn test_read_config_interpolates_env() {
+        let dir = std::env::temp_dir().join("gateway-config-env-test");
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("config.yaml");
+        let path = path.to_str().unwrap();
+        std::env::set_var("GATEWAY_ENV_TEST_HOST", "orchestrator.svc");
+        std::env::remove_var("GATEWAY_ENV_TEST_PORT");
+
+        fs::write(
+            path,
+            "orchestrator:\n  host: ${GATEWAY_ENV_TEST_HOST}\n  port: ${GATEWAY_ENV_TEST_PORT:-8032}\n\
+             detectors: []\nroutes: []\n",
+        )
+        .unwrap();
+        let cfg = read_config(path).unwrap();
+        assert_eq!(cfg.orchestrator.host, ["orchestrator.svc"]);
+        assert_eq!(cfg.orchestrator.port, Some(8032));
+
+        fs::write(
+            path,
+            "orchestrator:\n  host: localhost\n  port: ${GATEWAY_ENV_TEST_PORT}\n\
+             detectors: []\nroutes: []\n",
+        )
+        .unwrap();
+        let e = read_config(path).unwrap_err();
+        assert!(matches!(e, ConfigError::Env { .. }), "{:?}", e);
+        assert_eq!(
+            e.to_string(),
+            format!(
+                "failed to resolve config {}: environment variable GATEWAY_ENV_TEST_PORT is not set (line 3)",
+                path
+            )
+        );
+    }
+
+    #[test]
     fn test_validate_registered_detectors() {
         let gc = GatewayConfig {
             orchestrator: OrchestratorConfig {
-                host: "localhost".to_string(),
+                host: vec!["localhost".to_string()],
                 port: Some(1234),
+                ..Default::default()
             },
             detectors: vec![DetectorConfig {
                 name: "regex".to_string(),
@@ -126,112 +1579,206 @@ mod tests {
                 input: false,
                 output: false,
                 detector_params: None,
+                threshold: None,
             }],
             routes: vec![RouteConfig {
                 name: "route1".to_string(),
                 detectors: vec!["regex".to_string(), "not_existent_detector".to_string()],
                 fallback_message: None,
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+
+        assert!(validate_config(&gc).is_err());
+    }
+
+    #[test]
+    fn test_validate_disabled_route() {
+        let gc = GatewayConfig {
+            routes: vec![RouteConfig {
+                name: "off".to_string(),
+                enabled: false,
+                detectors: vec!["typo".to_string()],
+                ..Default::default()
             }],
+            ..Default::default()
         };
 
-        validate_registered_detectors(&gc);
+        let e = validate_config(&gc).unwrap_err();
+        assert!(
+            e.contains("could not find detector 'typo' in route 'off'"),
+            "{}",
+            e
+        );
+    }
+
+    #[test]
+    fn test_route_enabled_by_default() {
+        let yaml = r#"
+detectors: []
+routes:
+  - name: a
+    detectors: []
+  - name: b
+    detectors: []
+    enabled: false
+"#;
+        let cfg = parse_config(yaml).unwrap();
+        assert!(cfg.routes[0].enabled);
+        assert!(!cfg.routes[1].enabled);
     }
 
     #[test]
-    #[should_panic]
     fn test_validate_multiple_same_server_input_detectors() {
         let gc = GatewayConfig {
             orchestrator: OrchestratorConfig {
-                host: "localhost".to_string(),
+                host: vec!["localhost".to_string()],
                 port: Some(1234),
+                ..Default::default()
             },
-            detectors: vec![DetectorConfig {
-                name: "regex-1".to_string(),
-                server: Some("server-a".to_string()),
-                input: true,
-                output: false,
-                detector_params: None,
-            }, DetectorConfig {
-                name: "regex-2".to_string(),
-                server: Some("server-a".to_string()),
-                input: true,
-                output: false,
-                detector_params: None,
-            },
-
+            detectors: vec![
+                DetectorConfig {
+                    name: "regex-1".to_string(),
+                    server: Some("server-a".to_string()),
+                    input: true,
+                    output: false,
+                    detector_params: None,
+                    threshold: None,
+                },
+                DetectorConfig {
+                    name: "regex-2".to_string(),
+                    server: Some("server-a".to_string()),
+                    input: true,
+                    output: false,
+                    detector_params: None,
+                    threshold: None,
+                },
             ],
             routes: vec![RouteConfig {
                 name: "route1".to_string(),
                 detectors: vec!["regex-1".to_string(), "regex-2".to_string()],
                 fallback_message: None,
+                ..Default::default()
             }],
+            ..Default::default()
         };
 
-        validate_registered_detectors(&gc);
+        let e = validate_config(&gc).unwrap_err();
+        assert!(
+            e.contains("more than one input detector with server 'server-a'"),
+            "{}",
+            e
+        );
     }
 
     #[test]
-    #[should_panic]
     fn test_validate_multiple_same_server_output_detectors() {
         let gc = GatewayConfig {
             orchestrator: OrchestratorConfig {
-                host: "localhost".to_string(),
+                host: vec!["localhost".to_string()],
                 port: Some(1234),
+                ..Default::default()
             },
-            detectors: vec![DetectorConfig {
-                name: "regex-1".to_string(),
-                server: Some("server-a".to_string()),
-                input: false,
-                output: true,
-                detector_params: None,
-            }, DetectorConfig {
-                name: "regex-2".to_string(),
-                server: Some("server-a".to_string()),
-                input: false,
-                output: true,
-                detector_params: None,
-            },
-
+            detectors: vec![
+                DetectorConfig {
+                    name: "regex-1".to_string(),
+                    server: Some("server-a".to_string()),
+                    input: false,
+                    output: true,
+                    detector_params: None,
+                    threshold: None,
+                },
+                DetectorConfig {
+                    name: "regex-2".to_string(),
+                    server: Some("server-a".to_string()),
+                    input: false,
+                    output: true,
+                    detector_params: None,
+                    threshold: None,
+                },
             ],
             routes: vec![RouteConfig {
                 name: "route1".to_string(),
                 detectors: vec!["regex-1".to_string(), "regex-2".to_string()],
                 fallback_message: None,
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+
+        let e = validate_config(&gc).unwrap_err();
+        assert!(
+            e.contains("more than one output detector with server 'server-a'"),
+            "{}",
+            e
+        );
+    }
+
+    #[test]
+    fn test_validate_duplicate_input_detectors_are_not_reported_as_output() {
+        let detector = |name: &str| DetectorConfig {
+            name: name.to_string(),
+            server: Some("server-a".to_string()),
+            input: true,
+            output: false,
+            detector_params: None,
+            threshold: None,
+        };
+        let gc = GatewayConfig {
+            detectors: vec![detector("regex-1"), detector("regex-2")],
+            routes: vec![RouteConfig {
+                name: "route1".to_string(),
+                detectors: vec!["regex-1".to_string(), "regex-2".to_string()],
+                ..Default::default()
             }],
+            ..Default::default()
         };
 
-        validate_registered_detectors(&gc);
+        let message = validate_config(&gc).unwrap_err();
+        assert!(
+            message.contains("more than one input detector"),
+            "{}",
+            message
+        );
+        assert!(!message.contains("output detector"), "{}", message);
     }
 
     #[test]
     fn test_validate_multiple_same_server_detectors() {
         let gc = GatewayConfig {
             orchestrator: OrchestratorConfig {
-                host: "localhost".to_string(),
+                host: vec!["localhost".to_string()],
                 port: Some(1234),
+                ..Default::default()
             },
-            detectors: vec![DetectorConfig {
-                name: "regex-1".to_string(),
-                server: Some("server-a".to_string()),
-                input: true,
-                output: false,
-                detector_params: None,
-            }, DetectorConfig {
-                name: "regex-2".to_string(),
-                server: Some("server-a".to_string()),
-                input: false,
-                output: true,
-                detector_params: None,
-            },
-
+            detectors: vec![
+                DetectorConfig {
+                    name: "regex-1".to_string(),
+                    server: Some("server-a".to_string()),
+                    input: true,
+                    output: false,
+                    detector_params: None,
+                    threshold: None,
+                },
+                DetectorConfig {
+                    name: "regex-2".to_string(),
+                    server: Some("server-a".to_string()),
+                    input: false,
+                    output: true,
+                    detector_params: None,
+                    threshold: None,
+                },
             ],
             routes: vec![RouteConfig {
                 name: "route1".to_string(),
                 detectors: vec!["regex-1".to_string(), "regex-2".to_string()],
                 fallback_message: None,
+                ..Default::default()
             }],
+            ..Default::default()
         };
 
-        validate_registered_detectors(&gc);
+        validate_config(&gc).unwrap();
     }
 }
@@ -0,0 +1,477 @@
+use serde_json::{json, Map, Value};
+
+use crate::config::MAX_RETRIES;
+
+/// The JSON Schema of the config file, for editor completion and pre-commit checks of configs.
+/// It describes what the config structs deserialize, not what `validate_config` goes on to
+/// check, such as detectors that routes refer to existing.
+pub fn config_schema() -> Value {
+    let mut schema = object(
+        json!({
+            "server": reference("ServerConfig"),
+            "orchestrator": reference("OrchestratorConfig"),
+            "detectors": array(reference("DetectorConfig")),
+            "routes": array(reference("RouteConfig")),
+            "request_id_header": string_default("x-request-id"),
+            "inject_gateway_metadata": boolean(),
+            "capture": nullable(reference("CaptureConfig")),
+            "watchdog": nullable(reference("WatchdogConfig")),
+            "max_detection_results": nullable(count()),
+            "sse_initial_keep_alive": boolean(),
+            "forwarded_headers": reference("ForwardedHeadersConfig"),
+            "listener_tls": nullable(reference("ListenerTlsConfig")),
+            "orchestrator_headers": string_map(),
+            "malformed_frame_policy": variants(&["passthrough", "drop", "error"], "passthrough"),
+            "tenant_claim": nullable(reference("TenantClaimConfig")),
+            "stream_reconnect_attempts": retries(),
+            "stream_reconnect_backoff_ms": count_default(100),
+            "retry_budget": nullable(reference("RetryBudgetConfig")),
+            "inbound_timeout_ms": nullable(count()),
+            "allowed_orchestrator_hosts": nullable(array(string())),
+            "shutdown_grace_period_seconds": count_default(30),
+            "reload_debounce_ms": count_default(500),
+            "redact_logs": boolean(),
+            "api_keys": array(string()),
+        }),
+        &["detectors", "routes"],
+    );
+    schema.insert(
+        "$schema".to_string(),
+        json!("https://json-schema.org/draft/2020-12/schema"),
+    );
+    schema.insert("title".to_string(), json!("GatewayConfig"));
+    schema.insert("$defs".to_string(), Value::Object(definitions()));
+    Value::Object(schema)
+}
+
+fn definitions() -> Map<String, Value> {
+    let port = || nullable(json!({"type": "integer", "minimum": 0, "maximum": 65535}));
+    let bounds = || {
+        Value::Object(object(
+            json!({"min": nullable(number()), "max": nullable(number())}),
+            &[],
+        ))
+    };
+    [
+        (
+            "ServerConfig",
+            object(
+                json!({
+                    "host": nullable(json!({"type": "string", "description": "IP address"})),
+                    "port": port(),
+                }),
+                &[],
+            ),
+        ),
+        (
+            "OrchestratorConfig",
+            object(
+                json!({
+                    "host": {"anyOf": [string(), array(string())]},
+                    "port": port(),
+                    "health_path": string_default("/health"),
+                    "models_path": string_default("/v1/models"),
+                    "api_path": nullable(string()),
+                    "min_tls_version": nullable(json!({"enum": ["1.0", "1.1", "1.2"]})),
+                    "tls": reference("OrchestratorTlsConfig"),
+                    "timeout_seconds": nullable(count()),
+                    "max_retries": retries(),
+                    "backoff_ms": count_default(100),
+                }),
+                &["host"],
+            ),
+        ),
+        (
+            "OrchestratorTlsConfig",
+            object(
+                json!({
+                    "cert_path": string_default("/etc/tls/private/tls.crt"),
+                    "key_path": string_default("/etc/tls/private/tls.key"),
+                    "ca_path": string_default("/etc/tls/ca/service-ca.crt"),
+                }),
+                &[],
+            ),
+        ),
+        (
+            "CaptureConfig",
+            object(
+                json!({
+                    "capture_dir": string(),
+                    "sampling_rate": {"type": "number", "minimum": 0, "maximum": 1, "default": 0},
+                    "redact_content": boolean(),
+                    "compress": boolean(),
+                }),
+                &["capture_dir"],
+            ),
+        ),
+        (
+            "RetryBudgetConfig",
+            object(
+                json!({
+                    "retries_per_second": number(),
+                    "burst": count_default(10),
+                }),
+                &["retries_per_second"],
+            ),
+        ),
+        (
+            "WatchdogConfig",
+            object(
+                json!({
+                    "interval_ms": count_default(1000),
+                    "stall_threshold_ms": count_default(5000),
+                }),
+                &[],
+            ),
+        ),
+        (
+            "TenantClaimConfig",
+            object(
+                json!({
+                    "claim": string(),
+                    "header": string_default("x-tenant-id"),
+                    "hs256_secret": nullable(string()),
+                }),
+                &["claim"],
+            ),
+        ),
+        (
+            "ListenerTlsConfig",
+            object(
+                json!({"cert_path": string(), "key_path": string()}),
+                &["cert_path", "key_path"],
+            ),
+        ),
+        (
+            "ForwardedHeadersConfig",
+            object(
+                json!({
+                    "max_count": count_default(64),
+                    "max_bytes": count_default(16 * 1024),
+                }),
+                &[],
+            ),
+        ),
+        (
+            "DetectorConfig",
+            object(
+                json!({
+                    "name": string(),
+                    "server": nullable(string()),
+                    "input": {"type": "boolean"},
+                    "output": {"type": "boolean"},
+                    "detector_params": {},
+                    "threshold": nullable(number()),
+                }),
+                &["name", "input", "output"],
+            ),
+        ),
+        (
+            "RouteConfig",
+            object(
+                json!({
+                    "name": string(),
+                    "enabled": {"type": "boolean", "default": true},
+                    "detectors": array(string()),
+                    "fallback_message": nullable(string()),
+                    "fallback_messages": string_map(),
+                    "buffered_streaming": boolean(),
+                    "buffer_upstream_stream": boolean(),
+                    "streaming_detection_mode": nullable(json!({
+                        "enum": ["token", "sentence", "message"]
+                    })),
+                    "detect_last_message_only": boolean(),
+                    "allow_detector_selection": boolean(),
+                    "expose_verdict": boolean(),
+                    "verdict_threshold": nullable(number()),
+                    "action": variants(&["fallback", "truncate"], "fallback"),
+                    "truncation_notice": nullable(string()),
+                    "api_path": nullable(string()),
+                    "skip_detection_if": nullable(reference("SkipDetectionConfig")),
+                    "coalesce_identical_requests": boolean(),
+                    "param_bounds": nullable(reference("ParamBoundsConfig")),
+                    "return_detection_summary": boolean(),
+                    "detector_groups": array(reference("DetectorGroupConfig")),
+                    "detectors_merge_policy": variants(&["overwrite", "merge", "reject"], "overwrite"),
+                    "detections_trailer": boolean(),
+                    "content_pointer": nullable(string()),
+                    "isolated_client": boolean(),
+                    "timeout_seconds": nullable(count()),
+                    "allowed_models": nullable(array(string())),
+                    "system_prompt": nullable(string()),
+                    "max_tokens_cap": nullable(count()),
+                    "expose_detections": boolean(),
+                    "detection_only": boolean(),
+                }),
+                &["name", "detectors"],
+            ),
+        ),
+        (
+            "DetectorGroupConfig",
+            object(
+                json!({
+                    "detectors": array(string()),
+                    "action": {"enum": ["monitor", "redact", "block"]},
+                }),
+                &["detectors", "action"],
+            ),
+        ),
+        (
+            "ParamBoundsConfig",
+            object(
+                json!({
+                    "temperature": nullable(bounds()),
+                    "top_p": nullable(bounds()),
+                    "max_tokens": nullable(count()),
+                }),
+                &[],
+            ),
+        ),
+        (
+            "SkipDetectionConfig",
+            object(json!({"max_chars": nullable(count())}), &[]),
+        ),
+    ]
+    .into_iter()
+    .map(|(name, schema)| (name.to_string(), Value::Object(schema)))
+    .collect()
+}
+
+/// An object with exactly `properties`, as the config structs deny unknown fields.
+fn object(properties: Value, required: &[&str]) -> Map<String, Value> {
+    let mut schema = Map::new();
+    schema.insert("type".to_string(), json!("object"));
+    schema.insert("properties".to_string(), properties);
+    if !required.is_empty() {
+        schema.insert("required".to_string(), json!(required));
+    }
+    schema.insert("additionalProperties".to_string(), json!(false));
+    schema
+}
+
+fn reference(definition: &str) -> Value {
+    json!({"$ref": format!("#/$defs/{}", definition)})
+}
+
+/// An optional field, which YAML can also set to `null`.
+fn nullable(schema: Value) -> Value {
+    json!({"anyOf": [schema, {"type": "null"}]})
+}
+
+fn array(items: Value) -> Value {
+    json!({"type": "array", "items": items})
+}
+
+fn string() -> Value {
+    json!({"type": "string"})
+}
+
+fn string_default(default: &str) -> Value {
+    json!({"type": "string", "default": default})
+}
+
+fn string_map() -> Value {
+    json!({"type": "object", "additionalProperties": string()})
+}
+
+fn boolean() -> Value {
+    json!({"type": "boolean", "default": false})
+}
+
+fn number() -> Value {
+    json!({"type": "number"})
+}
+
+fn count() -> Value {
+    json!({"type": "integer", "minimum": 0})
+}
+
+fn count_default(default: u64) -> Value {
+    json!({"type": "integer", "minimum": 0, "default": default})
+}
+
+fn retries() -> Value {
+    json!({"type": "integer", "minimum": 0, "maximum": MAX_RETRIES, "default": 0})
+}
+
+fn variants(variants: &[&str], default: &str) -> Value {
+    json!({"enum": variants, "default": default})
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::de::DeserializeOwned;
+
+    use super::*;
+    use crate::config::*;
+
+    /// The fields a config struct accepts, as listed by serde when it meets an unknown one.
+    fn accepted_fields<T: DeserializeOwned>() -> Vec<String> {
+        let error = serde_json::from_value::<T>(json!({"not_a_field": null}))
+            .err()
+            .unwrap()
+            .to_string();
+        let expected = error
+            .split_once("expected ")
+            .unwrap_or_else(|| panic!("{}", error))
+            .1;
+        let mut fields: Vec<String> = expected
+            .split('`')
+            .skip(1)
+            .step_by(2)
+            .map(str::to_string)
+            .collect();
+        fields.sort();
+        fields
+    }
+
+    /// The variants a config enum accepts, as listed by serde when it meets an unknown one.
+    fn accepted_variants<T: DeserializeOwned>() -> Vec<Value> {
+        let error = serde_json::from_value::<T>(json!("not_a_variant"))
+            .err()
+            .unwrap()
+            .to_string();
+        let mut variants: Vec<Value> = error
+            .split_once("expected ")
+            .unwrap_or_else(|| panic!("{}", error))
+            .1
+            .split('`')
+            .skip(1)
+            .step_by(2)
+            .map(|variant| json!(variant))
+            .collect();
+        variants.sort_by_key(|variant| variant.to_string());
+        variants
+    }
+
+    fn properties(schema: &Value) -> Vec<String> {
+        let mut properties: Vec<String> = schema["properties"]
+            .as_object()
+            .unwrap()
+            .keys()
+            .cloned()
+            .collect();
+        properties.sort();
+        properties
+    }
+
+    fn enum_values(schema: &Value) -> Vec<Value> {
+        let schema = match schema.get("anyOf") {
+            Some(any_of) => &any_of[0],
+            None => schema,
+        };
+        let mut values = schema["enum"].as_array().unwrap().clone();
+        values.sort_by_key(|value| value.to_string());
+        values
+    }
+
+    #[test]
+    fn test_schema_matches_config_structs() {
+        let schema = config_schema();
+        let definition = |name: &str| schema["$defs"][name].clone();
+        let cases = [
+            (schema.clone(), accepted_fields::<GatewayConfig>()),
+            (
+                definition("ServerConfig"),
+                accepted_fields::<ServerConfig>(),
+            ),
+            (
+                definition("OrchestratorConfig"),
+                accepted_fields::<OrchestratorConfig>(),
+            ),
+            (
+                definition("OrchestratorTlsConfig"),
+                accepted_fields::<OrchestratorTlsConfig>(),
+            ),
+            (
+                definition("CaptureConfig"),
+                accepted_fields::<CaptureConfig>(),
+            ),
+            (
+                definition("RetryBudgetConfig"),
+                accepted_fields::<RetryBudgetConfig>(),
+            ),
+            (
+                definition("WatchdogConfig"),
+                accepted_fields::<WatchdogConfig>(),
+            ),
+            (
+                definition("TenantClaimConfig"),
+                accepted_fields::<TenantClaimConfig>(),
+            ),
+            (
+                definition("ListenerTlsConfig"),
+                accepted_fields::<ListenerTlsConfig>(),
+            ),
+            (
+                definition("ForwardedHeadersConfig"),
+                accepted_fields::<ForwardedHeadersConfig>(),
+            ),
+            (
+                definition("DetectorConfig"),
+                accepted_fields::<DetectorConfig>(),
+            ),
+            (definition("RouteConfig"), accepted_fields::<RouteConfig>()),
+            (
+                definition("DetectorGroupConfig"),
+                accepted_fields::<DetectorGroupConfig>(),
+            ),
+            (
+                definition("ParamBoundsConfig"),
+                accepted_fields::<ParamBoundsConfig>(),
+            ),
+            (
+                definition("SkipDetectionConfig"),
+                accepted_fields::<SkipDetectionConfig>(),
+            ),
+        ];
+        for (schema, fields) in cases {
+            assert_eq!(properties(&schema), fields);
+        }
+
+        let route = definition("RouteConfig");
+        let cases = [
+            (
+                &schema["properties"]["malformed_frame_policy"],
+                accepted_variants::<MalformedFramePolicy>(),
+            ),
+            (
+                &route["properties"]["streaming_detection_mode"],
+                accepted_variants::<StreamingDetectionMode>(),
+            ),
+            (
+                &route["properties"]["action"],
+                accepted_variants::<DetectionAction>(),
+            ),
+            (
+                &route["properties"]["detectors_merge_policy"],
+                accepted_variants::<DetectorsMergePolicy>(),
+            ),
+            (
+                &definition("DetectorGroupConfig")["properties"]["action"],
+                accepted_variants::<GroupAction>(),
+            ),
+        ];
+        for (schema, variants) in cases {
+            assert_eq!(enum_values(schema), variants, "{}", schema);
+        }
+    }
+
+    #[test]
+    fn test_schema_requires_fields_without_defaults() {
+        let schema = config_schema();
+        assert_eq!(schema["required"], json!(["detectors", "routes"]));
+        assert_eq!(
+            schema["$defs"]["RouteConfig"]["required"],
+            json!(["name", "detectors"])
+        );
+        assert_eq!(
+            schema["$defs"]["DetectorConfig"]["required"],
+            json!(["name", "input", "output"])
+        );
+        assert!(schema["$defs"]["WatchdogConfig"].get("required").is_none());
+        for (name, definition) in schema["$defs"].as_object().unwrap() {
+            assert_eq!(definition["additionalProperties"], false, "{}", name);
+        }
+    }
+}
@@ -0,0 +1,364 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::time::Duration;
+
+use axum::extract::rejection::JsonRejection;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use serde_json::{json, Value};
+
+/// Failure modes when talking to the orchestrator, kept distinct so handlers can map each one to
+/// an appropriate response.
+#[derive(Debug)]
+pub enum OrchestratorError {
+    /// No connection to the orchestrator could be made.
+    Connect(reqwest::Error),
+    /// The request could not be built or sent, or the response could not be decoded, for a
+    /// reason other than a failed connection, a timeout or a cut-off body.
+    Request(reqwest::Error),
+    /// The orchestrator responded with a non-success status.
+    Status { code: StatusCode, body: String },
+    /// The orchestrator response could not be decoded.
+    Parse(String),
+    /// The request to the orchestrator timed out.
+    Timeout(reqwest::Error),
+    /// The orchestrator closed the connection before the response body was complete.
+    IncompleteBody(reqwest::Error),
+    /// The orchestrator responded with a success status but no body.
+    EmptyBody,
+    /// The orchestrator sent nothing on a stream for longer than the route's timeout.
+    IdleTimeout(Duration),
+}
+
+impl OrchestratorError {
+    /// The status code the gateway responds with when this error ends a request.
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            OrchestratorError::Connect(_) => StatusCode::BAD_GATEWAY,
+            OrchestratorError::Request(_) => StatusCode::BAD_GATEWAY,
+            // The orchestrator's own errors, such as a 400 for an unknown model, are the
+            // client's to see; anything that isn't an error status is still a bad gateway
+            OrchestratorError::Status { code, .. }
+                if code.is_client_error() || code.is_server_error() =>
+            {
+                *code
+            }
+            OrchestratorError::Status { .. } => StatusCode::BAD_GATEWAY,
+            OrchestratorError::Parse(_) => StatusCode::BAD_GATEWAY,
+            OrchestratorError::Timeout(_) => StatusCode::GATEWAY_TIMEOUT,
+            OrchestratorError::IncompleteBody(_) => StatusCode::BAD_GATEWAY,
+            OrchestratorError::EmptyBody => StatusCode::BAD_GATEWAY,
+            OrchestratorError::IdleTimeout(_) => StatusCode::GATEWAY_TIMEOUT,
+        }
+    }
+}
+
+impl fmt::Display for OrchestratorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OrchestratorError::Connect(e) => {
+                write!(f, "Failed to connect to orchestrator: {:?}", e)
+            }
+            OrchestratorError::Request(e) => {
+                write!(f, "Request to orchestrator failed: {:?}", e)
+            }
+            OrchestratorError::Status { code, body } => {
+                write!(f, "Orchestrator returned error status {}: {}", code, body)
+            }
+            OrchestratorError::Parse(e) => {
+                write!(f, "Failed to parse orchestrator response: {}", e)
+            }
+            OrchestratorError::Timeout(e) => {
+                write!(f, "Request to orchestrator timed out: {:?}", e)
+            }
+            OrchestratorError::IncompleteBody(e) => {
+                write!(
+                    f,
+                    "Orchestrator closed the connection before the response was complete: {}",
+                    e
+                )
+            }
+            OrchestratorError::EmptyBody => write!(f, "Orchestrator returned empty response"),
+            OrchestratorError::IdleTimeout(timeout) => {
+                write!(f, "Orchestrator sent nothing for {}s", timeout.as_secs())
+            }
+        }
+    }
+}
+
+impl Error for OrchestratorError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            OrchestratorError::Connect(e)
+            | OrchestratorError::Request(e)
+            | OrchestratorError::Timeout(e)
+            | OrchestratorError::IncompleteBody(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for OrchestratorError {
+    fn from(e: reqwest::Error) -> Self {
+        if e.is_timeout() {
+            OrchestratorError::Timeout(e)
+        } else if e.is_connect() {
+            OrchestratorError::Connect(e)
+        } else if e.is_body() {
+            OrchestratorError::IncompleteBody(e)
+        } else {
+            OrchestratorError::Request(e)
+        }
+    }
+}
+
+impl From<serde_json::Error> for OrchestratorError {
+    fn from(e: serde_json::Error) -> Self {
+        OrchestratorError::Parse(e.to_string())
+    }
+}
+
+/// An error answered to a client in the OpenAI format,
+/// `{"error": {"message": ..., "type": ..., "code": ...}}`, which OpenAI SDKs know how to surface.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ApiError {
+    status: StatusCode,
+    error_type: &'static str,
+    message: String,
+    code: Option<String>,
+}
+
+impl ApiError {
+    pub fn new(status: StatusCode, error_type: &'static str, message: impl Into<String>) -> Self {
+        ApiError {
+            status,
+            error_type,
+            message: message.into(),
+            code: None,
+        }
+    }
+
+    /// The request is malformed or asks for something the route does not allow.
+    pub fn invalid_request(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, "invalid_request_error", message)
+    }
+
+    /// The request is well formed but not permitted on the route.
+    pub fn permission(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::FORBIDDEN, "permission_error", message)
+    }
+
+    /// The request did not present a valid API key for the gateway.
+    pub fn unauthorized(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::UNAUTHORIZED, "authentication_error", message)
+    }
+
+    /// Something went wrong within the gateway itself.
+    pub fn internal(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::INTERNAL_SERVER_ERROR, "server_error", message)
+    }
+
+    pub fn with_code(mut self, code: impl Into<String>) -> Self {
+        self.code = Some(code.into());
+        self
+    }
+
+    /// The error envelope, also sent as the data of an SSE event when a stream fails part way.
+    pub fn body(&self) -> Value {
+        json!({
+            "error": {
+                "message": self.message,
+                "type": self.error_type,
+                "code": self.code,
+            }
+        })
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.status, Json(self.body())).into_response()
+    }
+}
+
+impl From<OrchestratorError> for ApiError {
+    /// Timeouts and failures to get a usable answer from the orchestrator are told apart by
+    /// `type`. The orchestrator's own error responses, such as a 400 for an unknown model, are
+    /// the client's to see, so their message and code are passed on.
+    fn from(e: OrchestratorError) -> Self {
+        let status = e.status_code();
+        match &e {
+            OrchestratorError::Timeout(_) | OrchestratorError::IdleTimeout(_) => {
+                ApiError::new(status, "timeout", e.to_string())
+            }
+            OrchestratorError::Status { code, body } => {
+                let error_type = if *code == StatusCode::GATEWAY_TIMEOUT {
+                    "timeout"
+                } else if code.is_client_error() {
+                    "invalid_request_error"
+                } else {
+                    "upstream_error"
+                };
+                let (message, error_code) = orchestrator_error_message(body);
+                let message = match message {
+                    Some(message) => message,
+                    None if body.is_empty() => e.to_string(),
+                    None => body.clone(),
+                };
+                ApiError {
+                    status,
+                    error_type,
+                    message,
+                    code: error_code,
+                }
+            }
+            _ => ApiError::new(status, "upstream_error", e.to_string()),
+        }
+    }
+}
+
+impl From<JsonRejection> for ApiError {
+    /// A body that is not JSON, or not sent as JSON, keeps axum's status and explanation.
+    fn from(rejection: JsonRejection) -> Self {
+        ApiError::new(
+            rejection.status(),
+            "invalid_request_error",
+            rejection.body_text(),
+        )
+    }
+}
+
+/// The message and code of an orchestrator error body, which is either an OpenAI error envelope
+/// passed on from the model server or the orchestrator's own `{"code": ..., "details": ...}`.
+fn orchestrator_error_message(body: &str) -> (Option<String>, Option<String>) {
+    let Ok(body) = serde_json::from_str::<Value>(body) else {
+        return (None, None);
+    };
+    let error = match &body["error"] {
+        error @ Value::Object(_) => error,
+        _ => &body,
+    };
+    let message = ["message", "details", "detail"]
+        .iter()
+        .find_map(|key| error[key].as_str())
+        .map(str::to_string);
+    let code = error["code"].as_str().map(str::to_string);
+    (message, code)
+}
+
+/// Failure modes when loading the gateway config file.
+#[derive(Debug)]
+pub enum ConfigError {
+    /// No file exists at the config path.
+    NotFound { path: String },
+    /// The file exists but could not be read.
+    Io { path: String, source: io::Error },
+    /// The file is not valid YAML or does not match the config schema. `key` is the path of the
+    /// offending entry, e.g. `routes[2].detectors`.
+    Parse {
+        path: String,
+        key: String,
+        source: serde_yml::Error,
+    },
+    /// A `${NAME}` reference in the config could not be resolved.
+    Env { path: String, message: String },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::NotFound { path } => write!(f, "config file not found: {}", path),
+            ConfigError::Io { path, source } => {
+                write!(f, "failed to read config {}: {}", path, source)
+            }
+            // The YAML error already ends with its location, e.g. "at line 12 column 5"
+            ConfigError::Parse { path, key, source } => {
+                write!(f, "failed to parse {} at '{}': {}", path, key, source)
+            }
+            ConfigError::Env { path, message } => {
+                write!(f, "failed to resolve config {}: {}", path, message)
+            }
+        }
+    }
+}
+
+impl Error for ConfigError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ConfigError::Io { source, .. } => Some(source),
+            ConfigError::Parse { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_status_codes() {
+        let status = OrchestratorError::Status {
+            code: StatusCode::UNPROCESSABLE_ENTITY,
+            body: "bad request".to_string(),
+        };
+        assert_eq!(status.status_code(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(
+            status.to_string(),
+            "Orchestrator returned error status 422 Unprocessable Entity: bad request"
+        );
+
+        let redirect = OrchestratorError::Status {
+            code: StatusCode::FOUND,
+            body: String::new(),
+        };
+        assert_eq!(redirect.status_code(), StatusCode::BAD_GATEWAY);
+
+        let parse = OrchestratorError::from(serde_json::from_str::<u8>("nope").unwrap_err());
+        assert_eq!(parse.status_code(), StatusCode::BAD_GATEWAY);
+    }
+
+    #[test]
+    fn test_api_errors_from_orchestrator_errors() {
+        let cases = [
+            (
+                OrchestratorError::Status {
+                    code: StatusCode::UNPROCESSABLE_ENTITY,
+                    body: r#"{"code": 422, "details": "invalid params"}"#.to_string(),
+                },
+                json!({"message": "invalid params", "type": "invalid_request_error", "code": null}),
+            ),
+            (
+                OrchestratorError::Status {
+                    code: StatusCode::BAD_REQUEST,
+                    body: r#"{"error": {"message": "unknown model", "code": "model_not_found"}}"#
+                        .to_string(),
+                },
+                json!({"message": "unknown model", "type": "invalid_request_error", "code": "model_not_found"}),
+            ),
+            (
+                OrchestratorError::Status {
+                    code: StatusCode::SERVICE_UNAVAILABLE,
+                    body: "overloaded".to_string(),
+                },
+                json!({"message": "overloaded", "type": "upstream_error", "code": null}),
+            ),
+            (
+                OrchestratorError::EmptyBody,
+                json!({"message": "Orchestrator returned empty response", "type": "upstream_error", "code": null}),
+            ),
+            (
+                OrchestratorError::IdleTimeout(Duration::from_secs(5)),
+                json!({"message": "Orchestrator sent nothing for 5s", "type": "timeout", "code": null}),
+            ),
+        ];
+        for (error, expected) in cases {
+            let status = error.status_code();
+            let api_error = ApiError::from(error);
+            assert_eq!(api_error.status, status);
+            assert_eq!(api_error.body(), json!({"error": expected}));
+        }
+    }
+}
@@ -0,0 +1,61 @@
+use std::time::Duration;
+
+use axum::body::Body;
+use axum::extract::{Request, State};
+use axum::http::StatusCode;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use serde::Deserialize;
+
+use crate::error::ApiError;
+
+/// axum's default limit on JSON bodies, which the handlers enforce anyway.
+const MAX_BODY_BYTES: usize = 2 * 1024 * 1024;
+
+#[derive(Deserialize)]
+struct StreamFlag {
+    #[serde(default)]
+    stream: bool,
+}
+
+/// Answers with a 504 when a request takes longer than `timeout` to be responded to. Requests
+/// asking for a stream are left alone: they legitimately last as long as the generation does,
+/// and in buffered mode only start responding once it is complete.
+pub async fn inbound_timeout(
+    State(timeout): State<Duration>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let (parts, body) = request.into_parts();
+    let body = match axum::body::to_bytes(body, MAX_BODY_BYTES).await {
+        Ok(body) => body,
+        Err(_) => {
+            return ApiError::new(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                "invalid_request_error",
+                "request body is too large",
+            )
+            .into_response()
+        }
+    };
+    let streaming = serde_json::from_slice::<StreamFlag>(&body).is_ok_and(|flag| flag.stream);
+    let request = Request::from_parts(parts, Body::from(body));
+    if streaming {
+        return next.run(request).await;
+    }
+    match tokio::time::timeout(timeout, next.run(request)).await {
+        Ok(response) => response,
+        Err(_) => {
+            tracing::warn!(
+                "Request not answered within the inbound timeout of {}ms",
+                timeout.as_millis()
+            );
+            ApiError::new(
+                StatusCode::GATEWAY_TIMEOUT,
+                "timeout",
+                "the gateway did not respond in time",
+            )
+            .into_response()
+        }
+    }
+}
@@ -0,0 +1,172 @@
+use std::sync::Arc;
+
+use axum::extract::{Request, State};
+use axum::http::{header, HeaderMap, HeaderName};
+use axum::middleware::Next;
+use axum::response::Response;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use openssl::hash::MessageDigest;
+use openssl::pkey::PKey;
+use openssl::sign::Signer;
+use serde_json::Value;
+
+use crate::config::TenantClaimConfig;
+
+/// Reads the configured claim from the bearer token in the `authorization` header. The token
+/// is only verified when an HS256 secret is configured; otherwise the claims are trusted as
+/// sent. Missing, malformed or unverifiable tokens yield `None`.
+pub fn extract_claim(headers: &HeaderMap, config: &TenantClaimConfig) -> Option<String> {
+    let token = headers
+        .get(header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")?;
+    let mut parts = token.split('.');
+    let (Some(header), Some(payload), Some(signature), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        tracing::debug!("Authorization header does not carry a JWT");
+        return None;
+    };
+
+    if let Some(secret) = &config.hs256_secret {
+        if !verify_hs256(header, payload, signature, secret) {
+            tracing::warn!("Ignoring JWT with an invalid signature");
+            return None;
+        }
+    }
+
+    let claims: Value = serde_json::from_slice(&URL_SAFE_NO_PAD.decode(payload).ok()?).ok()?;
+    let claim = config
+        .claim
+        .split('.')
+        .try_fold(&claims, |value, key| value.get(key))?;
+    match claim {
+        Value::String(claim) => Some(claim.clone()),
+        Value::Number(claim) => Some(claim.to_string()),
+        _ => None,
+    }
+}
+
+/// Replaces whatever tenant header the client sent with the claim from its token, so only a
+/// tenant the gateway resolved itself reaches the handlers, templates and orchestrator.
+pub async fn resolve_tenant(
+    State(config): State<Arc<TenantClaimConfig>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let headers = request.headers_mut();
+    headers.remove(config.header.as_str());
+    match extract_claim(headers, &config) {
+        Some(tenant_id) => {
+            tracing::Span::current().record("tenant_id", tenant_id.as_str());
+            if let (Ok(name), Ok(value)) = (
+                HeaderName::from_bytes(config.header.as_bytes()),
+                tenant_id.parse(),
+            ) {
+                headers.insert(name, value);
+            }
+        }
+        None => tracing::debug!("No tenant claim found in request"),
+    }
+    next.run(request).await
+}
+
+fn verify_hs256(header: &str, payload: &str, signature: &str, secret: &str) -> bool {
+    let alg = URL_SAFE_NO_PAD
+        .decode(header)
+        .ok()
+        .and_then(|header| serde_json::from_slice::<Value>(&header).ok())
+        .and_then(|header| header["alg"].as_str().map(str::to_string));
+    if alg.as_deref() != Some("HS256") {
+        return false;
+    }
+    let Ok(signature) = URL_SAFE_NO_PAD.decode(signature) else {
+        return false;
+    };
+    let expected = PKey::hmac(secret.as_bytes()).and_then(|key| {
+        let mut signer = Signer::new(MessageDigest::sha256(), &key)?;
+        signer.update(format!("{}.{}", header, payload).as_bytes())?;
+        signer.sign_to_vec()
+    });
+    match expected {
+        Ok(expected) => {
+            expected.len() == signature.len() && openssl::memcmp::eq(&expected, &signature)
+        }
+        Err(_) => false,
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+    use serde_json::json;
+
+    pub(crate) fn token(claims: Value, secret: &str) -> String {
+        let header = URL_SAFE_NO_PAD.encode(json!({"alg": "HS256", "typ": "JWT"}).to_string());
+        let payload = URL_SAFE_NO_PAD.encode(claims.to_string());
+        let key = PKey::hmac(secret.as_bytes()).unwrap();
+        let mut signer = Signer::new(MessageDigest::sha256(), &key).unwrap();
+        signer
+            .update(format!("{}.{}", header, payload).as_bytes())
+            .unwrap();
+        let signature = URL_SAFE_NO_PAD.encode(signer.sign_to_vec().unwrap());
+        format!("{}.{}.{}", header, payload, signature)
+    }
+
+    fn headers(authorization: &str) -> HeaderMap {
+        HeaderMap::from_iter([(
+            header::AUTHORIZATION,
+            HeaderValue::from_str(authorization).unwrap(),
+        )])
+    }
+
+    fn config(claim: &str, hs256_secret: Option<&str>) -> TenantClaimConfig {
+        TenantClaimConfig {
+            claim: claim.to_string(),
+            header: "x-tenant-id".to_string(),
+            hs256_secret: hs256_secret.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn test_extract_claim() {
+        let token = token(json!({"org": {"tenant": "acme"}, "tid": 7}), "secret");
+        let headers = headers(&format!("Bearer {}", token));
+
+        assert_eq!(
+            extract_claim(&headers, &config("org.tenant", None)).as_deref(),
+            Some("acme")
+        );
+        assert_eq!(
+            extract_claim(&headers, &config("tid", None)).as_deref(),
+            Some("7")
+        );
+        assert_eq!(extract_claim(&headers, &config("missing", None)), None);
+        assert_eq!(extract_claim(&headers, &config("org", None)), None);
+    }
+
+    #[test]
+    fn test_extract_claim_invalid_tokens() {
+        let config = config("tid", None);
+        assert_eq!(extract_claim(&HeaderMap::new(), &config), None);
+        assert_eq!(extract_claim(&headers("Basic dXNlcg=="), &config), None);
+        assert_eq!(extract_claim(&headers("Bearer opaque"), &config), None);
+        assert_eq!(extract_claim(&headers("Bearer a.%%%.c"), &config), None);
+    }
+
+    #[test]
+    fn test_extract_claim_verifies_hs256() {
+        let headers = headers(&format!(
+            "Bearer {}",
+            token(json!({"tid": "acme"}), "secret")
+        ));
+        assert_eq!(
+            extract_claim(&headers, &config("tid", Some("secret"))).as_deref(),
+            Some("acme")
+        );
+        assert_eq!(extract_claim(&headers, &config("tid", Some("other"))), None);
+    }
+}
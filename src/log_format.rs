@@ -0,0 +1,260 @@
+use std::fmt;
+
+use serde_json::{Map, Value};
+use tracing::field::{Field, Visit};
+use tracing::{Event, Subscriber};
+use tracing_subscriber::field::RecordFields;
+use tracing_subscriber::filter::Targets;
+use tracing_subscriber::fmt::format::Writer;
+use tracing_subscriber::fmt::time::{FormatTime, SystemTime};
+use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields, FormattedFields};
+use tracing_subscriber::registry::LookupSpan;
+
+/// Output format of the gateway's logs, chosen with the `LOG_FORMAT` environment variable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    Compact,
+    Json,
+}
+
+impl LogFormat {
+    /// Reads the `LOG_FORMAT` value, defaulting to `compact` when it is unset.
+    pub fn from_env(value: Option<String>) -> Result<Self, String> {
+        match value.as_deref() {
+            None | Some("compact") => Ok(LogFormat::Compact),
+            Some("json") => Ok(LogFormat::Json),
+            Some(other) => Err(format!(
+                "LOG_FORMAT must be 'compact' or 'json', got '{}'",
+                other
+            )),
+        }
+    }
+}
+
+/// Reads the `RUST_LOG` value as a list of `target=level` directives, e.g.
+/// `info,vllm_orchestrator_gateway::coalesce=debug`, defaulting to `info` when it is unset.
+/// Disabled events are skipped before their fields are formatted, so debug logging of payloads
+/// costs nothing unless it is turned on.
+pub fn log_filter(value: Option<String>) -> Result<Targets, String> {
+    let directives = value.unwrap_or_else(|| "info".to_string());
+    directives
+        .parse()
+        .map_err(|e| format!("invalid RUST_LOG '{}': {}", directives, e))
+}
+
+/// Writes each event as a single JSON object with `timestamp`, `level` and `message` keys. The
+/// fields of the event and of every span it is in, such as `route` and `request_id` on the
+/// request span, are added as top level keys; an event's own fields win over its spans'.
+pub struct JsonFormat;
+
+impl<S, N> FormatEvent<S, N> for JsonFormat
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        let mut timestamp = String::new();
+        SystemTime.format_time(&mut Writer::new(&mut timestamp))?;
+        let mut line = Map::new();
+        line.insert("timestamp".to_string(), Value::String(timestamp));
+        line.insert(
+            "level".to_string(),
+            Value::String(event.metadata().level().to_string()),
+        );
+        if let Some(scope) = ctx.event_scope() {
+            for span in scope.from_root() {
+                let extensions = span.extensions();
+                // Span fields are stored as JSON by `JsonFields`
+                if let Some(Ok(Value::Object(fields))) = extensions
+                    .get::<FormattedFields<N>>()
+                    .map(|fields| serde_json::from_str(fields))
+                {
+                    line.extend(fields);
+                }
+            }
+        }
+        event.record(&mut JsonVisitor(&mut line));
+        writeln!(writer, "{}", Value::Object(line))
+    }
+}
+
+/// Stores span fields as a JSON object, so `JsonFormat` can add them to each event.
+pub struct JsonFields;
+
+impl<'writer> FormatFields<'writer> for JsonFields {
+    fn format_fields<R: RecordFields>(
+        &self,
+        mut writer: Writer<'writer>,
+        fields: R,
+    ) -> fmt::Result {
+        let mut map = Map::new();
+        fields.record(&mut JsonVisitor(&mut map));
+        write!(writer, "{}", Value::Object(map))
+    }
+
+    /// Fields recorded after the span was created are merged into its JSON object.
+    fn add_fields(
+        &self,
+        current: &'writer mut FormattedFields<Self>,
+        fields: &tracing::span::Record<'_>,
+    ) -> fmt::Result {
+        let mut map = match serde_json::from_str(&current.fields) {
+            Ok(Value::Object(map)) => map,
+            _ => Map::new(),
+        };
+        fields.record(&mut JsonVisitor(&mut map));
+        current.fields = Value::Object(map).to_string();
+        Ok(())
+    }
+}
+
+struct JsonVisitor<'a>(&'a mut Map<String, Value>);
+
+impl Visit for JsonVisitor<'_> {
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0
+            .insert(field.name().to_string(), format!("{:?}", value).into());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{Arc, Mutex};
+    use tracing_subscriber::filter::LevelFilter;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_json_lines_carry_span_fields() {
+        let buffer = Buffer::default();
+        let writer = buffer.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .fmt_fields(JsonFields)
+            .event_format(JsonFormat)
+            .with_writer(move || writer.clone())
+            .finish();
+
+        tracing::subscriber::with_default(subscriber, || {
+            let span = tracing::info_span!(
+                "request",
+                method = "POST",
+                route = tracing::field::Empty,
+                request_id = tracing::field::Empty,
+            );
+            let _entered = span.enter();
+            span.record("route", "pii");
+            span.record("request_id", "req-1");
+            tracing::info!(streaming = true, score = 0.5, "request completed");
+        });
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let line: Value = serde_json::from_str(output.trim_end()).unwrap();
+        assert_eq!(line["level"], "INFO");
+        assert_eq!(line["message"], "request completed");
+        assert_eq!(line["method"], "POST");
+        assert_eq!(line["route"], "pii");
+        assert_eq!(line["request_id"], "req-1");
+        assert_eq!(line["streaming"], true);
+        assert_eq!(line["score"], 0.5);
+        assert!(line["timestamp"].is_string(), "{}", output);
+    }
+
+    #[test]
+    fn test_log_filter_is_respected_per_target() {
+        struct Expensive<'a>(&'a AtomicBool);
+        impl fmt::Debug for Expensive<'_> {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                self.0.store(true, Ordering::SeqCst);
+                f.write_str("expensive")
+            }
+        }
+
+        let buffer = Buffer::default();
+        let writer = buffer.clone();
+        let filter = log_filter(Some(
+            "warn,vllm_orchestrator_gateway::log_format=debug".to_string(),
+        ))
+        .unwrap();
+        let subscriber = tracing_subscriber::fmt()
+            .with_max_level(LevelFilter::TRACE)
+            .with_writer(move || writer.clone())
+            .finish()
+            .with(filter);
+
+        let formatted = AtomicBool::new(false);
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::debug!("enabled for this module");
+            tracing::info!(target: "vllm_orchestrator_gateway::coalesce", "below warn");
+            tracing::debug!(
+                target: "vllm_orchestrator_gateway::coalesce",
+                "payload: {:?}",
+                Expensive(&formatted)
+            );
+            tracing::warn!(target: "vllm_orchestrator_gateway::coalesce", "at warn");
+        });
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        assert!(output.contains("enabled for this module"), "{}", output);
+        assert!(output.contains("at warn"), "{}", output);
+        assert!(!output.contains("below warn"), "{}", output);
+        assert!(!formatted.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn test_log_filter_defaults_to_info() {
+        let filter = log_filter(None).unwrap();
+        assert!(filter.would_enable("vllm_orchestrator_gateway", &tracing::Level::INFO));
+        assert!(!filter.would_enable("vllm_orchestrator_gateway", &tracing::Level::DEBUG));
+        assert!(log_filter(Some("info,gateway=loud".to_string())).is_err());
+    }
+
+    #[test]
+    fn test_log_format_from_env() {
+        assert_eq!(LogFormat::from_env(None), Ok(LogFormat::Compact));
+        assert_eq!(
+            LogFormat::from_env(Some("json".to_string())),
+            Ok(LogFormat::Json)
+        );
+        assert!(LogFormat::from_env(Some("pretty".to_string())).is_err());
+    }
+}
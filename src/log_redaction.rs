@@ -0,0 +1,200 @@
+use std::fmt;
+
+use axum::http::{HeaderName, HeaderValue};
+use serde::Serialize;
+use serde_json::Value;
+
+const REDACTED: &str = "[redacted]";
+
+/// Fields holding prompt or generated text: message and delta `content`, and the `text` of
+/// content parts and detection results.
+const CONTENT_FIELDS: [&str; 2] = ["content", "text"];
+
+/// Replaces every non-null content field in `value`, at any depth, with `[redacted]`. Everything
+/// else, such as the model, roles, finish reasons, detector ids and scores, is left as is.
+pub fn redact_content(value: &mut Value) {
+    match value {
+        Value::Object(fields) => {
+            for (key, field) in fields.iter_mut() {
+                if CONTENT_FIELDS.contains(&key.as_str()) && !field.is_null() {
+                    *field = Value::String(REDACTED.to_string());
+                } else {
+                    redact_content(field);
+                }
+            }
+        }
+        Value::Array(values) => values.iter_mut().for_each(redact_content),
+        _ => {}
+    }
+}
+
+/// A request or response payload as it appears in debug logs: JSON with its content redacted
+/// when `redact_logs` is set. The payload is only copied and scrubbed when the line is written.
+pub struct LoggedPayload<'a, T: ?Sized> {
+    payload: &'a T,
+    redact: bool,
+}
+
+pub fn logged<T: Serialize + ?Sized>(payload: &T, redact: bool) -> LoggedPayload<'_, T> {
+    LoggedPayload { payload, redact }
+}
+
+impl<T: Serialize + ?Sized> fmt::Debug for LoggedPayload<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut value = serde_json::to_value(self.payload).map_err(|_| fmt::Error)?;
+        if self.redact {
+            redact_content(&mut value);
+        }
+        write!(f, "{}", value)
+    }
+}
+
+/// A raw orchestrator response body as it appears in debug logs. With `redact_logs` set, a JSON
+/// body is logged with its content redacted and anything else only by its length.
+pub struct LoggedBody<'a> {
+    body: &'a str,
+    redact: bool,
+}
+
+pub fn logged_body(body: &str, redact: bool) -> LoggedBody<'_> {
+    LoggedBody { body, redact }
+}
+
+impl fmt::Display for LoggedBody<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if !self.redact {
+            return f.write_str(self.body);
+        }
+        match serde_json::from_str::<Value>(self.body) {
+            Ok(mut value) => {
+                redact_content(&mut value);
+                write!(f, "{}", value)
+            }
+            Err(_) => write!(f, "[{} bytes redacted]", self.body.len()),
+        }
+    }
+}
+
+/// Headers carrying credentials. Their values are left out of the logs whether or not
+/// `redact_logs` is set, as they are never needed to debug a request.
+const CREDENTIAL_HEADERS: [&str; 5] = [
+    "authorization",
+    "proxy-authorization",
+    "cookie",
+    "set-cookie",
+    "x-api-key",
+];
+
+/// A request header value as it appears in debug logs, with credentials replaced by `[redacted]`.
+pub struct LoggedHeader<'a> {
+    name: &'a HeaderName,
+    value: &'a HeaderValue,
+}
+
+pub fn logged_header<'a>(name: &'a HeaderName, value: &'a HeaderValue) -> LoggedHeader<'a> {
+    LoggedHeader { name, value }
+}
+
+impl fmt::Debug for LoggedHeader<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if CREDENTIAL_HEADERS.contains(&self.name.as_str()) {
+            return f.write_str(REDACTED);
+        }
+        write!(f, "{:?}", self.value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_redact_content() {
+        let mut payload = json!({
+            "model": "granite",
+            "messages": [
+                {"role": "system", "content": "be brief"},
+                {"role": "user", "content": [{"type": "text", "text": "my email is a@b.com"}]},
+                {"role": "assistant", "content": null, "tool_calls": []},
+            ],
+            "choices": [
+                {"index": 0, "message": {"role": "assistant", "content": "hello"}, "finish_reason": "stop"},
+                {"index": 1, "delta": {"content": "hi"}, "finish_reason": null},
+            ],
+            "detections": {"output": [{"choice_index": 0, "results": [
+                {"detector_id": "regex", "text": "a@b.com", "score": 0.9, "start": 12, "end": 19}
+            ]}]},
+            "detectors": {"input": {"regex": {"regex": ["email"]}}},
+        });
+
+        redact_content(&mut payload);
+
+        assert_eq!(
+            payload,
+            json!({
+                "model": "granite",
+                "messages": [
+                    {"role": "system", "content": "[redacted]"},
+                    {"role": "user", "content": "[redacted]"},
+                    {"role": "assistant", "content": null, "tool_calls": []},
+                ],
+                "choices": [
+                    {"index": 0, "message": {"role": "assistant", "content": "[redacted]"}, "finish_reason": "stop"},
+                    {"index": 1, "delta": {"content": "[redacted]"}, "finish_reason": null},
+                ],
+                "detections": {"output": [{"choice_index": 0, "results": [
+                    {"detector_id": "regex", "text": "[redacted]", "score": 0.9, "start": 12, "end": 19}
+                ]}]},
+                "detectors": {"input": {"regex": {"regex": ["email"]}}},
+            })
+        );
+    }
+
+    #[test]
+    fn test_logged_payload() {
+        let payload = json!({"model": "m", "messages": [{"role": "user", "content": "secret"}]});
+        assert_eq!(
+            format!("{:?}", logged(&payload, true)),
+            r#"{"messages":[{"content":"[redacted]","role":"user"}],"model":"m"}"#
+        );
+        assert_eq!(
+            format!("{:?}", logged(&payload, false)),
+            r#"{"messages":[{"content":"secret","role":"user"}],"model":"m"}"#
+        );
+        // Payloads that are still borrowed mutably by the handler log the same way
+        let mut map = payload.as_object().cloned().unwrap();
+        let borrowed = Some(&mut map);
+        assert!(!format!("{:?}", logged(&borrowed, true)).contains("secret"));
+    }
+
+    #[test]
+    fn test_logged_body() {
+        let body = r#"{"choices":[{"message":{"content":"secret"}}]}"#;
+        assert_eq!(
+            logged_body(body, true).to_string(),
+            r#"{"choices":[{"message":{"content":"[redacted]"}}]}"#
+        );
+        assert_eq!(logged_body(body, false).to_string(), body);
+        assert_eq!(
+            logged_body("secret, not json", true).to_string(),
+            "[16 bytes redacted]"
+        );
+    }
+
+    #[test]
+    fn test_logged_header() {
+        let value = HeaderValue::from_static("Bearer secret");
+        for name in [header_name("authorization"), header_name("x-api-key")] {
+            assert_eq!(format!("{:?}", logged_header(&name, &value)), "[redacted]");
+        }
+        assert_eq!(
+            format!("{:?}", logged_header(&header_name("x-request-id"), &value)),
+            r#""Bearer secret""#
+        );
+    }
+
+    fn header_name(name: &'static str) -> HeaderName {
+        HeaderName::from_static(name)
+    }
+}
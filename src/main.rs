@@ -1,50 +1,333 @@
-use axum::http::HeaderMap;
+use axum::extract::rejection::JsonRejection;
+use axum::extract::Query;
+use axum::http::{header, HeaderMap, HeaderName, HeaderValue, Method};
+use axum::response::sse::{Event, KeepAlive};
 use axum::{
     http::StatusCode,
-    response::{IntoResponse, Sse, Response},
-    routing::post,
-    Json, Router
+    response::{IntoResponse, Response, Sse},
+    routing::{get, post},
+    Json, Router,
 };
-use axum::response::sse::{Event, KeepAlive};
-use config::{validate_registered_detectors, DetectorConfig, GatewayConfig};
-use futures::StreamExt;
+use config::{
+    BoundsConfig, DetectionAction, DetectorConfig, DetectorsMergePolicy, GatewayConfig,
+    GroupAction, MalformedFramePolicy, ParamBoundsConfig, SkipDetectionConfig,
+    StreamingDetectionMode,
+};
+use futures::stream::BoxStream;
+use futures::{FutureExt, StreamExt};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
 use serde_json::json;
 use serde_json::{Map, Value};
 use std::error::Error;
-use std::sync::Arc;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use std::{
-    collections::HashMap,
+    collections::{BTreeSet, HashMap},
     env,
-    net::{IpAddr, SocketAddr},
 };
 use tower_http::trace::{self, TraceLayer};
 use tracing::Level;
-use anyhow::Context;
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
 
 mod api;
+mod auth;
+mod balancer;
+mod capture;
+mod coalesce;
 mod config;
+mod config_schema;
+mod error;
+mod inbound_timeout;
+mod jwt;
+mod log_format;
+mod log_redaction;
+mod metrics;
+mod reload;
+mod request_log;
+mod retry_budget;
+mod sse;
+mod templates;
+mod tls;
+mod trace_context;
+mod trailers;
+mod watchdog;
+
+/// Payload key under which gateway context is forwarded to the orchestrator, namespaced so it
+/// cannot collide with real completion parameters.
+const GATEWAY_METADATA_KEY: &str = "gateway_metadata";
+
+use error::{ApiError, OrchestratorError};
+use log_redaction::{logged, logged_body, logged_header};
+use request_log::{Decision, Outcome, RequestLog};
 
 use api::{
-    Detections, GenerationChoice, GenerationMessage, OrchestratorDetector, OrchestratorResponse,
-    StreamingResponse, StreamingDelta,
+    ChatDetectionResponse, ChatDetectionResult, CompletionResponse, DetectionSummary, Detections,
+    GenerationChoice, GenerationMessage, OrchestratorDetector, OrchestratorResponse,
+    StreamingChoice, StreamingDelta, StreamingResponse, Verdict,
 };
 
-fn get_orchestrator_detectors(
+/// The final event of every SSE response, which OpenAI compatible clients rely on to tell a
+/// finished stream from a dropped one.
+fn done_event() -> Event {
+    Event::default().data("[DONE]")
+}
+
+/// Ends a stream that failed part way with the error envelope as its last event, as the status
+/// has already been sent. The `error` event type is what OpenAI SDKs raise on.
+fn error_event(error: ApiError) -> Event {
+    Event::default()
+        .event("error")
+        .data(error.body().to_string())
+}
+
+/// The choices of a stream that have started but not finished, so a stream failing part way
+/// can finish them before its error event.
+#[derive(Debug, Default)]
+struct OpenChoices {
+    /// `id`, `created` and `model` of the last chunk
+    chunk: Option<(String, u64, String)>,
+    indexes: BTreeSet<u32>,
+}
+
+impl OpenChoices {
+    fn track(&mut self, chunk: &StreamingResponse) {
+        self.chunk = Some((chunk.id.clone(), chunk.created, chunk.model.clone()));
+        for choice in &chunk.choices {
+            if choice.finish_reason.is_some() {
+                self.indexes.remove(&choice.index);
+            } else {
+                self.indexes.insert(choice.index);
+            }
+        }
+    }
+
+    /// The events ending a stream on `error`: a chunk giving every open choice an `error`
+    /// finish reason, if any are open, then the error itself.
+    fn fail(&mut self, error: ApiError) -> Vec<Event> {
+        let mut events = Vec::new();
+        let open = self.chunk.take().filter(|_| !self.indexes.is_empty());
+        if let Some((id, created, model)) = open {
+            let chunk = StreamingResponse {
+                id,
+                object: "chat.completion.chunk".to_string(),
+                created,
+                model,
+                choices: std::mem::take(&mut self.indexes)
+                    .into_iter()
+                    .map(|index| StreamingChoice {
+                        index,
+                        delta: StreamingDelta {
+                            content: None,
+                            role: None,
+                            tool_calls: None,
+                        },
+                        logprobs: None,
+                        finish_reason: Some("error".to_string()),
+                        stop_reason: None,
+                    })
+                    .collect(),
+                usage: None,
+                detections: None,
+                warnings: None,
+            };
+            match Event::default().json_data(chunk) {
+                Ok(event) => events.push(event),
+                Err(e) => tracing::error!("Failed to serialize final chunk: {}", e),
+            }
+        }
+        events.push(error_event(error));
+        events
+    }
+}
+
+const SERVER_TIMING: &str = "server-timing";
+
+/// The OpenAI APIs a route generates through, which carry their content in different fields.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum CompletionsApi {
+    /// `/v1/chat/completions`, with the conversation in `messages`.
+    Chat,
+    /// The legacy `/v1/completions`, with the text in `prompt`.
+    Text,
+}
+
+impl CompletionsApi {
+    fn content_field(self) -> &'static str {
+        match self {
+            CompletionsApi::Chat => "messages",
+            CompletionsApi::Text => "prompt",
+        }
+    }
+
+    /// What an array found at a content pointer is taken to hold, for error messages.
+    fn content_field_kind(self) -> &'static str {
+        match self {
+            CompletionsApi::Chat => "message",
+            CompletionsApi::Text => "prompt",
+        }
+    }
+}
+
+/// Header trusted clients can use to run a subset of a route's detectors.
+const DETECTOR_SELECTION_HEADER: &str = "x-detectors";
+
+/// Header with the number of results from each detector, on routes with `expose_detections`.
+const DETECTIONS_HEADER: &str = "x-detections";
+
+/// Per-route state computed once at startup and shared by every request to that route.
+#[derive(Debug, Clone)]
+struct RouteContext {
+    name: String,
     detectors: Vec<String>,
-    detector_config: Vec<DetectorConfig>,
+    orchestrator_detectors: Value,
+    streaming_orchestrator_detectors: Value,
+    gateway_metadata: Option<Value>,
+    fallback_message: Option<String>,
+    fallback_messages: HashMap<String, String>,
+    buffered_streaming: bool,
+    buffer_upstream_stream: bool,
+    detect_last_message_only: bool,
+    streaming_detection_mode: Option<StreamingDetectionMode>,
+    allow_detector_selection: bool,
+    verdict_threshold: f64,
+    action: DetectionAction,
+    truncation_notice: Option<String>,
+    /// The orchestrator's chat completions path for this route, resolved once at startup
+    completions_path: String,
+    skip_detection_if: Option<SkipDetectionConfig>,
+    in_flight: Option<Arc<coalesce::Coalescer>>,
+    param_bounds: Option<ParamBoundsConfig>,
+    return_detection_summary: bool,
+    /// Group action per orchestrator detector id; detectors outside any group block
+    detector_actions: HashMap<String, GroupAction>,
+    /// Minimum score per orchestrator detector id for a result to count
+    detector_thresholds: HashMap<String, f64>,
+    detectors_merge_policy: DetectorsMergePolicy,
+    detections_trailer: bool,
+    content_pointer: Option<String>,
+    /// Shared by every route, so retries are limited across the whole gateway
+    retry_budget: Option<Arc<retry_budget::RetryBudget>>,
+    /// Limit on a non-streaming orchestrator request, or on the wait for each streamed chunk
+    timeout: Option<Duration>,
+    allowed_models: Option<Vec<String>>,
+    system_prompt: Option<String>,
+    max_tokens_cap: Option<u32>,
+    expose_detections: bool,
+    detection_only: bool,
+}
+
+/// Gateway state that outlives any one config. It is built once at startup and shared by every
+/// router, so a config reload neither refills the retry budget nor loses the watchdog's history.
+#[derive(Clone, Default)]
+struct ProcessState {
+    watchdog: Option<Arc<watchdog::Watchdog>>,
+    retry_budget: Option<Arc<retry_budget::RetryBudget>>,
+}
+
+impl ProcessState {
+    fn new(gateway_config: &GatewayConfig) -> Self {
+        ProcessState {
+            watchdog: gateway_config
+                .watchdog
+                .as_ref()
+                .map(watchdog::Watchdog::spawn),
+            retry_budget: gateway_config
+                .retry_budget
+                .as_ref()
+                .map(|config| Arc::new(retry_budget::RetryBudget::new(config))),
+        }
+    }
+}
+
+impl RouteContext {
+    /// The message to send in place of a response if anything was detected: the one set for the
+    /// detection type of the highest scoring result, or else the route's `fallback_message`.
+    fn fallback_for(&self, detections: Option<&Detections>) -> Option<&str> {
+        let detections = detections.filter(|d| d.has_hits())?;
+        detections
+            .top_detection_type()
+            .and_then(|detection_type| self.fallback_messages.get(detection_type))
+            .or(self.fallback_message.as_ref())
+            .map(String::as_str)
+    }
+
+    /// Returns a copy of this route running only `detectors`.
+    fn with_detectors(&self, detectors: Vec<String>, detector_config: &[DetectorConfig]) -> Self {
+        let orchestrator_detectors = get_orchestrator_detectors(&detectors, detector_config, None);
+        let streaming_orchestrator_detectors =
+            get_orchestrator_detectors(&detectors, detector_config, self.streaming_detection_mode);
+        RouteContext {
+            detectors,
+            orchestrator_detectors: serde_json::to_value(orchestrator_detectors).unwrap(),
+            streaming_orchestrator_detectors: serde_json::to_value(
+                streaming_orchestrator_detectors,
+            )
+            .unwrap(),
+            ..self.clone()
+        }
+    }
+}
+
+/// Intersects a comma separated list of requested detector names with the route's detectors.
+/// The selection can only narrow the route's detectors; unknown names are ignored.
+fn select_detectors(route_detectors: &[String], requested: &str) -> Vec<String> {
+    let requested: Vec<&str> = requested
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .collect();
+    for name in &requested {
+        if !route_detectors.iter().any(|detector| detector == name) {
+            tracing::warn!(
+                "Ignoring requested detector '{}' not configured on route",
+                name
+            );
+        }
+    }
+    route_detectors
+        .iter()
+        .filter(|detector| requested.contains(&detector.as_str()))
+        .cloned()
+        .collect()
+}
+
+/// The orchestrator `detectors` object for a route's `detectors`, keyed by each detector's
+/// `server`, or its name when it has none. Only the params of the route's own detectors are
+/// copied and nothing else is read, so routes can build it once at startup and tests can check
+/// it directly. It is only rebuilt per request when a client selects a subset of the route's
+/// detectors.
+fn get_orchestrator_detectors(
+    detectors: &[String],
+    detector_config: &[DetectorConfig],
+    streaming_detection_mode: Option<StreamingDetectionMode>,
 ) -> OrchestratorDetector {
     let mut input_detectors = HashMap::new();
     let mut output_detectors = HashMap::new();
 
     for detector in detector_config {
-        if detectors.contains(&detector.name) && detector.detector_params.is_some() {
-            let detector_params = detector.detector_params.unwrap();
-            let key = detector.server.clone().unwrap_or_else(|| detector.name.clone());
+        if !detectors.contains(&detector.name) {
+            continue;
+        }
+        if let Some(detector_params) = &detector.detector_params {
+            let key = detector.server.as_ref().unwrap_or(&detector.name);
             if detector.input {
                 input_detectors.insert(key.clone(), detector_params.clone());
             }
             if detector.output {
-                output_detectors.insert(key, detector_params);
+                let mut detector_params = detector_params.clone();
+                if let (Some(mode), Some(params)) =
+                    (streaming_detection_mode, detector_params.as_object_mut())
+                {
+                    params.insert(
+                        "streaming_detection_mode".to_string(),
+                        serde_json::to_value(mode).unwrap(),
+                    );
+                }
+                output_detectors.insert(key.clone(), detector_params);
             }
         }
     }
@@ -55,112 +338,980 @@ fn get_orchestrator_detectors(
     }
 }
 
+/// What the gateway was asked to do on the command line.
+#[derive(Debug, PartialEq)]
+enum Command {
+    /// Serve requests, configured from the environment
+    Serve,
+    /// Check a config file and exit
+    Validate { config_path: String },
+    /// Print the JSON Schema of the config file and exit
+    PrintConfigSchema,
+}
+
+const USAGE: &str =
+    "usage: vllm-orchestrator-gateway [--validate <config file> | --print-config-schema]";
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Command, String> {
+    let command = match args.next().as_deref() {
+        None => Command::Serve,
+        Some("--validate") => match args.next() {
+            Some(config_path) => Command::Validate { config_path },
+            None => return Err("--validate requires a config file".to_string()),
+        },
+        Some("--print-config-schema") => Command::PrintConfigSchema,
+        Some(other) => return Err(format!("unexpected argument '{}'", other)),
+    };
+    match args.next() {
+        Some(extra) => Err(format!("unexpected argument '{}'", extra)),
+        None => Ok(command),
+    }
+}
+
+/// Reads the config at `config_path` and checks it, describing every problem found.
+fn load_config(config_path: &str) -> Result<GatewayConfig, String> {
+    let gateway_config = config::read_config(config_path).map_err(|e| e.to_string())?;
+    config::validate_config(&gateway_config)?;
+    Ok(gateway_config)
+}
+
+/// Checks a config file without starting the server, for CI, returning the exit status.
+fn validate(config_path: &str) -> i32 {
+    match load_config(config_path) {
+        Ok(_) => {
+            println!("{} is valid", config_path);
+            0
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            1
+        }
+    }
+}
+
 #[tokio::main]
 async fn main() {
-    let config_path = env::var("GATEWAY_CONFIG").unwrap_or("config/config.yaml".to_string());
+    match parse_args(env::args().skip(1)) {
+        Ok(Command::Serve) => {}
+        Ok(Command::Validate { config_path }) => std::process::exit(validate(&config_path)),
+        Ok(Command::PrintConfigSchema) => {
+            let schema = config_schema::config_schema();
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&schema).expect("schema is valid JSON")
+            );
+            return;
+        }
+        Err(e) => {
+            eprintln!("{}\n{}", e, USAGE);
+            std::process::exit(2);
+        }
+    }
+
+    let config_dir = env::var("GATEWAY_CONFIG_DIR").unwrap_or("config".to_string());
+    let config_path = config::resolve_config_path(
+        env::var("GATEWAY_CONFIG").ok(),
+        env::var("GATEWAY_ENV").ok(),
+        &config_dir,
+    )
+    .unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
     tracing::debug!("Using config path: {}", config_path);
-    let gateway_config = config::read_config(&config_path);
+    let gateway_config = Arc::new(config::read_config(&config_path).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }));
     tracing::debug!("Loaded gateway config: {:?}", gateway_config);
-    validate_registered_detectors(&gateway_config);
-    tracing::debug!("Validated registered detectors");
-
-    tracing_subscriber::fmt()
-        .with_max_level(tracing::Level::DEBUG)
-        .with_target(false)
-        .compact()
-        .init();
-
-    let (client, scheme) =
-        build_orchestrator_client(&gateway_config.orchestrator.host)
-            .expect("Failed to build HTTP(s) client for communicating with orchestrator");
+    if let Err(e) = config::validate_config(&gateway_config) {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+    tracing::debug!("Validated gateway config");
+
+    let log_format =
+        log_format::LogFormat::from_env(env::var("LOG_FORMAT").ok()).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        });
+    let log_filter = log_format::log_filter(env::var("RUST_LOG").ok()).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    // Levels are left entirely to the filter
+    let logs = tracing_subscriber::fmt()
+        .with_max_level(LevelFilter::TRACE)
+        .with_target(false);
+    match log_format {
+        log_format::LogFormat::Compact => logs.compact().finish().with(log_filter).init(),
+        log_format::LogFormat::Json => logs
+            .fmt_fields(log_format::JsonFields)
+            .event_format(log_format::JsonFormat)
+            .finish()
+            .with(log_filter)
+            .init(),
+    }
+
+    let (client, scheme) = build_orchestrator_client(&gateway_config.orchestrator)
+        .expect("Failed to build HTTP(s) client for communicating with orchestrator");
     let orchestrator_client = Arc::new(client);
 
-    let mut app = Router::new().layer(
-        TraceLayer::new_for_http()
-            .make_span_with(trace::DefaultMakeSpan::new().level(Level::INFO))
-            .on_response(trace::DefaultOnResponse::new().level(Level::INFO)),
+    if let Some(capture) = &gateway_config.capture {
+        tracing::warn!(
+            "Request capture is enabled: {}% of non-streaming requests and responses will be \
+             written to {}{}",
+            capture.sampling_rate * 100.0,
+            capture.capture_dir,
+            if capture.redact_content {
+                " with message content redacted"
+            } else {
+                ", including user content"
+            }
+        );
+    }
+
+    let state = ProcessState::new(&gateway_config);
+    let router = build_router(gateway_config.clone(), orchestrator_client, scheme, &state)
+        .unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        });
+    let router = reload::ReloadableRouter::new(router);
+    tokio::spawn(reload_on_sighup(
+        config_path,
+        router.clone(),
+        state,
+        Duration::from_millis(gateway_config.reload_debounce_ms),
+    ));
+    let app = router.into_router();
+
+    let addr = config::resolve_bind_address(
+        &gateway_config.server,
+        env::var("HOST").ok(),
+        env::var("HTTP_PORT").ok(),
+    )
+    .unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    tracing::debug!("Binding to address: {}", addr);
+
+    let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
+
+    let acceptor = gateway_config
+        .listener_tls
+        .as_ref()
+        .map(|tls_config| tls::acceptor(tls_config).expect("Failed to load listener TLS config"));
+    match acceptor {
+        Some(_) => tracing::info!("listening on {} (https)", addr),
+        None => tracing::info!("listening on {}", addr),
+    }
+    serve(
+        listener,
+        app,
+        acceptor,
+        shutdown_signal(),
+        Duration::from_secs(gateway_config.shutdown_grace_period_seconds),
+    )
+    .await;
+}
+
+/// Serves `app` until `shutdown` resolves, then stops accepting connections and gives requests
+/// in flight, including streams, up to `grace` to complete. Whatever is still open after that is
+/// dropped when the process exits.
+async fn serve(
+    listener: tokio::net::TcpListener,
+    app: Router,
+    acceptor: Option<tokio_native_tls::TlsAcceptor>,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+    grace: Duration,
+) {
+    let shutdown = shutdown.shared();
+    let server = async {
+        match acceptor {
+            Some(acceptor) => tls::serve(listener, app, acceptor, shutdown.clone()).await,
+            None => axum::serve(listener, app)
+                .with_graceful_shutdown(shutdown.clone())
+                .await
+                .unwrap(),
+        }
+    };
+    let deadline = async {
+        shutdown.clone().await;
+        tracing::info!(
+            "Stopped accepting connections, waiting up to {:?} for requests in flight",
+            grace
+        );
+        tokio::time::sleep(grace).await;
+    };
+    tokio::select! {
+        _ = server => tracing::info!("All connections closed, shutting down"),
+        _ = deadline => tracing::warn!("Shutdown grace period elapsed, closing open connections"),
+    }
+}
+
+/// Reloads the config each time the process receives SIGHUP, once per burst of signals no more
+/// than `debounce` apart. An invalid config is logged and the current one kept, so a bad edit
+/// never takes the gateway down.
+async fn reload_on_sighup(
+    config_path: String,
+    router: reload::ReloadableRouter,
+    state: ProcessState,
+    debounce: Duration,
+) {
+    let sighup = match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup()) {
+        Ok(sighup) => sighup,
+        Err(e) => {
+            tracing::error!(
+                "Failed to listen for SIGHUP, config reloads are disabled: {}",
+                e
+            );
+            return;
+        }
+    };
+    let signals = futures::stream::unfold(sighup, |mut sighup| async move {
+        sighup.recv().await.map(|()| ((), sighup))
+    });
+    reload::debounce(Box::pin(signals), debounce, || {
+        tracing::info!("Received SIGHUP, reloading config from {}", config_path);
+        match reload_config(&config_path, &router, &state) {
+            Ok(()) => tracing::info!("Reloaded config"),
+            Err(e) => tracing::error!("Config reload failed, keeping the current config: {}", e),
+        }
+    })
+    .await;
+}
+
+/// Reads and validates the config at `config_path` and, if it is valid and its routes can be
+/// built, serves new requests with them. The listener settings, `server` and `listener_tls`, the
+/// shutdown grace period, the reload debounce, `watchdog` and `retry_budget` are only read at
+/// startup.
+fn reload_config(
+    config_path: &str,
+    router: &reload::ReloadableRouter,
+    state: &ProcessState,
+) -> Result<(), String> {
+    router.reload(|| {
+        let gateway_config = load_config(config_path)?;
+        let (client, scheme) = build_orchestrator_client(&gateway_config.orchestrator)
+            .map_err(|e| format!("failed to build orchestrator client: {}", e))?;
+        tracing::debug!("Reloaded gateway config: {:?}", gateway_config);
+        build_router(Arc::new(gateway_config), Arc::new(client), scheme, state)
+    })
+}
+
+/// Resolves once the process is asked to stop with SIGTERM, as Kubernetes does, or SIGINT.
+async fn shutdown_signal() {
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                sigterm.recv().await;
+            }
+            Err(e) => {
+                tracing::error!("Failed to listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await
+            }
+        }
+    };
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => tracing::info!("Received SIGINT, shutting down"),
+        _ = terminate => tracing::info!("Received SIGTERM, shutting down"),
+    }
+}
+
+/// Body of the `/health` and `/ready` probes.
+fn probe_status(status: &str) -> Json<Value> {
+    Json(json!({ "status": status }))
+}
+
+#[derive(Debug, Deserialize)]
+struct RoutesQuery {
+    #[serde(default)]
+    include_params: bool,
+}
+
+/// Body of `GET /routes`: each enabled route with its detectors and whether it has any fallback
+/// message. Detector params can hold things like regexes and thresholds worth keeping private,
+/// so they are only listed when asked for with `?include_params=true`.
+fn route_listing(gateway_config: &GatewayConfig, include_params: bool) -> Value {
+    let routes = gateway_config
+        .routes
+        .iter()
+        .filter(|route| route.enabled)
+        .map(|route| {
+            let mut listed = json!({
+                "name": route.name,
+                "detectors": route.detectors,
+                "fallback": route.fallback_message.is_some() || !route.fallback_messages.is_empty(),
+            });
+            if include_params {
+                let params: Map<String, Value> = route
+                    .detectors
+                    .iter()
+                    .filter_map(|name| {
+                        let detector = gateway_config.detectors.iter().find(|d| &d.name == name)?;
+                        let params = detector.detector_params.clone().unwrap_or(Value::Null);
+                        Some((name.clone(), params))
+                    })
+                    .collect();
+                listed["detector_params"] = Value::Object(params);
+            }
+            listed
+        })
+        .collect();
+    Value::Array(routes)
+}
+
+/// Builds the gateway's routes from a validated config. This also runs on config reloads, so
+/// anything that can still go wrong is returned as an error rather than taking the reload task
+/// down.
+fn build_router(
+    gateway_config: Arc<GatewayConfig>,
+    orchestrator_client: Arc<reqwest::Client>,
+    scheme: String,
+    state: &ProcessState,
+) -> Result<Router, String> {
+    let mut app = Router::new();
+
+    let watchdog = state.watchdog.clone();
+    app = app.route(
+        "/health",
+        get(move || async move {
+            match watchdog {
+                Some(watchdog) if watchdog.is_stalled() => {
+                    (StatusCode::SERVICE_UNAVAILABLE, probe_status("stalled"))
+                }
+                _ => (StatusCode::OK, probe_status("ok")),
+            }
+        }),
+    );
+
+    app = app.route(
+        "/metrics",
+        get(|headers: HeaderMap| async move {
+            let format = metrics::Format::negotiate(
+                headers
+                    .get(header::ACCEPT)
+                    .and_then(|value| value.to_str().ok()),
+            );
+            (
+                [(header::CONTENT_TYPE, format.content_type())],
+                metrics::render(format),
+            )
+        }),
+    );
+
+    let orchestrators = Arc::new(balancer::Balancer::new(orchestrator_base_urls(
+        &gateway_config.orchestrator,
+        &scheme,
+    )));
+    let health_urls: Vec<String> = orchestrators
+        .base_urls()
+        .iter()
+        .map(|base_url| format!("{}{}", base_url, gateway_config.orchestrator.health_path))
+        .collect();
+    let ready_client = orchestrator_client.clone();
+    app = app.route(
+        "/ready",
+        get(move || async move {
+            // Ready while any replica is healthy, since requests fail over to it
+            let checks = health_urls
+                .iter()
+                .map(|health_url| orchestrator_ready(&ready_client, health_url));
+            if futures::future::join_all(checks).await.contains(&true) {
+                (StatusCode::OK, probe_status("ready"))
+            } else {
+                (
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    probe_status("orchestrator unavailable"),
+                )
+            }
+        }),
     );
 
+    let retry_budget = state.retry_budget.clone();
+
+    // Kept apart from the probes and metrics so API keys only guard the routes. Detector params
+    // are only handed out to callers that presented a key, so never without `api_keys`.
+    let listing = route_listing(&gateway_config, false);
+    let listing_with_params =
+        (!gateway_config.api_keys.is_empty()).then(|| route_listing(&gateway_config, true));
+    let mut routes = Router::new().route(
+        "/routes",
+        get(move |Query(query): Query<RoutesQuery>| async move {
+            if !query.include_params {
+                return Ok(Json(listing));
+            }
+            listing_with_params.map(Json).ok_or_else(|| {
+                ApiError::permission("include_params requires api_keys to be configured")
+                    .with_code("include_params_not_allowed")
+            })
+        }),
+    );
     for route in gateway_config.routes.iter() {
+        if !route.enabled {
+            tracing::info!("route '{}' disabled, skipping", route.name);
+            continue;
+        }
         let gateway_config = gateway_config.clone();
-        let detectors = route.detectors.clone();
-        let path = format!("/{}/v1/chat/completions", route.name);
-        let fallback_message = route.fallback_message.clone();
-        let orchestrator_client = orchestrator_client.clone();
-        let scheme = scheme.clone();
+        // The detectors for a route are fully determined by config, so build and serialize
+        // them once here rather than on every request
+        let orchestrator_detectors =
+            get_orchestrator_detectors(&route.detectors, &gateway_config.detectors, None);
+        tracing::debug!(
+            "Orchestrator detectors for route {}: {:?}",
+            route.name,
+            orchestrator_detectors
+        );
+        let orchestrator_detectors = serde_json::to_value(&orchestrator_detectors)
+            .map_err(|e| format!("failed to serialize orchestrator detectors: {}", e))?;
+        if route.buffer_upstream_stream && !route.buffered_streaming {
+            tracing::warn!(
+                "Route {} sets buffer_upstream_stream without buffered_streaming; it has no effect",
+                route.name
+            );
+        }
+        let streaming_orchestrator_detectors = serde_json::to_value(get_orchestrator_detectors(
+            &route.detectors,
+            &gateway_config.detectors,
+            route.streaming_detection_mode,
+        ))
+        .map_err(|e| format!("failed to serialize orchestrator detectors: {}", e))?;
+        let gateway_metadata = gateway_config.inject_gateway_metadata.then(|| {
+            json!({
+                "route": route.name,
+                "gateway_version": env!("CARGO_PKG_VERSION"),
+            })
+        });
+        let route_context = Arc::new(RouteContext {
+            name: route.name.clone(),
+            detectors: route.detectors.clone(),
+            orchestrator_detectors,
+            streaming_orchestrator_detectors,
+            gateway_metadata,
+            fallback_message: route.fallback_message.clone(),
+            fallback_messages: route.fallback_messages.clone(),
+            buffered_streaming: route.buffered_streaming,
+            buffer_upstream_stream: route.buffer_upstream_stream,
+            detect_last_message_only: route.detect_last_message_only,
+            streaming_detection_mode: route.streaming_detection_mode,
+            allow_detector_selection: route.allow_detector_selection,
+            verdict_threshold: route.verdict_threshold.unwrap_or(0.0),
+            action: route.action,
+            truncation_notice: route.truncation_notice.clone(),
+            completions_path: chat_completions_path(&gateway_config, route.api_path.as_deref())
+                .to_string(),
+            skip_detection_if: route.skip_detection_if.clone(),
+            in_flight: route
+                .coalesce_identical_requests
+                .then(|| Arc::new(coalesce::Coalescer::default())),
+            param_bounds: route.param_bounds.clone(),
+            return_detection_summary: route.return_detection_summary,
+            detector_actions: detector_actions(route, &gateway_config.detectors),
+            detector_thresholds: detector_thresholds(route, &gateway_config.detectors),
+            detectors_merge_policy: route.detectors_merge_policy,
+            detections_trailer: route.detections_trailer,
+            content_pointer: route.content_pointer.clone(),
+            retry_budget: retry_budget.clone(),
+            timeout: route
+                .timeout_seconds
+                .or(gateway_config.orchestrator.timeout_seconds)
+                .map(Duration::from_secs),
+            allowed_models: route.allowed_models.clone(),
+            system_prompt: route.system_prompt.clone(),
+            max_tokens_cap: route.max_tokens_cap,
+            expose_detections: route.expose_detections,
+            detection_only: route.detection_only,
+        });
+        let route_client = route_client(route, &orchestrator_client, &gateway_config.orchestrator)?;
+        if route.expose_verdict {
+            let path = format!("/{}/v1/verdict", route.name);
+            let route_context = route_context.clone();
+            let gateway_config = gateway_config.clone();
+            let orchestrator_client = route_client.clone();
+            let orchestrators = orchestrators.clone();
+            routes = routes.route(
+                &path,
+                post(
+                    move |headers: HeaderMap, payload: Result<Json<Value>, JsonRejection>| async move {
+                        let Json(payload) = payload?;
+                        handle_verdict(
+                            headers,
+                            Json(payload),
+                            route_context,
+                            gateway_config,
+                            orchestrator_client,
+                            orchestrators,
+                        )
+                        .await
+                    },
+                )
+                .fallback(method_not_allowed),
+            );
+            tracing::info!("exposed endpoint: {}", path);
+        }
 
-        // Single endpoint that handles both streaming and non-streaming based on payload
-        app = app.route(
+        let path = format!("/{}/v1/models", route.name);
+        let models_route = route_context.clone();
+        let models_config = gateway_config.clone();
+        let models_client = route_client.clone();
+        let models_orchestrators = orchestrators.clone();
+        routes = routes.route(
             &path,
-            post(move |headers: HeaderMap, Json(payload): Json<serde_json::Value>| async move {
-                handle_chat_completions(
+            get(move |headers: HeaderMap| async move {
+                handle_models(
                     headers,
-                    Json(payload),
-                    detectors,
-                    gateway_config,
-                    fallback_message,
-                    orchestrator_client,
-                    scheme,
-                ).await
+                    models_route,
+                    models_config,
+                    models_client,
+                    models_orchestrators,
+                )
+                .await
             }),
         );
+        tracing::info!("exposed endpoint: {}", path);
+
+        if !route.detection_only {
+            let path = format!("/{}/v1/completions", route.name);
+            let route_context = route_context.clone();
+            let gateway_config = gateway_config.clone();
+            let orchestrator_client = route_client.clone();
+            let orchestrators = orchestrators.clone();
+            routes = routes.route(
+                &path,
+                post(
+                    move |headers: HeaderMap, payload: Result<Json<Value>, JsonRejection>| async move {
+                        let Json(payload) = payload?;
+                        handle_completions(
+                            headers,
+                            Json(payload),
+                            route_context,
+                            gateway_config,
+                            orchestrator_client,
+                            orchestrators,
+                        )
+                        .await
+                    },
+                )
+                .fallback(method_not_allowed),
+            );
+            tracing::info!("exposed endpoint: {}", path);
+        }
+
+        let path = format!("/{}/v1/chat/completions", route.name);
+        let orchestrator_client = route_client;
+        let orchestrators = orchestrators.clone();
+
+        // Single endpoint that handles both streaming and non-streaming based on payload
+        routes = routes.route(
+            &path,
+            post(
+                move |headers: HeaderMap, payload: Result<Json<Value>, JsonRejection>| async move {
+                    let Json(payload) = payload?;
+                    handle_chat_completions(
+                        headers,
+                        Json(payload),
+                        route_context,
+                        gateway_config,
+                        orchestrator_client,
+                        orchestrators,
+                    )
+                    .await
+                },
+            )
+            .fallback(method_not_allowed),
+        );
 
         tracing::info!("exposed endpoint: {}", path);
     }
+    // Applied to every route so no handler ever sees a tenant header the client set itself
+    if let Some(tenant) = &gateway_config.tenant_claim {
+        routes = routes.route_layer(axum::middleware::from_fn_with_state(
+            Arc::new(tenant.clone()),
+            jwt::resolve_tenant,
+        ));
+    }
+    if !gateway_config.api_keys.is_empty() {
+        routes = routes.route_layer(axum::middleware::from_fn_with_state(
+            Arc::new(gateway_config.api_keys.clone()),
+            auth::require_api_key,
+        ));
+    }
+    app = app.merge(routes);
 
-    let mut http_port = 8090;
-    if let Ok(port) = env::var("HTTP_PORT") {
-        match port.parse::<u16>() {
-            Ok(port) => {
-                tracing::debug!("Using HTTP_PORT from env: {}", port);
-                http_port = port
-            }
-            Err(err) => {
-                tracing::error!("Failed to parse HTTP_PORT: {}", err);
-                println!("{}", err)
+    // Layers only wrap the routes added before them, so they are applied last
+    if let Some(timeout_ms) = gateway_config.inbound_timeout_ms {
+        app = app.layer(axum::middleware::from_fn_with_state(
+            Duration::from_millis(timeout_ms),
+            inbound_timeout::inbound_timeout,
+        ));
+    }
+    Ok(app
+        .layer(axum::middleware::map_request(
+            trace_context::ensure_traceparent,
+        ))
+        .layer(
+            TraceLayer::new_for_http()
+                .make_span_with(|request: &axum::http::Request<axum::body::Body>| {
+                    tracing::info_span!(
+                        "request",
+                        method = %request.method(),
+                        uri = %request.uri(),
+                        version = ?request.version(),
+                        route = tracing::field::Empty,
+                        request_id = tracing::field::Empty,
+                        tenant_id = tracing::field::Empty,
+                        trace_id = tracing::field::Empty,
+                    )
+                })
+                .on_response(trace::DefaultOnResponse::new().level(Level::INFO)),
+        ))
+}
+
+/// Completion routes only accept POST; anything else gets a JSON body naming the allowed method
+/// instead of axum's empty 405.
+async fn method_not_allowed(method: Method) -> Response {
+    (
+        StatusCode::METHOD_NOT_ALLOWED,
+        [(header::ALLOW, "POST")],
+        Json(json!({
+            "error": {
+                "message": format!("Method {} is not allowed on this endpoint, use POST", method),
+                "type": "method_not_allowed",
+                "allowed_methods": ["POST"],
             }
+        })),
+    )
+        .into_response()
+}
+
+/// Rejects payloads the orchestrator could only answer with a confusing error: anything but a
+/// JSON object with a non-empty `messages` array. Other fields are left to the orchestrator.
+fn validate_messages(payload: &Value) -> Result<(), &'static str> {
+    let Some(payload) = payload.as_object() else {
+        return Err("request body must be a JSON object");
+    };
+    match payload.get("messages") {
+        Some(Value::Array(messages)) if !messages.is_empty() => Ok(()),
+        Some(Value::Array(_)) => Err("'messages' must not be empty"),
+        Some(_) => Err("'messages' must be an array"),
+        None => Err("'messages' is required"),
+    }
+}
+
+/// Rejects text completions requests without a prompt, and streaming ones, which the gateway
+/// cannot check for detections as they arrive on this API.
+fn validate_prompt(payload: &Value) -> Result<(), &'static str> {
+    let Some(payload) = payload.as_object() else {
+        return Err("request body must be a JSON object");
+    };
+    if payload.get("stream").and_then(Value::as_bool) == Some(true) {
+        return Err("streaming is not supported on /v1/completions");
+    }
+    match payload.get("prompt") {
+        Some(Value::String(prompt)) if prompt.is_empty() => Err("'prompt' must not be empty"),
+        Some(Value::String(_)) => Ok(()),
+        Some(Value::Array(prompts)) if !prompts.iter().all(Value::is_string) => {
+            Err("'prompt' must be a string or an array of strings")
         }
+        Some(Value::Array(prompts)) if prompts.is_empty() => Err("'prompt' must not be empty"),
+        Some(Value::Array(_)) => Ok(()),
+        Some(_) => Err("'prompt' must be a string or an array of strings"),
+        None => Err("'prompt' is required"),
+    }
+}
+
+/// Rejects requests for a model outside the route's `allowed_models`, if it has any.
+fn check_model(payload: &Value, route: &RouteContext) -> Result<(), String> {
+    let Some(allowed_models) = &route.allowed_models else {
+        return Ok(());
+    };
+    match payload.get("model").and_then(Value::as_str) {
+        Some(model) if allowed_models.iter().any(|allowed| allowed == model) => Ok(()),
+        Some(model) => Err(format!(
+            "model '{}' is not allowed on route '{}', use one of: {}",
+            model,
+            route.name,
+            allowed_models.join(", ")
+        )),
+        None => Err(format!(
+            "a model is required on route '{}', use one of: {}",
+            route.name,
+            allowed_models.join(", ")
+        )),
     }
+}
 
-    let host = env::var("HOST").unwrap_or_else(|_| "0.0.0.0".to_string());
-    tracing::debug!("Using host: {}", host);
+/// Caps the number of detection results forwarded to the client at `max`, keeping the highest
+/// scoring ones, and records how many were produced versus returned.
+fn cap_detections(detections: &mut Option<Detections>, max: Option<usize>) {
+    if let Some(detections) = detections {
+        let produced = detections.result_count();
+        if let Some(max) = max.filter(|max| produced > *max) {
+            tracing::warn!(
+                "Orchestrator returned {} detection results, keeping the top {}",
+                produced,
+                max
+            );
+            detections.truncate_results(max);
+        }
+        metrics::record_detection_results(produced, detections.result_count());
+    }
+}
 
-    let ip: IpAddr = host.parse().expect("Failed to parse host IP address");
-    let addr = SocketAddr::from((ip, http_port));
-    tracing::debug!("Binding to address: {}", addr);
+/// Text that redacted output spans are replaced with.
+const REDACTION_PLACEHOLDER: &str = "[REDACTED]";
 
-    let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
-    tracing::info!("listening on {}", addr);
+/// Maps the orchestrator id of each grouped detector in the route to its group's action.
+fn detector_actions(
+    route: &config::RouteConfig,
+    detector_config: &[DetectorConfig],
+) -> HashMap<String, GroupAction> {
+    let mut actions = HashMap::new();
+    for group in &route.detector_groups {
+        for detector in detector_config
+            .iter()
+            .filter(|d| group.detectors.contains(&d.name))
+        {
+            let id = detector
+                .server
+                .clone()
+                .unwrap_or_else(|| detector.name.clone());
+            let action = actions.entry(id).or_insert(group.action);
+            *action = (*action).max(group.action);
+        }
+    }
+    actions
+}
+
+/// Maps the orchestrator id of each detector in the route that sets a `threshold` to it.
+fn detector_thresholds(
+    route: &config::RouteConfig,
+    detector_config: &[DetectorConfig],
+) -> HashMap<String, f64> {
+    detector_config
+        .iter()
+        .filter(|d| route.detectors.contains(&d.name))
+        .filter_map(|detector| {
+            let id = detector
+                .server
+                .clone()
+                .unwrap_or_else(|| detector.name.clone());
+            detector.threshold.map(|threshold| (id, threshold))
+        })
+        .collect()
+}
+
+/// Drops detection results below their detector's threshold before anything acts on them.
+fn apply_thresholds(detections: &mut Option<Detections>, route: &RouteContext) {
+    if let Some(detections) = detections {
+        detections.drop_below_thresholds(&route.detector_thresholds);
+    }
+}
 
-    axum::serve(listener, app).await.unwrap();
+/// Leaves out detection results scoring below their detector's threshold, as [`apply_thresholds`]
+/// does for generation responses.
+fn retain_above_thresholds(detections: &mut Vec<ChatDetectionResult>, route: &RouteContext) {
+    detections.retain(|detection| {
+        detection
+            .detector_id
+            .as_ref()
+            .and_then(|id| route.detector_thresholds.get(id))
+            .is_none_or(|threshold| detection.score >= *threshold)
+    });
+}
+
+/// The most severe action among the detectors that flagged something, or `None` if nothing was
+/// flagged. Without detector groups any detections block, as they always have.
+fn triggered_action(detections: &Option<Detections>, route: &RouteContext) -> Option<GroupAction> {
+    let detections = detections.as_ref().filter(|d| d.has_hits())?;
+    if route.detector_actions.is_empty() {
+        return Some(GroupAction::Block);
+    }
+    detections
+        .flagged_detectors()
+        .map(|id| {
+            route
+                .detector_actions
+                .get(id)
+                .copied()
+                .unwrap_or(GroupAction::Block)
+        })
+        .max()
 }
 
+/// Returns the fallback choices to send instead of `choices` if anything was detected: one per
+/// original choice so none of them is left out when the client asked for several, or a single
+/// one when there was no generation, as with input detections.
 fn check_payload_detections(
     detections: &Option<Detections>,
-    route_fallback_message: Option<String>,
-) -> Option<GenerationChoice> {
-    if let (Some(fallback_message), Some(_)) = (route_fallback_message, detections) {
-        return Some(GenerationChoice {
-            message: GenerationMessage::new(fallback_message),
-            finish_reason: String::from("stop"),
-            index: 0,
-            logprobs: None,
-        });
-    }
+    route: &RouteContext,
+    choices: &[GenerationChoice],
+) -> Option<Vec<GenerationChoice>> {
+    let fallback_message = route.fallback_for(detections.as_ref())?;
+    let indexes: Vec<u32> = match choices {
+        [] => vec![0],
+        choices => choices.iter().map(|choice| choice.index).collect(),
+    };
+    Some(
+        indexes
+            .into_iter()
+            .map(|index| GenerationChoice {
+                message: GenerationMessage::new(fallback_message.to_string()),
+                finish_reason: String::from("stop"),
+                index,
+                logprobs: None,
+            })
+            .collect(),
+    )
+}
+
+/// Runs the route's input detectors over the request messages through the orchestrator's chat
+/// detection endpoint, without generating.
+async fn detect_chat(
+    headers: &HeaderMap,
+    payload: &Value,
+    route: &RouteContext,
+    gateway_config: &GatewayConfig,
+    orchestrator_client: &reqwest::Client,
+    orchestrators: &balancer::Balancer,
+) -> Result<Vec<ChatDetectionResult>, ApiError> {
+    let urls = orchestrators.urls("/api/v2/text/detection/chat");
+    let mut detection_payload = Map::new();
+    detection_payload.insert(
+        "messages".to_string(),
+        payload.get("messages").cloned().unwrap_or(json!([])),
+    );
+    detection_payload.insert(
+        "detectors".to_string(),
+        route.orchestrator_detectors["input"].clone(),
+    );
 
-    None
+    let response: ChatDetectionResponse = orchestrator_post_request(
+        Some(&mut detection_payload),
+        headers,
+        gateway_config,
+        &urls,
+        orchestrator_client,
+        route.timeout,
+        route.retry_budget.as_deref(),
+    )
+    .await
+    .map_err(ApiError::from)?;
+    Ok(response.detections)
 }
 
-async fn handle_chat_completions(
+/// Runs the route's input detectors over the request messages without generating, and returns
+/// a normalized verdict rather than the raw detections.
+async fn handle_verdict(
     headers: HeaderMap,
     Json(payload): Json<serde_json::Value>,
-    detectors: Vec<String>,
-    gateway_config: GatewayConfig,
-    route_fallback_message: Option<String>,
+    route: Arc<RouteContext>,
+    gateway_config: Arc<GatewayConfig>,
     orchestrator_client: Arc<reqwest::Client>,
-    scheme: String,
-) -> Result<Response, (StatusCode, String)> {
-    tracing::debug!("handle_chat_completions called with payload: {:?}", payload);
+    orchestrators: Arc<balancer::Balancer>,
+) -> Result<Json<Verdict>, ApiError> {
+    tracing::debug!(
+        "handle_verdict called with payload: {:?}",
+        logged(&payload, gateway_config.redact_logs)
+    );
+
+    let mut detections = detect_chat(
+        &headers,
+        &payload,
+        &route,
+        &gateway_config,
+        &orchestrator_client,
+        &orchestrators,
+    )
+    .await?;
+    retain_above_thresholds(&mut detections, &route);
+    Ok(Json(Verdict::from_detections(
+        detections,
+        route.verdict_threshold,
+    )))
+}
+
+/// Answers a chat completions request on a `detection_only` route with the input detections
+/// alone, leaving out results below their detector's threshold. Nothing is generated, so a
+/// request for a stream gets the same JSON response.
+async fn handle_detection_only(
+    headers: HeaderMap,
+    Json(payload): Json<serde_json::Value>,
+    route: Arc<RouteContext>,
+    gateway_config: Arc<GatewayConfig>,
+    orchestrator_client: Arc<reqwest::Client>,
+    orchestrators: Arc<balancer::Balancer>,
+) -> Result<Json<ChatDetectionResponse>, ApiError> {
+    tracing::debug!(
+        "handle_detection_only called with payload: {:?}",
+        logged(&payload, gateway_config.redact_logs)
+    );
+
+    let mut detections = detect_chat(
+        &headers,
+        &payload,
+        &route,
+        &gateway_config,
+        &orchestrator_client,
+        &orchestrators,
+    )
+    .await?;
+    retain_above_thresholds(&mut detections, &route);
+    Ok(Json(ChatDetectionResponse { detections }))
+}
+
+/// Passes the orchestrator's model list through as it is, status included, so OpenAI SDK
+/// clients can discover models on the route before chatting.
+async fn handle_models(
+    headers: HeaderMap,
+    route: Arc<RouteContext>,
+    gateway_config: Arc<GatewayConfig>,
+    orchestrator_client: Arc<reqwest::Client>,
+    orchestrators: Arc<balancer::Balancer>,
+) -> Result<Response, ApiError> {
+    let urls = orchestrators.urls(&gateway_config.orchestrator.models_path);
+    let response = send_with_failover(&urls, |url| {
+        let mut req = forward_headers(orchestrator_client.get(url), &headers, &gateway_config);
+        if let Some(timeout) = route.timeout {
+            req = req.timeout(timeout);
+        }
+        req.send()
+            .map(|result| result.map_err(OrchestratorError::from))
+    })
+    .await?;
+
+    let status = response.status();
+    let content_type = response.headers().get(header::CONTENT_TYPE).cloned();
+    let body = response.bytes().await.map_err(|e| {
+        if e.is_timeout() {
+            OrchestratorError::Timeout(e)
+        } else {
+            OrchestratorError::IncompleteBody(e)
+        }
+    })?;
+    let mut response = (status, body).into_response();
+    if let Some(content_type) = content_type {
+        response
+            .headers_mut()
+            .insert(header::CONTENT_TYPE, content_type);
+    }
+    Ok(response)
+}
+
+async fn handle_chat_completions(
+    headers: HeaderMap,
+    Json(mut payload): Json<serde_json::Value>,
+    route: Arc<RouteContext>,
+    gateway_config: Arc<GatewayConfig>,
+    orchestrator_client: Arc<reqwest::Client>,
+    orchestrators: Arc<balancer::Balancer>,
+) -> Result<Response, ApiError> {
+    tracing::debug!(
+        "handle_chat_completions called with payload: {:?}",
+        logged(&payload, gateway_config.redact_logs)
+    );
 
     // Check if streaming is requested
     let is_streaming = payload
@@ -168,208 +1319,1163 @@ async fn handle_chat_completions(
         .and_then(|obj| obj.get("stream"))
         .and_then(|v| v.as_bool())
         .unwrap_or(false);
+    // Every request is logged once, here, whichever way it is handled or rejected
+    let mut log = RequestLog::new(&route.name, payload["model"].as_str(), is_streaming)
+        .with_trace_id(trace_context::request_trace_id(&headers));
+
+    let request_id = trace_request(&headers, &route, &gateway_config);
+    let request_id_header = gateway_config.request_id_header.clone();
 
-    let result = if is_streaming {
-        handle_streaming_generation(
+    let result = match prepare_request(
+        &headers,
+        &mut payload,
+        route,
+        &gateway_config,
+        request_id.as_ref(),
+        CompletionsApi::Chat,
+        &mut log,
+    ) {
+        Err(e) => Err(e),
+        Ok(route) if route.detection_only => {
+            let result = handle_detection_only(
+                headers,
+                Json(payload),
+                route,
+                gateway_config,
+                orchestrator_client,
+                orchestrators,
+            )
+            .await;
+            if result.is_err() {
+                log.record(Decision::Error, None);
+            }
+            result.map(|response| response.into_response())
+        }
+        Ok(route) if is_streaming && route.buffered_streaming => {
+            handle_buffered_streaming_generation(
+                headers,
+                Json(payload),
+                route,
+                gateway_config,
+                orchestrator_client,
+                orchestrators,
+                log,
+            )
+            .await
+            .map(|response| response.into_response())
+        }
+        Ok(route) if is_streaming => handle_streaming_generation(
             headers,
             Json(payload),
-            detectors,
+            route,
             gateway_config,
-            route_fallback_message,
             orchestrator_client,
-            scheme,
+            orchestrators,
+            log,
         )
         .await
-        .map(|response| response.into_response())
-    } else {
-        handle_non_streaming_generation(
+        .map(|response| response.into_response()),
+        Ok(route) => handle_non_streaming_generation(
             headers,
             Json(payload),
-            detectors,
+            route,
             gateway_config,
-            route_fallback_message,
             orchestrator_client,
-            scheme,
+            orchestrators,
+            log,
         )
         .await
-        .map(|response| response.into_response())
+        .map(|response| response.into_response()),
     };
 
-    result
+    let mut response = result.unwrap_or_else(IntoResponse::into_response);
+    echo_request_id(&mut response, request_id, &request_id_header);
+    Ok(response)
 }
 
-async fn handle_non_streaming_generation(
-    headers: HeaderMap,
+fn echo_request_id(
+    response: &mut Response,
+    request_id: Option<HeaderValue>,
+    request_id_header: &str,
+) {
+    if let (Some(value), Ok(name)) = (
+        request_id,
+        HeaderName::from_bytes(request_id_header.as_bytes()),
+    ) {
+        response.headers_mut().insert(name, value);
+    }
+}
+
+/// Records the route and the caller's request id on the request span. The request id is
+/// returned so it can be echoed back and correlated with gateway and orchestrator logs.
+fn trace_request(
+    headers: &HeaderMap,
+    route: &RouteContext,
+    gateway_config: &GatewayConfig,
+) -> Option<HeaderValue> {
+    let request_id = headers
+        .get(gateway_config.request_id_header.as_str())
+        .cloned();
+    let span = tracing::Span::current();
+    span.record("route", route.name.as_str());
+    if let Some(request_id) = request_id.as_ref().and_then(|v| v.to_str().ok()) {
+        span.record("request_id", request_id);
+    }
+    request_id
+}
+
+/// Applies everything chat and text completions have in common before the orchestrator is
+/// called: gateway metadata, parameter bounds, the content pointer and system prompt, and the
+/// client's detector selection. The rewritten payload is then validated, with rejections
+/// recorded on `log`. Returns the route the request is to be handled with.
+fn prepare_request(
+    headers: &HeaderMap,
+    payload: &mut Value,
+    route: Arc<RouteContext>,
+    gateway_config: &GatewayConfig,
+    request_id: Option<&HeaderValue>,
+    api: CompletionsApi,
+    log: &mut RequestLog,
+) -> Result<Arc<RouteContext>, ApiError> {
+    if let (Some(mut metadata), Some(obj)) =
+        (route.gateway_metadata.clone(), payload.as_object_mut())
+    {
+        if let Some(request_id) = request_id.and_then(|v| v.to_str().ok()) {
+            metadata["request_id"] = json!(request_id);
+        }
+        obj.insert(GATEWAY_METADATA_KEY.to_string(), metadata);
+    }
+
+    if let (Some(bounds), Some(obj)) = (&route.param_bounds, payload.as_object_mut()) {
+        clamp_params(obj, bounds);
+    }
+
+    if let (Some(cap), Some(obj)) = (route.max_tokens_cap, payload.as_object_mut()) {
+        cap_max_tokens(obj, cap);
+    }
+
+    if let Some(pointer) = &route.content_pointer {
+        if let Err(e) = content_from_pointer(payload, pointer, api) {
+            log.record(Decision::Error, None);
+            return Err(e);
+        }
+    }
+
+    // Text completions have no conversation to put a system prompt in
+    if let (Some(prompt), Some(obj), CompletionsApi::Chat) =
+        (&route.system_prompt, payload.as_object_mut(), api)
+    {
+        prepend_system_prompt(obj, prompt);
+    }
+
+    let route = match headers.get(DETECTOR_SELECTION_HEADER) {
+        Some(requested) if route.allow_detector_selection => {
+            let requested = requested.to_str().unwrap_or_default();
+            let selected = select_detectors(&route.detectors, requested);
+            tracing::debug!("Client selected detectors {:?}", selected);
+            Arc::new(route.with_detectors(selected, &gateway_config.detectors))
+        }
+        _ => route,
+    };
+
+    let validated = match api {
+        CompletionsApi::Chat => validate_messages(payload),
+        CompletionsApi::Text => validate_prompt(payload),
+    };
+    let checked = validated.map_err(ApiError::invalid_request).and_then(|()| {
+        check_model(payload, &route)
+            .map_err(|message| ApiError::permission(message).with_code("model_not_allowed"))
+    });
+    if let Err(e) = checked {
+        log.record(Decision::Error, None);
+        return Err(e);
+    }
+    Ok(route)
+}
+
+/// Serves the legacy text completions API: the route's detectors are added to the request and
+/// the orchestrator's completions endpoint is called, then the fallback applies as on chat
+/// completions, with each choice's `text` replaced. Only non-streaming requests are supported.
+async fn handle_completions(
+    headers: HeaderMap,
     Json(mut payload): Json<serde_json::Value>,
-    detectors: Vec<String>,
-    gateway_config: GatewayConfig,
-    route_fallback_message: Option<String>,
+    route: Arc<RouteContext>,
+    gateway_config: Arc<GatewayConfig>,
     orchestrator_client: Arc<reqwest::Client>,
-    scheme: String,
-) -> Result<impl IntoResponse, (StatusCode, String)> {
-    tracing::debug!("handle_non_streaming_generation called with payload: {:?}", payload);
+    orchestrators: Arc<balancer::Balancer>,
+) -> Result<Response, ApiError> {
+    tracing::debug!(
+        "handle_completions called with payload: {:?}",
+        logged(&payload, gateway_config.redact_logs)
+    );
+
+    // Logged from here on, so that rejected requests are reported too
+    let mut log = RequestLog::new(&route.name, payload["model"].as_str(), false)
+        .with_trace_id(trace_context::request_trace_id(&headers));
+    let request_id = trace_request(&headers, &route, &gateway_config);
+    let result = match prepare_request(
+        &headers,
+        &mut payload,
+        route,
+        &gateway_config,
+        request_id.as_ref(),
+        CompletionsApi::Text,
+        &mut log,
+    ) {
+        Ok(route) => {
+            complete_text(
+                headers,
+                payload,
+                &route,
+                &gateway_config,
+                &orchestrator_client,
+                &orchestrators,
+                log,
+            )
+            .await
+        }
+        Err(e) => Err(e),
+    };
+    let mut response = result.into_response();
+    echo_request_id(&mut response, request_id, &gateway_config.request_id_header);
+    Ok(response)
+}
+
+async fn complete_text(
+    headers: HeaderMap,
+    mut payload: Value,
+    route: &RouteContext,
+    gateway_config: &GatewayConfig,
+    orchestrator_client: &reqwest::Client,
+    orchestrators: &balancer::Balancer,
+    mut log: RequestLog,
+) -> Result<Json<CompletionResponse>, ApiError> {
+    let payload = payload.as_object_mut().expect("validated as an object");
+    if let Err(e) = insert_detectors(payload, &headers, route, false) {
+        log.record(Decision::Error, None);
+        return Err(e);
+    }
+
+    let urls = orchestrators.urls(COMPLETIONS_API_PATH);
+    let response = orchestrator_post_request::<CompletionResponse>(
+        Some(payload),
+        &headers,
+        gateway_config,
+        &urls,
+        orchestrator_client,
+        route.timeout,
+        route.retry_budget.as_deref(),
+    )
+    .await;
+    log.orchestrator_responded();
+    let mut response = match response {
+        Ok(response) => response,
+        Err(e) => {
+            log.orchestrator_failed();
+            return Err(ApiError::from(e));
+        }
+    };
+
+    apply_thresholds(&mut response.detections, route);
+    // Text completions have no spans to truncate or redact, so anything that isn't only
+    // monitored falls back
+    let decision = match triggered_action(&response.detections, route) {
+        None => Decision::Passthrough,
+        Some(GroupAction::Monitor) => Decision::Monitor,
+        Some(_) => match route.fallback_for(response.detections.as_ref()) {
+            Some(fallback_message) => {
+                response.replace_choices(fallback_message);
+                Decision::Fallback
+            }
+            None => Decision::Passthrough,
+        },
+    };
+    log.record(decision, response.detections.as_ref());
+    cap_detections(
+        &mut response.detections,
+        gateway_config.max_detection_results,
+    );
+    Ok(Json(response))
+}
+
+async fn handle_non_streaming_generation(
+    headers: HeaderMap,
+    Json(payload): Json<serde_json::Value>,
+    route: Arc<RouteContext>,
+    gateway_config: Arc<GatewayConfig>,
+    orchestrator_client: Arc<reqwest::Client>,
+    orchestrators: Arc<balancer::Balancer>,
+    mut log: RequestLog,
+) -> Result<impl IntoResponse, ApiError> {
+    tracing::debug!(
+        "handle_non_streaming_generation called with payload: {:?}",
+        logged(&payload, gateway_config.redact_logs)
+    );
+
+    let started = Instant::now();
+    let capture = gateway_config
+        .capture
+        .clone()
+        .filter(capture::should_capture)
+        .map(|capture| (capture, payload.clone()));
+    let route_name = route.name.clone();
+    let expose_detections = route.expose_detections;
+
+    // A request that joined another's orchestrator call has no round trip of its own. Each
+    // caller logs the shared outcome itself, so every request gets its own line.
+    let (response, orchestrator_duration) = match route.in_flight.clone() {
+        Some(in_flight) => {
+            let key = coalescing_key(&headers, &payload, &route);
+            let work = async move {
+                let mut outcome = Outcome::default();
+                let response = complete_with_detections(
+                    headers,
+                    Json(payload),
+                    route,
+                    gateway_config,
+                    orchestrator_client,
+                    orchestrators,
+                    &mut outcome,
+                )
+                .await
+                .map(|(orchestrator_response, _)| json!(orchestrator_response));
+                (response, outcome)
+            };
+            let (response, outcome) = in_flight.run(key, work).await;
+            log.merge(&outcome);
+            (response?, None)
+        }
+        None => {
+            let mut outcome = Outcome::default();
+            let response = complete_with_detections(
+                headers,
+                Json(payload),
+                route,
+                gateway_config,
+                orchestrator_client,
+                orchestrators,
+                &mut outcome,
+            )
+            .await;
+            log.merge(&outcome);
+            let (orchestrator_response, orchestrator_duration) = response?;
+            (json!(orchestrator_response), Some(orchestrator_duration))
+        }
+    };
+
+    if let Some((capture, request)) = capture {
+        let response = response.clone();
+        tokio::spawn(async move {
+            capture::write_capture(&capture, &route_name, request, response).await
+        });
+    }
+
+    let detection_counts = expose_detections.then(|| detection_counts(&response));
+    let mut response = Json(response).into_response();
+    if let Some(counts) = detection_counts {
+        match counts.parse() {
+            Ok(value) => {
+                response.headers_mut().insert(DETECTIONS_HEADER, value);
+            }
+            Err(e) => tracing::error!("Failed to encode detections header: {}", e),
+        }
+    }
+    if let Ok(value) = server_timing(started.elapsed(), orchestrator_duration).parse() {
+        response.headers_mut().insert(SERVER_TIMING, value);
+    }
+    Ok(response)
+}
+
+/// Formats the `x-detections` header value: a JSON object with the number of results from each
+/// detector in `response`, e.g. `{"hap":1,"regex-language":2}`, or `{}` when nothing was flagged.
+fn detection_counts(response: &Value) -> String {
+    let counts = response
+        .get("detections")
+        .cloned()
+        .and_then(|detections| serde_json::from_value::<Detections>(detections).ok())
+        .map(|detections| detections.counts_by_detector())
+        .unwrap_or_default();
+    json!(counts).to_string()
+}
+
+/// Formats a `Server-Timing` header value, in milliseconds, with the total time spent in the
+/// gateway and the orchestrator round trip when there was one.
+fn server_timing(total: Duration, orchestrator: Option<Duration>) -> String {
+    let mut timing = format!("total;dur={:.1}", total.as_secs_f64() * 1000.0);
+    if let Some(orchestrator) = orchestrator {
+        timing.push_str(&format!(
+            ", orchestrator;dur={:.1}",
+            orchestrator.as_secs_f64() * 1000.0
+        ));
+    }
+    timing
+}
+
+/// Identifies requests that may share one orchestrator call: the same route, payload and
+/// detectors (with templated params resolved), sent with the same credentials. JSON object keys
+/// are sorted, so field order in the client's payload does not matter.
+fn coalescing_key(headers: &HeaderMap, payload: &Value, route: &RouteContext) -> String {
+    let mut detectors = route.orchestrator_detectors.clone();
+    if let Some(payload) = payload.as_object() {
+        templates::resolve(&mut detectors, payload, headers);
+    }
+    json!({
+        "route": route.name,
+        "payload": payload,
+        "detectors": detectors,
+        "authorization": headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok()),
+    })
+    .to_string()
+}
+
+/// Validates the full completion before streaming it: a single non-streaming orchestrator call
+/// is made, and only once its detections are known is the (possibly fallback) content sent to
+/// the client as SSE chunks.
+async fn handle_buffered_streaming_generation(
+    headers: HeaderMap,
+    Json(mut payload): Json<serde_json::Value>,
+    route: Arc<RouteContext>,
+    gateway_config: Arc<GatewayConfig>,
+    orchestrator_client: Arc<reqwest::Client>,
+    orchestrators: Arc<balancer::Balancer>,
+    mut log: RequestLog,
+) -> Result<Response, ApiError> {
+    tracing::debug!(
+        "handle_buffered_streaming_generation called with payload: {:?}",
+        logged(&payload, gateway_config.redact_logs)
+    );
+
+    if route.buffer_upstream_stream {
+        return handle_buffered_upstream_generation(
+            headers,
+            Json(payload),
+            route,
+            gateway_config,
+            orchestrator_client,
+            orchestrators,
+            log,
+        )
+        .await;
+    }
 
-    let orchestrator_detectors =
-        get_orchestrator_detectors(detectors.clone(), gateway_config.detectors.clone());
-    tracing::debug!("Orchestrator detectors: {:?}", orchestrator_detectors);
+    if let Some(obj) = payload.as_object_mut() {
+        obj.insert("stream".to_string(), Value::Bool(false));
+    }
+
+    let mut outcome = Outcome::default();
+    let response = complete_with_detections(
+        headers,
+        Json(payload),
+        route,
+        gateway_config,
+        orchestrator_client,
+        orchestrators,
+        &mut outcome,
+    )
+    .await;
+    log.merge(&outcome);
+    let (orchestrator_response, _) = response?;
+
+    let events = orchestrator_response
+        .into_streaming_responses()
+        .into_iter()
+        .map(|chunk| Event::default().json_data(chunk))
+        .chain([Ok(done_event())]);
+
+    Ok(Sse::new(futures::stream::iter(events))
+        .keep_alive(KeepAlive::default())
+        .into_response())
+}
 
+/// Buffered streaming that reads the orchestrator's own stream rather than making a single
+/// non-streaming call. Chunks are held back until the stream completes cleanly; as soon as a
+/// chunk carries blocking detections the upstream request is dropped, cancelling generation, and
+/// only the fallback is sent, or with the `truncate` action the chunks so far followed by the
+/// truncated one. Chunks flagged by redact or monitor groups are kept, redacted or as they are.
+async fn handle_buffered_upstream_generation(
+    headers: HeaderMap,
+    Json(mut payload): Json<serde_json::Value>,
+    route: Arc<RouteContext>,
+    gateway_config: Arc<GatewayConfig>,
+    orchestrator_client: Arc<reqwest::Client>,
+    orchestrators: Arc<balancer::Balancer>,
+    mut log: RequestLog,
+) -> Result<Response, ApiError> {
+    let urls = orchestrators.urls(&route.completions_path);
     let mut payload = payload.as_object_mut();
+    if let Err(e) = insert_detectors(payload.as_mut().unwrap(), &headers, &route, true) {
+        log.record(Decision::Error, None);
+        return Err(e);
+    }
 
-    let url: String = match gateway_config.orchestrator.port {
-        Some(port) => format!(
-            "{}://{}:{}/api/v2/chat/completions-detection",
-            scheme,
-            gateway_config.orchestrator.host,
-            port
-        ),
-        None => format!(
-            "{}://{}/api/v2/chat/completions-detection",
-            scheme,
-            gateway_config.orchestrator.host
-        ),
+    let stream = orchestrator_streaming_request(
+        payload,
+        &headers,
+        &gateway_config,
+        &urls,
+        &orchestrator_client,
+        route.timeout,
+    )
+    .await;
+    log.orchestrator_responded();
+    let stream = stream.map_err(|e| {
+        log.orchestrator_failed();
+        ApiError::from(e)
+    })?;
+    let mut stream = Box::pin(stream);
+
+    let mut buffered = Vec::new();
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.map_err(|e| {
+            log.orchestrator_failed();
+            ApiError::from(e)
+        })?;
+        if let Ok(mut streaming_response) = serde_json::from_str::<StreamingResponse>(&chunk) {
+            apply_thresholds(&mut streaming_response.detections, &route);
+            let (decision, _) = apply_streaming_action(&mut streaming_response, &route);
+            log.record(decision, streaming_response.detections.as_ref());
+            match decision {
+                Decision::Fallback => {
+                    // Dropping the upstream stream closes the connection to the orchestrator
+                    drop(stream);
+                    tracing::debug!("Detection in buffered stream, cancelled upstream request");
+                    let event = Event::default().json_data(streaming_response);
+                    return Ok(
+                        Sse::new(futures::stream::iter([event, Ok(done_event())])).into_response()
+                    );
+                }
+                Decision::Truncate => {
+                    drop(stream);
+                    tracing::debug!("Detection in buffered stream, truncated and cancelled");
+                    buffered.push(
+                        serde_json::to_string(&streaming_response)
+                            .map_err(|_| ApiError::internal("serialization failed"))?,
+                    );
+                    break;
+                }
+                Decision::Redact => {
+                    buffered.push(
+                        serde_json::to_string(&streaming_response)
+                            .map_err(|_| ApiError::internal("serialization failed"))?,
+                    );
+                    continue;
+                }
+                _ => {}
+            }
+        }
+        buffered.push(chunk);
+    }
+
+    let events = buffered
+        .into_iter()
+        .map(|chunk| Event::default().data(chunk))
+        .chain([done_event()])
+        .map(Ok::<_, std::convert::Infallible>);
+    Ok(Sse::new(futures::stream::iter(events))
+        .keep_alive(KeepAlive::default())
+        .into_response())
+}
+
+/// Adds the route's detectors to an outgoing orchestrator payload, combining them with any
+/// detectors the client sent according to the route's merge policy.
+fn insert_detectors(
+    payload: &mut Map<String, Value>,
+    headers: &HeaderMap,
+    route: &RouteContext,
+    streaming: bool,
+) -> Result<(), ApiError> {
+    let client_detectors = payload.remove("detectors");
+    if client_detectors.is_some() && route.detectors_merge_policy == DetectorsMergePolicy::Reject {
+        return Err(ApiError::invalid_request(
+            "detectors may not be set by the client on this route",
+        ));
+    }
+    if let Some(rules) = &route.skip_detection_if {
+        if should_skip_detection(rules, payload) {
+            tracing::debug!("Skipping detection for route {}", route.name);
+            return Ok(());
+        }
+    }
+    let mut detectors = if streaming {
+        route.streaming_orchestrator_detectors.clone()
+    } else {
+        route.orchestrator_detectors.clone()
     };
-    tracing::debug!("Orchestrator URL: {}", url);
+    templates::resolve(&mut detectors, payload, headers);
+    if let (DetectorsMergePolicy::Merge, Some(client_detectors)) =
+        (route.detectors_merge_policy, client_detectors)
+    {
+        merge_detectors(&mut detectors, client_detectors);
+    }
+    if route.detect_last_message_only {
+        annotate_last_user_message(&mut detectors, payload);
+    }
+    payload.insert("detectors".to_string(), detectors);
+    Ok(())
+}
 
-    payload.as_mut().unwrap().insert(
-        "detectors".to_string(),
-        serde_json::to_value(&orchestrator_detectors).unwrap(),
+/// Deep-merges client detectors into the route's. The route's detectors act as a floor: the
+/// client can add detectors and params, but anything the route sets keeps the route's value,
+/// so a client cannot disable or weaken a mandated detector.
+fn merge_detectors(detectors: &mut Value, client: Value) {
+    // Anything but two objects: the route's value stands
+    if let (Value::Object(detectors), Value::Object(client)) = (detectors, client) {
+        for (key, value) in client {
+            match detectors.get_mut(&key) {
+                Some(existing) => merge_detectors(existing, value),
+                None => {
+                    detectors.insert(key, value);
+                }
+            }
+        }
+    }
+}
+
+/// A request skips detection when its combined message text is shorter than `max_chars`.
+/// Messages with non-text content are never skipped.
+fn should_skip_detection(rules: &SkipDetectionConfig, payload: &Map<String, Value>) -> bool {
+    let Some(max_chars) = rules.max_chars else {
+        return false;
+    };
+    let Some(messages) = payload.get("messages").and_then(Value::as_array) else {
+        return false;
+    };
+    let mut chars = 0;
+    for message in messages {
+        match &message["content"] {
+            Value::String(content) => chars += content.chars().count(),
+            Value::Null => {}
+            _ => return false,
+        }
+    }
+    chars < max_chars
+}
+
+/// Sets the payload's `messages`, or `prompt` for text completions, from the content the
+/// route's JSON pointer refers to, for clients that don't send OpenAI shaped requests. For chat,
+/// a string becomes a single user message and an array is taken as the messages themselves.
+/// Either is taken as the prompt as it is. Anything else is rejected.
+fn content_from_pointer(
+    payload: &mut Value,
+    pointer: &str,
+    api: CompletionsApi,
+) -> Result<(), ApiError> {
+    let content = match (payload.pointer(pointer), api) {
+        (Some(Value::String(content)), CompletionsApi::Chat) => {
+            json!([{"role": "user", "content": content}])
+        }
+        (Some(content @ (Value::String(_) | Value::Array(_))), _) => content.clone(),
+        _ => {
+            return Err(ApiError::invalid_request(format!(
+                "no string or {} array found at '{}'",
+                api.content_field_kind(),
+                pointer
+            )))
+        }
+    };
+    // The pointer resolved, so the payload is an object or array; only objects can carry messages
+    let Some(payload) = payload.as_object_mut() else {
+        return Err(ApiError::invalid_request(
+            "request body must be a JSON object",
+        ));
+    };
+    payload.insert(api.content_field().to_string(), content);
+    Ok(())
+}
+
+/// Puts the route's system prompt at the front of the conversation, ahead of any system message
+/// the client sent, unless the conversation already starts with it. Missing or empty `messages`
+/// are left alone so that the request is still rejected as invalid.
+fn prepend_system_prompt(payload: &mut Map<String, Value>, prompt: &str) {
+    let Some(Value::Array(messages)) = payload.get_mut("messages") else {
+        return;
+    };
+    let leads = |message: &Value| message["role"] == "system" && message["content"] == prompt;
+    if messages.is_empty() || messages.first().is_some_and(leads) {
+        return;
+    }
+    messages.insert(0, json!({"role": "system", "content": prompt}));
+}
+
+/// Clamps the sampling parameters present in the payload into the route's bounds. Parameters
+/// the client did not send are left unset.
+fn clamp_params(payload: &mut Map<String, Value>, bounds: &ParamBoundsConfig) {
+    for (param, bounds) in [("temperature", bounds.temperature), ("top_p", bounds.top_p)] {
+        let (Some(BoundsConfig { min, max }), Some(value)) =
+            (bounds, payload.get(param).and_then(Value::as_f64))
+        else {
+            continue;
+        };
+        let clamped = value
+            .max(min.unwrap_or(f64::MIN))
+            .min(max.unwrap_or(f64::MAX));
+        if clamped != value {
+            tracing::debug!("Clamped {} from {} to {}", param, value, clamped);
+            payload.insert(param.to_string(), json!(clamped));
+        }
+    }
+    if let (Some(cap), Some(value)) = (
+        bounds.max_tokens,
+        payload.get("max_tokens").and_then(Value::as_u64),
+    ) {
+        if value > cap {
+            tracing::debug!("Clamped max_tokens from {} to {}", value, cap);
+            payload.insert("max_tokens".to_string(), json!(cap));
+        }
+    }
+}
+
+/// Lowers `max_tokens` to the route's cap, or sets it to the cap when the client sent none, so
+/// that no request can generate without limit. Smaller values are kept.
+fn cap_max_tokens(payload: &mut Map<String, Value>, cap: u32) {
+    let requested = payload.get("max_tokens");
+    if requested.is_some_and(|value| value.as_u64().is_some_and(|value| value <= u64::from(cap))) {
+        return;
+    }
+    match requested {
+        Some(value) => tracing::info!("Capped max_tokens from {} to {}", value, cap),
+        None => tracing::info!("Set max_tokens to the route cap of {}", cap),
+    }
+    payload.insert("max_tokens".to_string(), json!(cap));
+}
+
+/// Points every input detector at the final user message via a `message_index` param so the
+/// orchestrator can skip the rest of the history. If there is no user message the detectors are
+/// left untouched and the full history is inspected.
+fn annotate_last_user_message(detectors: &mut Value, payload: &Map<String, Value>) {
+    let last_user_message = payload
+        .get("messages")
+        .and_then(Value::as_array)
+        .and_then(|messages| messages.iter().rposition(|m| m["role"] == "user"));
+    let Some(index) = last_user_message else {
+        return;
+    };
+    if let Some(input) = detectors.get_mut("input").and_then(Value::as_object_mut) {
+        for params in input.values_mut() {
+            if let Some(params) = params.as_object_mut() {
+                params.insert("message_index".to_string(), json!(index));
+            }
+        }
+    }
+}
+
+/// Replaces the content of every choice in a streaming chunk with the route's fallback message.
+/// Frames carrying input detections arrive before any generation and have no choices, so one is
+/// added.
+fn apply_streaming_fallback(streaming_response: &mut StreamingResponse, fallback_message: &str) {
+    let delta = || StreamingDelta {
+        content: Some(fallback_message.to_string()),
+        role: Some("assistant".to_string()),
+        tool_calls: None,
+    };
+    if streaming_response.choices.is_empty() {
+        streaming_response.choices.push(StreamingChoice {
+            index: 0,
+            delta: delta(),
+            logprobs: None,
+            finish_reason: Some("stop".to_string()),
+            stop_reason: None,
+        });
+        return;
+    }
+    for choice in streaming_response.choices.iter_mut() {
+        choice.delta = delta();
+        choice.finish_reason = Some("stop".to_string());
+        // The original stop_reason describes the generation that was replaced
+        choice.stop_reason = None;
+    }
+}
+
+/// Applies the action of the most severe group flagging a streaming chunk, returning the decision
+/// and whether the stream ends with this chunk. Monitored hits pass the chunk through and redacted
+/// ones mask their spans in it. Blocking hits replace it with the fallback, or with the
+/// `truncate` action cut it at the first output detection, and end the stream, as anything after
+/// it would continue a blocked response: input hits flag the prompt, so nothing generated for it
+/// may be sent, and output hits flag the content in this chunk.
+fn apply_streaming_action(
+    streaming_response: &mut StreamingResponse,
+    route: &RouteContext,
+) -> (Decision, bool) {
+    match triggered_action(&streaming_response.detections, route) {
+        Some(GroupAction::Block) => match route.action {
+            DetectionAction::Fallback => {
+                match route.fallback_for(streaming_response.detections.as_ref()) {
+                    Some(fallback_message) => {
+                        apply_streaming_fallback(streaming_response, fallback_message);
+                        (Decision::Fallback, true)
+                    }
+                    None => (Decision::Passthrough, false),
+                }
+            }
+            DetectionAction::Truncate => {
+                if streaming_response
+                    .truncate_at_output_detections(route.truncation_notice.as_deref())
+                {
+                    (Decision::Truncate, true)
+                } else {
+                    (Decision::Passthrough, false)
+                }
+            }
+        },
+        Some(GroupAction::Redact) => {
+            let detector_actions = &route.detector_actions;
+            let redacted = streaming_response
+                .redact_output_detections(REDACTION_PLACEHOLDER, |id| {
+                    detector_actions.get(id) == Some(&GroupAction::Redact)
+                });
+            let decision = if redacted {
+                Decision::Redact
+            } else {
+                Decision::Passthrough
+            };
+            (decision, false)
+        }
+        Some(GroupAction::Monitor) => (Decision::Monitor, false),
+        None => (Decision::Passthrough, false),
+    }
+}
+
+/// Sends the payload to the orchestrator as a non-streaming request and applies the route's
+/// fallback message if anything was detected. What happened is recorded in `outcome` for the
+/// caller's request log.
+async fn complete_with_detections(
+    headers: HeaderMap,
+    Json(mut payload): Json<serde_json::Value>,
+    route: Arc<RouteContext>,
+    gateway_config: Arc<GatewayConfig>,
+    orchestrator_client: Arc<reqwest::Client>,
+    orchestrators: Arc<balancer::Balancer>,
+    outcome: &mut Outcome,
+) -> Result<(OrchestratorResponse, Duration), ApiError> {
+    let mut payload = payload.as_object_mut();
+
+    let urls = orchestrators.urls(&route.completions_path);
+    tracing::debug!("Orchestrator URLs: {:?}", urls);
+
+    if let Err(e) = insert_detectors(payload.as_mut().unwrap(), &headers, &route, false) {
+        outcome.record(Decision::Error, None);
+        return Err(e);
+    }
+    tracing::debug!(
+        "Payload after inserting detectors: {:?}",
+        logged(&payload, gateway_config.redact_logs)
     );
-    tracing::debug!("Payload after inserting detectors: {:?}", payload);
 
-    let response_result =
-        orchestrator_post_request(payload, &headers, &url, &orchestrator_client).await;
+    let orchestrator_started = Instant::now();
+    let response_result = orchestrator_post_request::<OrchestratorResponse>(
+        payload,
+        &headers,
+        &gateway_config,
+        &urls,
+        &orchestrator_client,
+        route.timeout,
+        route.retry_budget.as_deref(),
+    )
+    .await;
+    let orchestrator_duration = orchestrator_started.elapsed();
+    outcome.orchestrator_responded(orchestrator_duration);
 
     match response_result {
         Ok(mut orchestrator_response) => {
-            let detection =
-                check_payload_detections(&orchestrator_response.detections, route_fallback_message);
-            if let Some(message) = detection {
-                tracing::debug!("Fallback message triggered: {:?}", message);
-                orchestrator_response.choices = vec![message];
+            let mut decision = Decision::Passthrough;
+            apply_thresholds(&mut orchestrator_response.detections, &route);
+            // Summarize before capping so the counts cover everything that was detected
+            if route.return_detection_summary {
+                orchestrator_response.detection_summary = orchestrator_response
+                    .detections
+                    .as_ref()
+                    .map(Detections::summary);
             }
-            Ok(Json(json!(orchestrator_response)).into_response())
+            match triggered_action(&orchestrator_response.detections, &route) {
+                Some(GroupAction::Block) => match route.action {
+                    DetectionAction::Fallback => {
+                        let fallback = check_payload_detections(
+                            &orchestrator_response.detections,
+                            &route,
+                            &orchestrator_response.choices,
+                        );
+                        if let Some(choices) = fallback {
+                            tracing::debug!("Fallback message triggered: {:?}", choices);
+                            orchestrator_response.choices = choices;
+                            decision = Decision::Fallback;
+                        }
+                    }
+                    DetectionAction::Truncate => {
+                        if orchestrator_response
+                            .truncate_at_output_detections(route.truncation_notice.as_deref())
+                        {
+                            tracing::debug!("Output truncated at first detection");
+                            decision = Decision::Truncate;
+                        }
+                    }
+                },
+                Some(GroupAction::Redact) => {
+                    let detector_actions = &route.detector_actions;
+                    if orchestrator_response.redact_output_detections(REDACTION_PLACEHOLDER, |id| {
+                        detector_actions.get(id) == Some(&GroupAction::Redact)
+                    }) {
+                        tracing::debug!("Redacted detected output spans");
+                        decision = Decision::Redact;
+                    }
+                }
+                Some(GroupAction::Monitor) => {
+                    tracing::debug!("Monitored detectors flagged the response");
+                    decision = Decision::Monitor;
+                }
+                None => {}
+            }
+            outcome.record(decision, orchestrator_response.detections.as_ref());
+            // Capped last so the actions above see every detected span
+            cap_detections(
+                &mut orchestrator_response.detections,
+                gateway_config.max_detection_results,
+            );
+            Ok((orchestrator_response, orchestrator_duration))
+        }
+        Err(e) => {
+            outcome.orchestrator_failed();
+            Err(ApiError::from(e))
         }
-        Err(_) => Err((
-            StatusCode::INTERNAL_SERVER_ERROR,
-            response_result.err().unwrap().to_string(),
-        )),
     }
 }
 
 async fn handle_streaming_generation(
     headers: HeaderMap,
     Json(mut payload): Json<serde_json::Value>,
-    detectors: Vec<String>,
-    gateway_config: GatewayConfig,
-    route_fallback_message: Option<String>,
+    route: Arc<RouteContext>,
+    gateway_config: Arc<GatewayConfig>,
     orchestrator_client: Arc<reqwest::Client>,
-    scheme: String,
-) -> Result<impl IntoResponse, (StatusCode, String)> {
-    tracing::debug!("handle_streaming_generation called with payload: {:?}", payload);
-
-    let orchestrator_detectors =
-        get_orchestrator_detectors(detectors.clone(), gateway_config.detectors.clone());
-    tracing::debug!("Orchestrator detectors: {:?}", orchestrator_detectors);
+    orchestrators: Arc<balancer::Balancer>,
+    mut log: RequestLog,
+) -> Result<impl IntoResponse, ApiError> {
+    tracing::debug!(
+        "handle_streaming_generation called with payload: {:?}",
+        logged(&payload, gateway_config.redact_logs)
+    );
 
     let mut payload = payload.as_object_mut();
 
-    let url: String = match gateway_config.orchestrator.port {
-        Some(port) => format!(
-            "{}://{}:{}/api/v2/chat/completions-detection",
-            scheme,
-            gateway_config.orchestrator.host,
-            port
-        ),
-        None => format!(
-            "{}://{}/api/v2/chat/completions-detection",
-            scheme,
-            gateway_config.orchestrator.host
-        ),
-    };
-    tracing::debug!("Orchestrator URL: {}", url);
+    let urls = orchestrators.urls(&route.completions_path);
+    tracing::debug!("Orchestrator URLs: {:?}", urls);
 
-    payload.as_mut().unwrap().insert(
-        "detectors".to_string(),
-        serde_json::to_value(&orchestrator_detectors).unwrap(),
+    if let Err(e) = insert_detectors(payload.as_mut().unwrap(), &headers, &route, true) {
+        log.record(Decision::Error, None);
+        return Err(e);
+    }
+    tracing::debug!(
+        "Payload after inserting detectors: {:?}",
+        logged(&payload, gateway_config.redact_logs)
     );
-    tracing::debug!("Payload after inserting detectors: {:?}", payload);
 
-    let response_result =
-        orchestrator_streaming_request(payload, &headers, &url, &orchestrator_client).await;
+    let response_result = reconnecting_streaming_request(
+        payload.cloned().unwrap_or_default(),
+        headers,
+        gateway_config.clone(),
+        urls,
+        orchestrator_client,
+        route.retry_budget.clone(),
+        route.timeout,
+    )
+    .await;
+    log.orchestrator_responded();
 
+    let max_detection_results = gateway_config.max_detection_results;
+    let malformed_frame_policy = gateway_config.malformed_frame_policy;
+    let redact_logs = gateway_config.redact_logs;
+    let trailer_summary = route
+        .detections_trailer
+        .then(|| Arc::new(Mutex::new(DetectionSummary::default())));
+    let stream_summary = trailer_summary.clone();
     match response_result {
         Ok(stream) => {
-            let sse_stream = stream.map(move |chunk_result| -> Result<Event, anyhow::Error> {
+            // Each frame yields the events to send and whether the stream ends after them. Errors
+            // always end it, as the client cannot tell what, if anything, went missing.
+            let mut open_choices = OpenChoices::default();
+            let sse_stream = stream.map(move |chunk_result| -> (Vec<Event>, bool) {
                 match chunk_result {
                     Ok(chunk) => {
                         // Check if we need to apply fallback message
-                        if let Ok(mut streaming_response) = serde_json::from_str::<StreamingResponse>(&chunk) {
-                            if let Some(fallback_message) = &route_fallback_message {
-                                if streaming_response.detections.is_some() {
-                                    // Apply fallback message to the first chunk
-                                    if streaming_response.choices.len() > 0 {
-                                        streaming_response.choices[0].delta = StreamingDelta {
-                                            content: Some(fallback_message.clone()),
-                                            role: Some("assistant".to_string()),
-                                            tool_calls: None,
-                                        };
-                                        streaming_response.choices[0].finish_reason = Some("stop".to_string());
-                                    }
-                                }
+                        if let Ok(mut streaming_response) =
+                            serde_json::from_str::<StreamingResponse>(&chunk)
+                        {
+                            apply_thresholds(&mut streaming_response.detections, &route);
+                            let (decision, blocked) =
+                                apply_streaming_action(&mut streaming_response, &route);
+                            log.record(decision, streaming_response.detections.as_ref());
+                            if let (Some(summary), Some(detections)) =
+                                (&stream_summary, &streaming_response.detections)
+                            {
+                                summary.lock().unwrap().add(detections.summary());
                             }
+                            cap_detections(
+                                &mut streaming_response.detections,
+                                max_detection_results,
+                            );
 
                             match serde_json::to_string(&streaming_response) {
-                                Ok(json_str) => Ok(Event::default().data(json_str)),
+                                Ok(json_str) => {
+                                    open_choices.track(&streaming_response);
+                                    (vec![Event::default().data(json_str)], blocked)
+                                }
                                 Err(e) => {
-                                    tracing::error!("Failed to serialize streaming response: {}", e);
-                                    Ok(Event::default().data("{\"error\": \"serialization failed\"}"))
+                                    tracing::error!(
+                                        "Failed to serialize streaming response: {}",
+                                        e
+                                    );
+                                    let error = ApiError::internal("serialization failed");
+                                    (open_choices.fail(error), true)
                                 }
                             }
                         } else {
-                            // If it's not a valid JSON chunk, pass it through as-is
-                            Ok(Event::default().data(chunk))
+                            tracing::warn!(
+                                "Malformed streaming frame: {}",
+                                logged_body(&chunk, redact_logs)
+                            );
+                            match malformed_frame_policy {
+                                MalformedFramePolicy::Passthrough => {
+                                    (vec![Event::default().data(chunk)], false)
+                                }
+                                MalformedFramePolicy::Drop => (Vec::new(), false),
+                                MalformedFramePolicy::Error => {
+                                    log.record(Decision::Error, None);
+                                    let error = ApiError::new(
+                                        StatusCode::BAD_GATEWAY,
+                                        "upstream_error",
+                                        "malformed frame from orchestrator",
+                                    );
+                                    (open_choices.fail(error), true)
+                                }
+                            }
                         }
                     }
                     Err(e) => {
                         tracing::error!("Error processing streaming chunk: {}", e);
-                        Ok(Event::default().data(format!("{{\"error\": \"{}\"}}", e)))
+                        log.orchestrator_failed();
+                        (open_choices.fail(ApiError::from(e)), true)
                     }
                 }
             });
+            // The stream ends as soon as a frame closes it, dropping the orchestrator stream
+            // rather than waiting for it to send another frame or go idle first
+            let sse_stream =
+                futures::stream::unfold((sse_stream, false), |(mut frames, closed)| async move {
+                    if closed {
+                        return None;
+                    }
+                    let (events, close) = frames.next().await?;
+                    Some((events, (frames, close)))
+                })
+                .flat_map(|events| {
+                    futures::stream::iter(events.into_iter().map(Ok::<_, anyhow::Error>))
+                })
+                // Sent exactly once, after the orchestrator stream has ended for whatever reason
+                .chain(futures::stream::once(futures::future::ready(Ok(
+                    done_event(),
+                ))));
 
-            Ok(Sse::new(sse_stream)
+            // Optionally tell the client the stream is alive before the first token arrives, so
+            // slow-starting generations aren't mistaken for a dead connection
+            let initial_keep_alive = gateway_config
+                .sse_initial_keep_alive
+                .then(|| Ok(Event::default().comment("keep-alive")));
+            let sse_stream = futures::stream::iter(initial_keep_alive).chain(sse_stream);
+
+            let response = Sse::new(sse_stream)
                 .keep_alive(KeepAlive::default())
-                .into_response())
+                .into_response();
+            Ok(match trailer_summary {
+                Some(summary) => trailers::with_detections_trailer(response, summary),
+                None => response,
+            })
         }
         Err(e) => {
             tracing::error!("Streaming request failed: {}", e);
-            Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))
+            log.orchestrator_failed();
+            Err(ApiError::from(e))
+        }
+    }
+}
+
+/// The base URL of every configured orchestrator host. A host's own port takes precedence over
+/// the orchestrator `port`.
+fn orchestrator_base_urls(orchestrator: &config::OrchestratorConfig, scheme: &str) -> Vec<String> {
+    orchestrator
+        .host
+        .iter()
+        .map(
+            |host| match (config::split_host_port(host), orchestrator.port) {
+                ((_, None), Some(port)) => format!("{}://{}:{}", scheme, host, port),
+                _ => format!("{}://{}", scheme, host),
+            },
+        )
+        .collect()
+}
+
+const DEFAULT_API_PATH: &str = "/api/v2/chat/completions-detection";
+
+/// The orchestrator's text completions endpoint, behind each route's `/v1/completions`.
+const COMPLETIONS_API_PATH: &str = "/api/v2/text/completions-detection";
+
+/// Resolves the chat completions path; it is taken from the route, then the orchestrator
+/// config, then the default.
+fn chat_completions_path<'a>(
+    gateway_config: &'a GatewayConfig,
+    route_api_path: Option<&'a str>,
+) -> &'a str {
+    route_api_path
+        .or(gateway_config.orchestrator.api_path.as_deref())
+        .unwrap_or(DEFAULT_API_PATH)
+}
+
+/// Readiness probe: the gateway is ready when the orchestrator's health endpoint answers with
+/// any 2xx within a short timeout.
+async fn orchestrator_ready(client: &reqwest::Client, health_url: &str) -> bool {
+    match client
+        .get(health_url)
+        .timeout(std::time::Duration::from_secs(2))
+        .send()
+        .await
+    {
+        Ok(response) => response.status().is_success(),
+        Err(e) => {
+            tracing::warn!("Orchestrator health check at {} failed: {}", health_url, e);
+            false
         }
     }
 }
 
-fn build_orchestrator_client(hostname: &str) -> Result<(reqwest::Client, String), anyhow::Error> {
-    use openssl::pkcs12::Pkcs12;
-    use openssl::pkey::PKey;
-    use openssl::x509::X509;
+fn build_orchestrator_client(
+    orchestrator: &config::OrchestratorConfig,
+) -> Result<(reqwest::Client, String), anyhow::Error> {
     use reqwest::tls::{Certificate, Identity};
     use reqwest::Client;
     use std::fs;
 
-    let cert_path = "/etc/tls/private/tls.crt";
-    let key_path = "/etc/tls/private/tls.key";
-    let ca_path = "/etc/tls/ca/service-ca.crt";
+    let cert_path = &orchestrator.tls.cert_path;
+    let key_path = &orchestrator.tls.key_path;
+    let ca_path = &orchestrator.tls.ca_path;
 
     let mut builder = Client::builder();
     let mut scheme = String::from("http");
 
+    if let Some(version) = &orchestrator.min_tls_version {
+        let version = config::parse_tls_version(version).map_err(anyhow::Error::msg)?;
+        builder = builder.min_tls_version(version);
+    }
+
     // Add custom CA if it exists
     if fs::metadata(ca_path).is_ok() {
         let ca_cert = fs::read(ca_path)?;
         let ca = Certificate::from_pem(&ca_cert)?;
         tracing::debug!("Adding custom CA certificate from {}", ca_path);
         builder = builder.add_root_certificate(ca);
-        if hostname == "localhost" {
+        if orchestrator
+            .host
+            .iter()
+            .any(|host| config::split_host_port(host).0 == "localhost")
+        {
             builder = builder.danger_accept_invalid_hostnames(true); // the orchestrator's certificate is only valid for the service's DNS name
         }
     }
@@ -379,17 +2485,7 @@ fn build_orchestrator_client(hostname: &str) -> Result<(reqwest::Client, String)
         let cert_pem = fs::read(cert_path)?;
         let key_pem = fs::read(key_path)?;
 
-        // Load cert and key using openssl
-        let cert = X509::from_pem(&cert_pem)?;
-        let key = PKey::private_key_from_pem(&key_pem)?;
-
-        // Create PKCS#12 archive in memory (no password)
-        let mut pk_builder = Pkcs12::builder();
-        pk_builder.name("identity");
-        pk_builder.pkey(&key);
-        pk_builder.cert(&cert);
-        let pkcs12 = pk_builder.build2("")?;
-        let pkcs12_der = pkcs12.to_der()?;
+        let pkcs12_der = tls::pkcs12_der_from_pem(&cert_pem, &key_pem)?;
 
         // Load as native-tls Identity
         let identity = Identity::from_pkcs12_der(&pkcs12_der, "")?;
@@ -405,143 +2501,4843 @@ fn build_orchestrator_client(hostname: &str) -> Result<(reqwest::Client, String)
     Ok((builder.build()?, scheme))
 }
 
-async fn orchestrator_post_request(
-    payload: Option<&mut Map<String, Value>>,
-    headers: &HeaderMap,
-    url: &str,
-    client: &reqwest::Client,
-) -> Result<OrchestratorResponse, anyhow::Error> {
+/// The client a route talks to the orchestrator with: the shared one, or one with its own
+/// connection pool when the route asks for isolation, so heavy traffic on another route cannot
+/// exhaust its connections.
+fn route_client(
+    route: &config::RouteConfig,
+    shared: &Arc<reqwest::Client>,
+    orchestrator: &config::OrchestratorConfig,
+) -> Result<Arc<reqwest::Client>, String> {
+    if !route.isolated_client {
+        return Ok(shared.clone());
+    }
     tracing::debug!(
-        "Sending POST request to {} with payload: {:?}",
-        url,
-        payload
+        "Building isolated orchestrator client for route {}",
+        route.name
     );
+    let (client, _) = build_orchestrator_client(orchestrator).map_err(|e| {
+        format!(
+            "failed to build orchestrator client for route {}: {}",
+            route.name, e
+        )
+    })?;
+    Ok(Arc::new(client))
+}
 
-    let mut req = client.post(url).json(&payload);
-
-    // Forward authorization headers
+/// Copies the subset of inbound headers the orchestrator should see onto an outgoing request:
+/// authorization, `x-forwarded-*`, the W3C trace context and the configured request-id header.
+/// The tenant header is copied too; by then `jwt::resolve_tenant` has replaced any value the
+/// client sent with the one from its token.
+fn forward_headers(
+    req: reqwest::RequestBuilder,
+    headers: &HeaderMap,
+    gateway_config: &GatewayConfig,
+) -> reqwest::RequestBuilder {
+    let limits = &gateway_config.forwarded_headers;
+    let mut forwarded = HeaderMap::new();
+    let mut count = 0;
+    let mut bytes = 0;
     for (name, value) in headers.iter() {
-        // filter out headers t
-        tracing::debug!("Header {}: {:?}", name, value);
+        tracing::debug!("Header {}: {:?}", name, logged_header(name, value));
         let name_str = name.as_str().to_ascii_lowercase();
-        if name_str == "authorization" {
-            req = req.header(name, value);
+        // The gateway's own API key is never passed on to the orchestrator
+        if name_str == "authorization"
+            && auth::is_gateway_credential(&gateway_config.api_keys, value)
+        {
+            continue;
+        }
+        if name_str == "authorization"
+            || name_str == trace_context::TRACEPARENT
+            || name_str == trace_context::TRACESTATE
+            || name_str.starts_with("x-forwarded")
+            || name_str.eq_ignore_ascii_case(&gateway_config.request_id_header)
+            || gateway_config
+                .tenant_claim
+                .as_ref()
+                .is_some_and(|tenant| name_str.eq_ignore_ascii_case(&tenant.header))
+        {
+            let size = name.as_str().len() + value.len();
+            if count + 1 > limits.max_count || bytes + size > limits.max_bytes {
+                tracing::warn!(
+                    "Dropping forwarded header {}: limit of {} headers / {} bytes reached",
+                    name,
+                    limits.max_count,
+                    limits.max_bytes
+                );
+                continue;
+            }
+            count += 1;
+            bytes += size;
+            forwarded.append(name, value.clone());
         }
-        if name_str.starts_with("x-forwarded") {
-            req = req.header(name, value);
+    }
+    // Configured headers replace any forwarded or default header of the same name, such as the
+    // `content-type` set for the JSON body, rather than being sent alongside it.
+    for (name, value) in &gateway_config.orchestrator_headers {
+        if let (Ok(name), Ok(value)) = (
+            HeaderName::from_bytes(name.as_bytes()),
+            HeaderValue::from_str(value),
+        ) {
+            forwarded.insert(name, value);
         }
     }
+    req.headers(forwarded)
+}
 
-    let response_result = req.send().await;
-    let response = match response_result {
-        Ok(resp) => resp,
-        Err(e) => {
-            tracing::error!("Failed to send request or connect to orchestrator: {:?}", e);
-            if let Some(source) = e.source() {
-                tracing::error!("Underlying error: {:?}", source);
+/// Sends a request to each of `urls` in turn until one of them can be connected to. Only
+/// connection failures move on to the next URL, as the request never reached that orchestrator;
+/// anything else, including a timeout, is returned as is.
+async fn send_with_failover<F, Fut>(
+    urls: &[String],
+    mut send: F,
+) -> Result<reqwest::Response, OrchestratorError>
+where
+    F: FnMut(&str) -> Fut,
+    Fut: Future<Output = Result<reqwest::Response, OrchestratorError>>,
+{
+    let (last, others) = urls
+        .split_last()
+        .expect("at least one orchestrator host is configured");
+    for url in others {
+        match send(url).await {
+            Err(OrchestratorError::Connect(e)) => {
+                tracing::warn!(
+                    "Failed to connect to orchestrator at {}, trying the next: {}",
+                    url,
+                    e
+                );
             }
-            // print out the error chain for more details
-            let mut source = e.source();
+            result => return result,
+        }
+    }
+    send(last).await
+}
+
+/// Sends a non-streaming request, retrying it up to the orchestrator's `max_retries` times on
+/// transient failures: connection errors and 502, 503 or 504 responses. Retries back off
+/// exponentially from `backoff_ms` and each takes one from the `budget`, if there is one.
+async fn orchestrator_post_request<T: DeserializeOwned>(
+    mut payload: Option<&mut Map<String, Value>>,
+    headers: &HeaderMap,
+    gateway_config: &GatewayConfig,
+    urls: &[String],
+    client: &reqwest::Client,
+    timeout: Option<Duration>,
+    budget: Option<&retry_budget::RetryBudget>,
+) -> Result<T, OrchestratorError> {
+    let orchestrator = &gateway_config.orchestrator;
+    let mut attempt = 0;
+    loop {
+        let result = orchestrator_post_attempt(
+            payload.as_deref_mut(),
+            headers,
+            gateway_config,
+            urls,
+            client,
+            timeout,
+        )
+        .await;
+        let error = match result {
+            Err(e) if attempt < orchestrator.max_retries && is_transient(&e) => e,
+            result => return result,
+        };
+        if budget.is_some_and(|budget| !budget.try_acquire()) {
+            tracing::warn!(
+                "Orchestrator request failed ({}), retry budget exhausted",
+                error
+            );
+            return Err(error);
+        }
+        let backoff = backoff_ms(orchestrator.backoff_ms, attempt);
+        attempt += 1;
+        tracing::warn!(
+            "Orchestrator request failed ({}), retrying in {}ms (attempt {} of {})",
+            error,
+            backoff,
+            attempt,
+            orchestrator.max_retries
+        );
+        tokio::time::sleep(Duration::from_millis(backoff)).await;
+    }
+}
+
+/// Longest wait before an orchestrator retry, however many attempts came before it.
+const MAX_BACKOFF_MS: u64 = 30_000;
+
+/// The wait before retry number `attempt`, counting from 0: `base_ms`, doubled for each earlier
+/// attempt and capped at `MAX_BACKOFF_MS`.
+fn backoff_ms(base_ms: u64, attempt: u32) -> u64 {
+    let factor = 1u64.checked_shl(attempt).unwrap_or(u64::MAX);
+    base_ms.saturating_mul(factor).min(MAX_BACKOFF_MS)
+}
+
+/// Failures a repeated request may well not run into: a failed connection, or a 502, 503 or 504.
+/// Timeouts and cut-off bodies are left out, as the orchestrator may still be working on, or
+/// have acted on, the first request.
+fn is_transient(error: &OrchestratorError) -> bool {
+    match error {
+        OrchestratorError::Connect(_) => true,
+        OrchestratorError::Status { code, .. } => matches!(
+            *code,
+            StatusCode::BAD_GATEWAY | StatusCode::SERVICE_UNAVAILABLE | StatusCode::GATEWAY_TIMEOUT
+        ),
+        _ => false,
+    }
+}
+
+async fn orchestrator_post_attempt<T: DeserializeOwned>(
+    payload: Option<&mut Map<String, Value>>,
+    headers: &HeaderMap,
+    gateway_config: &GatewayConfig,
+    urls: &[String],
+    client: &reqwest::Client,
+    timeout: Option<Duration>,
+) -> Result<T, OrchestratorError> {
+    tracing::debug!(
+        "Sending POST request to {:?} with payload: {:?}",
+        urls,
+        logged(&payload, gateway_config.redact_logs)
+    );
+
+    let response_result = send_with_failover(urls, |url| {
+        let mut req = forward_headers(client.post(url).json(&payload), headers, gateway_config);
+        if let Some(timeout) = timeout {
+            req = req.timeout(timeout);
+        }
+        req.send()
+            .map(|result| result.map_err(OrchestratorError::from))
+    })
+    .await;
+    let response = match response_result {
+        Ok(resp) => resp,
+        Err(e) => {
+            tracing::error!("{}", e);
+            // print out the error chain for more details
+            let mut source = e.source();
             while let Some(s) = source {
                 tracing::error!("Caused by: {:?}", s);
                 source = s.source();
             }
-            return Err(anyhow::anyhow!(
-                "Failed to send request or connect to orchestrator: {:?}",
-                e
-            ));
+            return Err(e);
         }
     };
 
     let status = response.status();
-    let text = response.text().await.unwrap_or_else(|e| {
-        tracing::error!("Failed to read response body: {:?}", e);
-        String::new()
-    });
-    tracing::debug!("Received response status: {}, body: {}", status, text);
+    let text = match response.text().await {
+        Ok(text) => text,
+        Err(e) if e.is_timeout() => return Err(OrchestratorError::Timeout(e)),
+        Err(e) => {
+            let err = OrchestratorError::IncompleteBody(e);
+            tracing::error!("{}", err);
+            return Err(err);
+        }
+    };
+    tracing::debug!(
+        "Received response status: {}, body: {}",
+        status,
+        logged_body(&text, gateway_config.redact_logs)
+    );
 
     if !status.is_success() {
         // Return the error with the status code and response body
         tracing::error!("Orchestrator returned error status {}: {}", status, text);
-        return Err(anyhow::anyhow!(
-            "Orchestrator returned error status {}: {}",
-            status,
-            text
-        ));
+        return Err(OrchestratorError::Status {
+            code: status,
+            body: text,
+        });
+    }
+
+    if text.trim().is_empty() {
+        let err = OrchestratorError::EmptyBody;
+        tracing::error!("{}", err);
+        return Err(err);
     }
 
     let json: serde_json::Value = serde_json::from_str(&text)?;
-    tracing::debug!("Parsed JSON response: {:?}", json);
-    Ok(serde_json::from_value(json).expect("unexpected json response from request"))
+    tracing::debug!(
+        "Parsed JSON response: {:?}",
+        logged(&json, gateway_config.redact_logs)
+    );
+    serde_json::from_value(json).map_err(|e| {
+        let err = OrchestratorError::Parse(format!(
+            "unexpected response shape ({}): {}",
+            e,
+            body_snippet(&text)
+        ));
+        tracing::error!("{}", err);
+        err
+    })
+}
+
+/// The start of a response body, short enough to include in an error message.
+fn body_snippet(body: &str) -> String {
+    const MAX_CHARS: usize = 200;
+    match body.char_indices().nth(MAX_CHARS) {
+        Some((end, _)) => format!("{}...", &body[..end]),
+        None => body.to_string(),
+    }
+}
+
+/// Opens the orchestrator stream, reconnecting with the same payload up to
+/// `stream_reconnect_attempts` times if the connection fails before any content has arrived.
+/// Once content has been received a failure is passed on as is, since replaying the request
+/// would send the client duplicate tokens. Each reconnection also takes a retry from the
+/// `retry_budget`, if one is configured.
+async fn reconnecting_streaming_request(
+    payload: Map<String, Value>,
+    headers: HeaderMap,
+    gateway_config: Arc<GatewayConfig>,
+    urls: Vec<String>,
+    client: Arc<reqwest::Client>,
+    budget: Option<Arc<retry_budget::RetryBudget>>,
+    idle_timeout: Option<Duration>,
+) -> Result<BoxStream<'static, Result<String, OrchestratorError>>, OrchestratorError> {
+    struct Reconnect {
+        payload: Map<String, Value>,
+        headers: HeaderMap,
+        gateway_config: Arc<GatewayConfig>,
+        urls: Vec<String>,
+        client: Arc<reqwest::Client>,
+        budget: Option<Arc<retry_budget::RetryBudget>>,
+        idle_timeout: Option<Duration>,
+        attempt: u32,
+    }
+
+    impl Reconnect {
+        async fn open(
+            &mut self,
+        ) -> Result<BoxStream<'static, Result<String, OrchestratorError>>, OrchestratorError>
+        {
+            loop {
+                let result = orchestrator_streaming_request(
+                    Some(&mut self.payload),
+                    &self.headers,
+                    &self.gateway_config,
+                    &self.urls,
+                    &self.client,
+                    self.idle_timeout,
+                )
+                .await;
+                match result {
+                    Ok(stream) => return Ok(stream.boxed()),
+                    Err(e) if self.retry(&e).await => continue,
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+
+        /// Waits out the backoff and returns true if another attempt should be made.
+        async fn retry(&mut self, error: &OrchestratorError) -> bool {
+            // Nothing has been sent to the client yet, so any failure of the connection is worth
+            // another attempt; a request that cannot be built would fail again
+            let retryable = match error {
+                OrchestratorError::Connect(_)
+                | OrchestratorError::IncompleteBody(_)
+                | OrchestratorError::Timeout(_)
+                | OrchestratorError::IdleTimeout(_) => true,
+                OrchestratorError::Request(e) => e.is_request(),
+                _ => false,
+            };
+            if !retryable || self.attempt >= self.gateway_config.stream_reconnect_attempts {
+                return false;
+            }
+            if self
+                .budget
+                .as_ref()
+                .is_some_and(|budget| !budget.try_acquire())
+            {
+                tracing::warn!(
+                    "Orchestrator stream failed before any content ({}), retry budget exhausted",
+                    error
+                );
+                return false;
+            }
+            let backoff = backoff_ms(
+                self.gateway_config.stream_reconnect_backoff_ms,
+                self.attempt,
+            );
+            self.attempt += 1;
+            tracing::warn!(
+                "Orchestrator stream failed before any content ({}), reconnecting in {}ms",
+                error,
+                backoff
+            );
+            tokio::time::sleep(std::time::Duration::from_millis(backoff)).await;
+            true
+        }
+    }
+
+    let mut reconnect = Reconnect {
+        payload,
+        headers,
+        gateway_config,
+        urls,
+        client,
+        budget,
+        idle_timeout,
+        attempt: 0,
+    };
+    let stream = reconnect.open().await?;
+    if reconnect.gateway_config.stream_reconnect_attempts == 0 {
+        return Ok(stream);
+    }
+
+    let stream = futures::stream::unfold(
+        (stream, reconnect, false),
+        |(mut stream, mut reconnect, mut delivered)| async move {
+            loop {
+                match stream.next().await {
+                    Some(Ok(data)) => {
+                        delivered = true;
+                        return Some((Ok(data), (stream, reconnect, delivered)));
+                    }
+                    Some(Err(e)) if !delivered && reconnect.retry(&e).await => {
+                        match reconnect.open().await {
+                            Ok(reopened) => stream = reopened,
+                            Err(e) => return Some((Err(e), (stream, reconnect, delivered))),
+                        }
+                    }
+                    Some(Err(e)) => return Some((Err(e), (stream, reconnect, delivered))),
+                    None => return None,
+                }
+            }
+        },
+    );
+    Ok(stream.boxed())
 }
 
+/// Opens a stream of SSE event data from the orchestrator. With an `idle_timeout`, both the
+/// wait for the response and every wait for the next chunk are bounded by it; a stream that
+/// goes quiet ends with an `IdleTimeout` error.
 async fn orchestrator_streaming_request(
     payload: Option<&mut Map<String, Value>>,
     headers: &HeaderMap,
-    url: &str,
+    gateway_config: &GatewayConfig,
+    urls: &[String],
     client: &reqwest::Client,
-) -> Result<impl futures::Stream<Item = Result<String, anyhow::Error>>, anyhow::Error> {
+    idle_timeout: Option<Duration>,
+) -> Result<impl futures::Stream<Item = Result<String, OrchestratorError>>, OrchestratorError> {
     tracing::debug!(
-        "Sending streaming POST request to {} with payload: {:?}",
-        url,
-        payload
+        "Sending streaming POST request to {:?} with payload: {:?}",
+        urls,
+        logged(&payload, gateway_config.redact_logs)
     );
 
-    let mut req = client.post(url).json(&payload);
+    let response = send_with_failover(urls, |url| {
+        let send = forward_headers(client.post(url).json(&payload), headers, gateway_config).send();
+        async move {
+            match idle_timeout {
+                Some(timeout) => tokio::time::timeout(timeout, send)
+                    .await
+                    .map_err(|_| OrchestratorError::IdleTimeout(timeout))?
+                    .map_err(OrchestratorError::from),
+                None => send.await.map_err(OrchestratorError::from),
+            }
+        }
+    })
+    .await?;
 
-    // Forward authorization headers
-    for (name, value) in headers.iter() {
-        tracing::debug!("Header {}: {:?}", name, value);
-        let name_str = name.as_str().to_ascii_lowercase();
-        if name_str == "authorization" {
-            req = req.header(name, value);
+    let status = response.status();
+    if !status.is_success() {
+        let error_text = response
+            .text()
+            .await
+            .unwrap_or_else(|_| "Unknown error".to_string());
+        let err = OrchestratorError::Status {
+            code: status,
+            body: error_text,
+        };
+        tracing::error!("{}", err);
+        return Err(err);
+    }
+
+    // Chunks can split an event anywhere, so events are reassembled before being passed on
+    let mut decoder = sse::SseDecoder::default();
+    let chunks = response
+        .bytes_stream()
+        .map(|chunk| chunk.map_err(OrchestratorError::from));
+    let chunks = match idle_timeout {
+        Some(timeout) => with_idle_timeout(chunks.boxed(), timeout),
+        None => chunks.boxed(),
+    };
+    let event_stream = chunks
+        .map(Some)
+        .chain(futures::stream::once(futures::future::ready(None)))
+        .flat_map(move |chunk| {
+            let events = match chunk {
+                Some(Ok(chunk)) => decoder.push(&chunk),
+                Some(Err(e)) => return futures::stream::iter(vec![Err(e)]),
+                None => decoder.finish().map(Vec::from_iter),
+            };
+            let events = match events {
+                Ok(events) => events.into_iter().map(Ok).collect(),
+                Err(e) => vec![Err(OrchestratorError::Parse(format!(
+                    "Invalid UTF-8 in stream: {}",
+                    e
+                )))],
+            };
+            futures::stream::iter(events)
+        })
+        // Anything the orchestrator sends after [DONE] is not part of the stream
+        .take_while(|item| futures::future::ready(!matches!(item, Ok(data) if data == "[DONE]")))
+        // Events carrying no data are not forwarded
+        .filter(|item| futures::future::ready(!matches!(item, Ok(data) if data.is_empty())));
+
+    Ok(event_stream)
+}
+
+/// Ends `stream` with an `IdleTimeout` error if it yields nothing for `timeout`.
+fn with_idle_timeout<T: Send + 'static>(
+    stream: BoxStream<'static, Result<T, OrchestratorError>>,
+    timeout: Duration,
+) -> BoxStream<'static, Result<T, OrchestratorError>> {
+    futures::stream::unfold(Some(stream), move |stream| async move {
+        let mut stream = stream?;
+        match tokio::time::timeout(timeout, stream.next()).await {
+            Ok(Some(item)) => Some((item, Some(stream))),
+            Ok(None) => None,
+            Err(_) => {
+                tracing::warn!("Orchestrator stream idle for {:?}, closing it", timeout);
+                Some((Err(OrchestratorError::IdleTimeout(timeout)), None))
+            }
         }
-        if name_str.starts_with("x-forwarded") {
-            req = req.header(name, value);
+    })
+    .boxed()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config::{OrchestratorConfig, OrchestratorTlsConfig, RouteConfig};
+    use std::net::SocketAddr;
+    use std::sync::Mutex;
+
+    /// Requests received by the mock orchestrator, as (headers, body) pairs.
+    type Captured = Arc<Mutex<Vec<(HeaderMap, Value)>>>;
+
+    /// The smallest conversation the gateway accepts.
+    fn user_messages() -> Value {
+        json!([{"role": "user", "content": "hi"}])
+    }
+
+    fn completion_response(detections: Value) -> Value {
+        json!({
+            "id": "chatcmpl-test",
+            "choices": [{
+                "finish_reason": "stop",
+                "index": 0,
+                "logprobs": null,
+                "message": {
+                    "content": "generated text",
+                    "refusal": null,
+                    "role": "assistant",
+                    "tool_calls": null,
+                    "audio": null
+                }
+            }],
+            "created": 1741182909,
+            "model": "test-model",
+            "service_tier": null,
+            "system_fingerprint": null,
+            "object": "chat.completion",
+            "usage": {"completion_tokens": 1, "prompt_tokens": 1, "total_tokens": 2},
+            "detections": detections,
+            "warnings": null
+        })
+    }
+
+    fn output_detections() -> Value {
+        json!({
+            "input": null,
+            "output": [{
+                "choice_index": 0,
+                "results": [{
+                    "start": 0,
+                    "end": 9,
+                    "text": "generated",
+                    "detection_type": "pii",
+                    "detection": "EmailAddress",
+                    "detector_id": "regex-language",
+                    "score": 1.0
+                }]
+            }]
+        })
+    }
+
+    async fn spawn_server(app: Router) -> SocketAddr {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        addr
+    }
+
+    /// Starts a mock orchestrator that records every request and replies with `response`.
+    async fn spawn_orchestrator(response: Value) -> (SocketAddr, Captured) {
+        let captured: Captured = Arc::new(Mutex::new(Vec::new()));
+        let recorder = captured.clone();
+        let app = Router::new().route(
+            "/api/v2/chat/completions-detection",
+            post(
+                move |headers: HeaderMap, Json(body): Json<Value>| async move {
+                    recorder.lock().unwrap().push((headers, body));
+                    Json(response)
+                },
+            ),
+        );
+        (spawn_server(app).await, captured)
+    }
+
+    /// Starts a mock orchestrator that records every request and replies with the raw SSE `body`.
+    async fn spawn_streaming_orchestrator(body: &'static str) -> (SocketAddr, Captured) {
+        let captured: Captured = Arc::new(Mutex::new(Vec::new()));
+        let recorder = captured.clone();
+        let app = Router::new().route(
+            "/api/v2/chat/completions-detection",
+            post(
+                move |headers: HeaderMap, Json(request): Json<Value>| async move {
+                    recorder.lock().unwrap().push((headers, request));
+                    ([("content-type", "text/event-stream")], body)
+                },
+            ),
+        );
+        (spawn_server(app).await, captured)
+    }
+
+    /// Streaming orchestrator that sends each frame as its own chunk, slightly apart, so the
+    /// gateway sees them individually.
+    async fn spawn_chunked_orchestrator(frames: &[impl ToString]) -> SocketAddr {
+        let frames: Vec<String> = frames.iter().map(ToString::to_string).collect();
+        let app = Router::new().route(
+            "/api/v2/chat/completions-detection",
+            post(move || async move {
+                let body = futures::stream::iter(frames).then(|frame| async move {
+                    tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+                    Ok::<_, std::convert::Infallible>(frame)
+                });
+                (
+                    [("content-type", "text/event-stream")],
+                    axum::body::Body::from_stream(body),
+                )
+            }),
+        );
+        spawn_server(app).await
+    }
+
+    /// Extracts the `data:` payloads from a raw SSE response body, checking that the stream ends
+    /// with exactly one `[DONE]`, which is left out.
+    fn sse_data(body: &str) -> Vec<&str> {
+        let mut data: Vec<&str> = body
+            .lines()
+            .filter_map(|line| line.strip_prefix("data: "))
+            .collect();
+        assert_eq!(data.pop(), Some("[DONE]"), "{}", body);
+        assert!(!data.contains(&"[DONE]"), "{}", body);
+        data
+    }
+
+    fn test_config(orchestrator: SocketAddr) -> GatewayConfig {
+        GatewayConfig {
+            orchestrator: OrchestratorConfig {
+                host: vec![orchestrator.ip().to_string()],
+                port: Some(orchestrator.port()),
+                ..Default::default()
+            },
+            routes: vec![RouteConfig {
+                name: "test".to_string(),
+                detectors: vec![],
+                fallback_message: Some("fallback".to_string()),
+                ..Default::default()
+            }],
+            ..Default::default()
         }
     }
 
-    let response = req
-        .send()
-        .await
-        .context("Failed to send request or connect to orchestrator: {}")?;
+    async fn spawn_gateway(gateway_config: &GatewayConfig) -> SocketAddr {
+        let app = build_router(
+            Arc::new(gateway_config.clone()),
+            Arc::new(reqwest::Client::new()),
+            "http".to_string(),
+            &ProcessState::new(gateway_config),
+        )
+        .unwrap();
+        spawn_server(app).await
+    }
 
-    let status = response.status();
-    if !status.is_success() {
-        let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
-        let err_msg = format!("Orchestrator returned error status {}: {}", status, error_text);
-        tracing::error!("{}", err_msg);
-        anyhow::bail!(err_msg);
-    }
-
-    let stream = response.bytes_stream();
-    let chunk_stream = stream.map(|chunk_result| {
-        chunk_result
-            .map_err(|e| anyhow::anyhow!("Failed to read chunk: {}", e))
-            .and_then(|chunk| {
-                let chunk_str = String::from_utf8(chunk.to_vec())
-                    .map_err(|e| anyhow::anyhow!("Invalid UTF-8 in chunk: {}", e))?;
-
-                // Parse SSE format and extract data
-                let lines: Vec<&str> = chunk_str.lines().collect();
-                let mut data_lines = Vec::new();
-
-                for line in lines {
-                    if line.starts_with("data: ") {
-                        let data = &line[6..]; // Remove "data: " prefix
-                        if data != "[DONE]" {
-                            data_lines.push(data.to_string());
-                        }
-                    }
-                }
+    #[tokio::test]
+    async fn test_requests_share_the_gateway_config() {
+        let started = Arc::new(tokio::sync::Notify::new());
+        let release = Arc::new(tokio::sync::Notify::new());
+        let (handler_started, handler_release) = (started.clone(), release.clone());
+        let app = Router::new().route(
+            "/api/v2/chat/completions-detection",
+            post(move || async move {
+                handler_started.notify_one();
+                handler_release.notified().await;
+                Json(completion_response(Value::Null))
+            }),
+        );
+        let orchestrator = spawn_server(app).await;
+        let gateway_config = Arc::new(test_config(orchestrator));
+        let app = build_router(
+            gateway_config.clone(),
+            Arc::new(reqwest::Client::new()),
+            "http".to_string(),
+            &ProcessState::default(),
+        )
+        .unwrap();
+        let gateway = spawn_server(app).await;
+        let idle = Arc::strong_count(&gateway_config);
 
-                if data_lines.is_empty() {
-                    Ok("".to_string())
-                } else {
-                    Ok(data_lines.join("\n"))
-                }
+        // A request in flight holds a reference to the same config rather than its own copy
+        let request = tokio::spawn(
+            reqwest::Client::new()
+                .post(format!("http://{}/test/v1/chat/completions", gateway))
+                .json(&json!({"model": "m", "messages": user_messages()}))
+                .send(),
+        );
+        started.notified().await;
+        assert!(Arc::strong_count(&gateway_config) > idle);
+        release.notify_one();
+        let response = request.await.unwrap().unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        response.bytes().await.unwrap();
+        // The handler's reference is released shortly after the response has been sent
+        for _ in 0..50 {
+            if Arc::strong_count(&gateway_config) == idle {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(Arc::strong_count(&gateway_config), idle);
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let args = |args: &[&str]| parse_args(args.iter().map(|arg| arg.to_string()));
+        assert_eq!(args(&[]), Ok(Command::Serve));
+        assert_eq!(
+            args(&["--validate", "config/config.yaml"]),
+            Ok(Command::Validate {
+                config_path: "config/config.yaml".to_string()
             })
-    });
+        );
+        assert!(args(&["--validate"]).is_err());
+        assert_eq!(
+            args(&["--print-config-schema"]),
+            Ok(Command::PrintConfigSchema)
+        );
+        assert!(args(&["--print-config-schema", "config.yaml"]).is_err());
+        assert!(args(&["--validate", "a.yaml", "b.yaml"]).is_err());
+        assert!(args(&["config.yaml"]).is_err());
+    }
+
+    #[test]
+    fn test_validate() {
+        let dir = std::env::temp_dir().join("gateway-config-validate-test");
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("config.yaml");
+        let path = path.to_str().unwrap();
+
+        std::fs::write(
+            path,
+            "orchestrator:\n  host: localhost\n  port: 0\ndetectors: []\n\
+             routes:\n  - name: pii\n    detectors: [missing]\n",
+        )
+        .unwrap();
+        assert_ne!(validate(path), 0);
+        let e = load_config(path).unwrap_err();
+        assert!(e.contains("could not find detector 'missing'"), "{}", e);
+        assert!(e.contains("orchestrator port must not be 0"), "{}", e);
+
+        assert_ne!(validate(dir.join("missing.yaml").to_str().unwrap()), 0);
+        assert_eq!(validate("config/config.yaml"), 0);
+    }
+
+    #[tokio::test]
+    async fn test_reload_config() {
+        let (orchestrator, captured) = spawn_orchestrator(completion_response(Value::Null)).await;
+        let dir = std::env::temp_dir().join("gateway-config-reload-test");
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("config.yaml");
+        let path = path.to_str().unwrap();
+        let write_config = |route_detector: &str, regex: &str| {
+            let config = format!(
+                "orchestrator:\n  host: {}\n  port: {}\ndetectors:\n  - name: pii\n    \
+                 input: true\n    output: false\n    detector_params:\n      regex: [{}]\n\
+                 routes:\n  - name: test\n    detectors: [{}]\n",
+                orchestrator.ip(),
+                orchestrator.port(),
+                regex,
+                route_detector
+            );
+            std::fs::write(path, config).unwrap();
+        };
+        let send_request = |gateway: SocketAddr| async move {
+            let response = reqwest::Client::new()
+                .post(format!("http://{}/test/v1/chat/completions", gateway))
+                .json(&json!({"model": "m", "messages": user_messages()}))
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        };
+
+        write_config("pii", "email");
+        let state = ProcessState::default();
+        let router = reload::ReloadableRouter::new(
+            build_router(
+                Arc::new(config::read_config(path).unwrap()),
+                Arc::new(reqwest::Client::new()),
+                "http".to_string(),
+                &state,
+            )
+            .unwrap(),
+        );
+        let gateway = spawn_server(router.clone().into_router()).await;
+        send_request(gateway).await;
+
+        write_config("pii", "ssn");
+        reload_config(path, &router, &state).unwrap();
+        send_request(gateway).await;
+
+        // A config that fails validation leaves the current one in place
+        write_config("missing", "email");
+        let e = reload_config(path, &router, &state).unwrap_err();
+        assert!(e.contains("could not find detector 'missing'"), "{}", e);
+        send_request(gateway).await;
+
+        // As does one with routes that could not be served side by side
+        let config = std::fs::read_to_string(path)
+            .unwrap()
+            .replace("email", "ssn");
+        std::fs::write(
+            path,
+            format!("{}  - name: test\n    detectors: []\n", config),
+        )
+        .unwrap();
+        let e = reload_config(path, &router, &state).unwrap_err();
+        assert!(
+            e.contains("route name 'test' is used by more than one route"),
+            "{}",
+            e
+        );
+        send_request(gateway).await;
+
+        let params: Vec<Value> = captured
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(_, payload)| payload["detectors"]["input"]["pii"]["regex"].clone())
+            .collect();
+        assert_eq!(
+            params,
+            [
+                json!(["email"]),
+                json!(["ssn"]),
+                json!(["ssn"]),
+                json!(["ssn"])
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_reload_mid_request_keeps_one_config() {
+        // Answers only once released, so the config can be swapped while a request waits on it
+        let received = Arc::new(tokio::sync::Notify::new());
+        let release = Arc::new(tokio::sync::Semaphore::new(0));
+        let captured: Captured = Arc::new(Mutex::new(Vec::new()));
+        let app = {
+            let (received, release, recorder) =
+                (received.clone(), release.clone(), captured.clone());
+            Router::new().route(
+                "/api/v2/chat/completions-detection",
+                post(
+                    move |headers: HeaderMap, Json(body): Json<Value>| async move {
+                        recorder.lock().unwrap().push((headers, body));
+                        received.notify_one();
+                        let _permit = release.acquire().await.unwrap();
+                        Json(completion_response(output_detections()))
+                    },
+                ),
+            )
+        };
+        let orchestrator = spawn_server(app).await;
+
+        let dir = std::env::temp_dir().join("gateway-config-reload-mid-request-test");
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("config.yaml");
+        let path = path.to_str().unwrap();
+        let write_config = |version: &str| {
+            let config = format!(
+                "orchestrator:\n  host: {}\n  port: {}\ndetectors:\n  - name: pii\n    \
+                 input: true\n    output: true\n    detector_params:\n      regex: [{version}]\n\
+                 routes:\n  - name: test\n    detectors: [pii]\n    fallback_message: {version}\n",
+                orchestrator.ip(),
+                orchestrator.port(),
+            );
+            std::fs::write(path, config).unwrap();
+        };
+        let send_request = |gateway: SocketAddr| async move {
+            let body: Value = reqwest::Client::new()
+                .post(format!("http://{}/test/v1/chat/completions", gateway))
+                .json(&json!({"model": "m", "messages": user_messages()}))
+                .send()
+                .await
+                .unwrap()
+                .json()
+                .await
+                .unwrap();
+            body["choices"][0]["message"]["content"].clone()
+        };
+
+        write_config("old");
+        let state = ProcessState::default();
+        let router = reload::ReloadableRouter::new(
+            build_router(
+                Arc::new(config::read_config(path).unwrap()),
+                Arc::new(reqwest::Client::new()),
+                "http".to_string(),
+                &state,
+            )
+            .unwrap(),
+        );
+        let gateway = spawn_server(router.clone().into_router()).await;
+
+        // The detectors are sent before the reload and the fallback decided after it
+        let in_flight = tokio::spawn(send_request(gateway));
+        received.notified().await;
+        write_config("new");
+        reload_config(path, &router, &state).unwrap();
+        release.add_permits(2);
+        assert_eq!(in_flight.await.unwrap(), "old");
+
+        assert_eq!(send_request(gateway).await, "new");
+        let params: Vec<Value> = captured
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(_, payload)| payload["detectors"]["output"]["pii"]["regex"].clone())
+            .collect();
+        assert_eq!(params, [json!(["old"]), json!(["new"])]);
+    }
+
+    #[test]
+    fn test_backoff_ms() {
+        assert_eq!(backoff_ms(100, 0), 100);
+        assert_eq!(backoff_ms(100, 3), 800);
+        assert_eq!(backoff_ms(100, 20), MAX_BACKOFF_MS);
+        assert_eq!(backoff_ms(100, 64), MAX_BACKOFF_MS);
+        assert_eq!(backoff_ms(u64::MAX, 1), MAX_BACKOFF_MS);
+    }
+
+    #[test]
+    fn test_orchestrator_base_urls() {
+        let cases = [
+            (Some(8032), "http", "http://orchestrator:8032"),
+            (Some(8032), "https", "https://orchestrator:8032"),
+            (None, "http", "http://orchestrator"),
+            (None, "https", "https://orchestrator"),
+        ];
+        for (port, scheme, expected) in cases {
+            let orchestrator = OrchestratorConfig {
+                host: vec!["orchestrator".to_string()],
+                port,
+                ..Default::default()
+            };
+            assert_eq!(orchestrator_base_urls(&orchestrator, scheme), [expected]);
+        }
+
+        let orchestrator = OrchestratorConfig {
+            host: vec![
+                "orchestrator-0".to_string(),
+                "orchestrator-1:8033".to_string(),
+            ],
+            port: Some(8032),
+            ..Default::default()
+        };
+        assert_eq!(
+            orchestrator_base_urls(&orchestrator, "http"),
+            ["http://orchestrator-0:8032", "http://orchestrator-1:8033"]
+        );
+    }
+
+    #[test]
+    fn test_chat_completions_path_precedence() {
+        let cases = [
+            (None, None, "/api/v2/chat/completions-detection"),
+            (Some("/orchestrator"), None, "/orchestrator"),
+            (None, Some("/route"), "/route"),
+            (Some("/orchestrator"), Some("/route"), "/route"),
+        ];
+        for (orchestrator_api_path, route_api_path, expected) in cases {
+            let gateway_config = GatewayConfig {
+                orchestrator: OrchestratorConfig {
+                    api_path: orchestrator_api_path.map(str::to_string),
+                    ..Default::default()
+                },
+                ..Default::default()
+            };
+            assert_eq!(
+                chat_completions_path(&gateway_config, route_api_path),
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn test_build_orchestrator_client_from_configured_paths() {
+        let dir = std::env::temp_dir().join("gateway-orchestrator-tls-test");
+        std::fs::create_dir_all(&dir).unwrap();
+        let (cert_pem, key_pem) = tls::tests::self_signed();
+        std::fs::write(dir.join("tls.crt"), &cert_pem).unwrap();
+        std::fs::write(dir.join("tls.key"), &key_pem).unwrap();
+        std::fs::write(dir.join("ca.crt"), &cert_pem).unwrap();
+        let path = |file: &str| dir.join(file).to_string_lossy().into_owned();
+
+        let orchestrator = OrchestratorConfig {
+            tls: OrchestratorTlsConfig {
+                cert_path: path("tls.crt"),
+                key_path: path("tls.key"),
+                ca_path: path("ca.crt"),
+            },
+            ..Default::default()
+        };
+        let (_, scheme) = build_orchestrator_client(&orchestrator).unwrap();
+        assert_eq!(scheme, "https");
+
+        // Without a client certificate the orchestrator is reached over plain HTTP
+        let orchestrator = OrchestratorConfig {
+            tls: OrchestratorTlsConfig {
+                cert_path: path("missing.crt"),
+                ..orchestrator.tls
+            },
+            ..Default::default()
+        };
+        let (_, scheme) = build_orchestrator_client(&orchestrator).unwrap();
+        assert_eq!(scheme, "http");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_build_router_reports_route_client_errors() {
+        let dir = std::env::temp_dir().join("gateway-build-router-test");
+        std::fs::create_dir_all(&dir).unwrap();
+        let ca_path = dir.join("ca.crt");
+        std::fs::write(&ca_path, "not a certificate").unwrap();
+        let mut gateway_config = test_config("127.0.0.1:1".parse().unwrap());
+        gateway_config.orchestrator.tls.ca_path = ca_path.to_str().unwrap().to_string();
+        gateway_config.routes[0].isolated_client = true;
+
+        let e = build_router(
+            Arc::new(gateway_config),
+            Arc::new(reqwest::Client::new()),
+            "http".to_string(),
+            &ProcessState::default(),
+        )
+        .unwrap_err();
+        assert!(
+            e.starts_with("failed to build orchestrator client for route test"),
+            "{}",
+            e
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_route_client_isolation() {
+        let shared = Arc::new(reqwest::Client::new());
+        let orchestrator = OrchestratorConfig::default();
+        let mut route = RouteConfig::default();
+        assert!(Arc::ptr_eq(
+            &route_client(&route, &shared, &orchestrator).unwrap(),
+            &shared
+        ));
+
+        route.isolated_client = true;
+        let isolated = route_client(&route, &shared, &orchestrator).unwrap();
+        assert!(!Arc::ptr_eq(&isolated, &shared));
+        assert!(!Arc::ptr_eq(
+            &route_client(&route, &shared, &orchestrator).unwrap(),
+            &isolated
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_payload_without_messages_is_rejected() {
+        let (orchestrator, captured) = spawn_orchestrator(completion_response(Value::Null)).await;
+        let gateway = spawn_gateway(&test_config(orchestrator)).await;
+
+        for (payload, message) in [
+            (json!({"model": "m"}), "'messages' is required"),
+            (
+                json!({"model": "m", "messages": []}),
+                "'messages' must not be empty",
+            ),
+            (
+                json!({"model": "m", "messages": "hi", "stream": true}),
+                "'messages' must be an array",
+            ),
+            (json!(["hi"]), "request body must be a JSON object"),
+        ] {
+            let response = reqwest::Client::new()
+                .post(format!("http://{}/test/v1/chat/completions", gateway))
+                .header("x-request-id", "req-1")
+                .json(&payload)
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{}", payload);
+            assert_eq!(response.headers()["x-request-id"], "req-1");
+            assert_eq!(
+                response.json::<Value>().await.unwrap(),
+                json!({"error": {"message": message, "type": "invalid_request_error", "code": null}})
+            );
+        }
+        // Bodies that aren't JSON at all are answered in the same shape
+        let response = reqwest::Client::new()
+            .post(format!("http://{}/test/v1/chat/completions", gateway))
+            .header("content-type", "application/json")
+            .body("{not json")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body: Value = response.json().await.unwrap();
+        assert_eq!(body["error"]["type"], "invalid_request_error");
+        assert!(body["error"]["message"].is_string(), "{}", body);
+        assert!(captured.lock().unwrap().is_empty());
+
+        // Anything else in the payload is left to the orchestrator
+        let response = reqwest::Client::new()
+            .post(format!("http://{}/test/v1/chat/completions", gateway))
+            .json(&json!({"messages": user_messages(), "unknown": {"nested": true}}))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            captured.lock().unwrap()[0].1["unknown"],
+            json!({"nested": true})
+        );
+    }
+
+    #[tokio::test]
+    async fn test_route_model_allowlist() {
+        let (orchestrator, captured) = spawn_orchestrator(completion_response(Value::Null)).await;
+        let mut gateway_config = test_config(orchestrator);
+        gateway_config.routes[0].allowed_models =
+            Some(vec!["granite".to_string(), "llama".to_string()]);
+        let gateway = spawn_gateway(&gateway_config).await;
+
+        let response = reqwest::Client::new()
+            .post(format!("http://{}/test/v1/chat/completions", gateway))
+            .json(&json!({"model": "llama", "messages": user_messages()}))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(captured.lock().unwrap().len(), 1);
+
+        for (payload, message) in [
+            (
+                json!({"model": "gpt-4", "messages": user_messages(), "stream": true}),
+                "model 'gpt-4' is not allowed on route 'test', use one of: granite, llama",
+            ),
+            (
+                json!({"messages": user_messages()}),
+                "a model is required on route 'test', use one of: granite, llama",
+            ),
+        ] {
+            let response = reqwest::Client::new()
+                .post(format!("http://{}/test/v1/chat/completions", gateway))
+                .json(&payload)
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::FORBIDDEN, "{}", payload);
+            assert_eq!(
+                response.json::<Value>().await.unwrap(),
+                json!({"error": {"message": message, "type": "permission_error", "code": "model_not_allowed"}})
+            );
+        }
+        assert_eq!(captured.lock().unwrap().len(), 1);
+
+        // Without an allowlist any model is passed through
+        let (orchestrator, captured) = spawn_orchestrator(completion_response(Value::Null)).await;
+        let gateway = spawn_gateway(&test_config(orchestrator)).await;
+        let response = reqwest::Client::new()
+            .post(format!("http://{}/test/v1/chat/completions", gateway))
+            .json(&json!({"model": "gpt-4", "messages": user_messages()}))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(captured.lock().unwrap()[0].1["model"], "gpt-4");
+    }
+
+    #[tokio::test]
+    async fn test_isolated_client_route() {
+        let (orchestrator, captured) = spawn_orchestrator(completion_response(Value::Null)).await;
+        let mut gateway_config = test_config(orchestrator);
+        gateway_config.routes[0].isolated_client = true;
+        let gateway = spawn_gateway(&gateway_config).await;
+
+        let response = reqwest::Client::new()
+            .post(format!("http://{}/test/v1/chat/completions", gateway))
+            .json(&json!({"model": "m", "messages": user_messages()}))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(captured.lock().unwrap().len(), 1);
+    }
+
+    /// Config for the test route spread across several orchestrators, each addressed by its own
+    /// `host:port`.
+    fn replicated_config(orchestrators: &[SocketAddr]) -> GatewayConfig {
+        let mut gateway_config = test_config(orchestrators[0]);
+        gateway_config.orchestrator.host = orchestrators.iter().map(ToString::to_string).collect();
+        gateway_config.orchestrator.port = None;
+        gateway_config
+    }
+
+    /// An address nothing is listening on, so connecting to it is refused.
+    async fn unreachable_addr() -> SocketAddr {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        listener.local_addr().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_requests_alternate_between_orchestrators() {
+        let (first, first_captured) = spawn_orchestrator(completion_response(Value::Null)).await;
+        let (second, second_captured) = spawn_orchestrator(completion_response(Value::Null)).await;
+        let gateway = spawn_gateway(&replicated_config(&[first, second])).await;
+
+        let client = reqwest::Client::new();
+        for request in 1..=4 {
+            let response = client
+                .post(format!("http://{}/test/v1/chat/completions", gateway))
+                .json(&json!({"model": "m", "messages": user_messages()}))
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(first_captured.lock().unwrap().len(), (request + 1) / 2);
+            assert_eq!(second_captured.lock().unwrap().len(), request / 2);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_unreachable_orchestrator_is_skipped() {
+        let (orchestrator, captured) = spawn_orchestrator(completion_response(Value::Null)).await;
+        let gateway = spawn_gateway(&replicated_config(&[
+            unreachable_addr().await,
+            orchestrator,
+        ]))
+        .await;
+
+        let client = reqwest::Client::new();
+        for _ in 0..2 {
+            let response = client
+                .post(format!("http://{}/test/v1/chat/completions", gateway))
+                .json(&json!({"model": "m", "messages": user_messages()}))
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+        assert_eq!(captured.lock().unwrap().len(), 2);
+
+        // Streaming requests fail over too
+        let (streaming, captured) =
+            spawn_streaming_orchestrator("data: {\"id\":\"1\",\"choices\":[]}\n\n").await;
+        let gateway =
+            spawn_gateway(&replicated_config(&[unreachable_addr().await, streaming])).await;
+        let body = client
+            .post(format!("http://{}/test/v1/chat/completions", gateway))
+            .json(&json!({"model": "m", "messages": user_messages(), "stream": true}))
+            .send()
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+        assert_eq!(sse_data(&body).len(), 1, "{}", body);
+        assert_eq!(captured.lock().unwrap().len(), 1);
+
+        // With every orchestrator down the request fails
+        let gateway = spawn_gateway(&replicated_config(&[
+            unreachable_addr().await,
+            unreachable_addr().await,
+        ]))
+        .await;
+        let response = client
+            .post(format!("http://{}/test/v1/chat/completions", gateway))
+            .json(&json!({"model": "m", "messages": user_messages()}))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+    }
+
+    #[tokio::test]
+    async fn test_ready_while_any_orchestrator_is_healthy() {
+        let healthy = spawn_server(Router::new().route("/health", get(|| async { "ok" }))).await;
+        let gateway = spawn_gateway(&replicated_config(&[unreachable_addr().await, healthy])).await;
+        let response = reqwest::get(format!("http://{}/ready", gateway))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    fn regex_detector(input: bool, output: bool) -> DetectorConfig {
+        DetectorConfig {
+            name: "regex".to_string(),
+            server: Some("regex".to_string()),
+            input,
+            output,
+            detector_params: Some(json!({"regex": ["email"]})),
+            threshold: None,
+        }
+    }
+
+    /// Detectors keyed by server and by name, plus one the route does not use and one without
+    /// params, with the route detectors and the orchestrator `detectors` they should produce.
+    fn keyed_detectors() -> (Vec<DetectorConfig>, Vec<String>, Value) {
+        let detector = |name: &str, server: Option<&str>, input, params| DetectorConfig {
+            name: name.to_string(),
+            server: server.map(str::to_string),
+            input,
+            output: true,
+            detector_params: params,
+            threshold: None,
+        };
+        let detector_config = vec![
+            detector(
+                "pii",
+                Some("regex-server"),
+                true,
+                Some(json!({"regex": ["email"]})),
+            ),
+            detector("hap", None, false, Some(json!({"threshold": 0.5}))),
+            detector("unrouted", None, true, Some(json!({}))),
+            detector("unconfigured", None, true, None),
+        ];
+        let route_detectors = vec![
+            "pii".to_string(),
+            "hap".to_string(),
+            "unconfigured".to_string(),
+        ];
+        let expected = json!({
+            "input": {"regex-server": {"regex": ["email"]}},
+            "output": {"regex-server": {"regex": ["email"]}, "hap": {"threshold": 0.5}},
+        });
+        (detector_config, route_detectors, expected)
+    }
+
+    #[test]
+    fn test_get_orchestrator_detectors_keys_by_server_or_name() {
+        let (detector_config, route_detectors, expected) = keyed_detectors();
+        let detectors = get_orchestrator_detectors(&route_detectors, &detector_config, None);
+        assert_eq!(serde_json::to_value(detectors).unwrap(), expected);
+    }
+
+    #[tokio::test]
+    async fn test_route_detectors_are_built_from_config() {
+        let (orchestrator, captured) = spawn_orchestrator(completion_response(Value::Null)).await;
+        let mut gateway_config = test_config(orchestrator);
+        let (detector_config, route_detectors, expected) = keyed_detectors();
+        gateway_config.detectors = detector_config;
+        gateway_config.routes[0].detectors = route_detectors;
+        let gateway = spawn_gateway(&gateway_config).await;
+
+        for _ in 0..2 {
+            let response = reqwest::Client::new()
+                .post(format!("http://{}/test/v1/chat/completions", gateway))
+                .json(&json!({"model": "m", "messages": user_messages()}))
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+        // Every request carries the structure built once at startup
+        let captured = captured.lock().unwrap();
+        assert_eq!(captured.len(), 2);
+        for (_, payload) in captured.iter() {
+            assert_eq!(payload["detectors"], expected);
+        }
+    }
+
+    #[test]
+    fn test_get_orchestrator_detectors_streaming_mode() {
+        let detectors = get_orchestrator_detectors(
+            &["regex".to_string()],
+            &[regex_detector(true, true)],
+            Some(StreamingDetectionMode::Sentence),
+        );
+        assert_eq!(
+            detectors.output["regex"],
+            json!({"regex": ["email"], "streaming_detection_mode": "sentence"})
+        );
+        assert_eq!(detectors.input["regex"], json!({"regex": ["email"]}));
+
+        let detectors =
+            get_orchestrator_detectors(&["regex".to_string()], &[regex_detector(true, true)], None);
+        assert_eq!(detectors.output["regex"], json!({"regex": ["email"]}));
+    }
+
+    fn param_bounds() -> ParamBoundsConfig {
+        ParamBoundsConfig {
+            temperature: Some(BoundsConfig {
+                min: Some(0.1),
+                max: Some(1.0),
+            }),
+            top_p: Some(BoundsConfig {
+                min: None,
+                max: Some(0.9),
+            }),
+            max_tokens: Some(512),
+        }
+    }
+
+    #[test]
+    fn test_clamp_params() {
+        let cases = [
+            ("temperature", json!(2.0), json!(1.0)),
+            ("temperature", json!(0.0), json!(0.1)),
+            ("temperature", json!(0.5), json!(0.5)),
+            ("top_p", json!(0.95), json!(0.9)),
+            ("top_p", json!(0.0), json!(0.0)),
+            ("max_tokens", json!(4096), json!(512)),
+            ("max_tokens", json!(100), json!(100)),
+        ];
+        for (param, value, expected) in cases {
+            let mut payload = json!({"model": "m", param: value});
+            clamp_params(payload.as_object_mut().unwrap(), &param_bounds());
+            assert_eq!(payload[param], expected, "{} = {}", param, value);
+        }
+    }
 
-    Ok(chunk_stream)
+    #[test]
+    fn test_clamp_params_leaves_unset_params_alone() {
+        let mut payload = json!({"model": "m"});
+        clamp_params(payload.as_object_mut().unwrap(), &param_bounds());
+        assert_eq!(payload, json!({"model": "m"}));
+    }
+
+    #[test]
+    fn test_cap_max_tokens() {
+        let cases = [
+            (json!({"max_tokens": 4096}), json!(512)),
+            (json!({"max_tokens": 512}), json!(512)),
+            (json!({"max_tokens": 100}), json!(100)),
+            (json!({}), json!(512)),
+        ];
+        for (payload, expected) in cases {
+            let mut capped = payload.clone();
+            cap_max_tokens(capped.as_object_mut().unwrap(), 512);
+            assert_eq!(capped["max_tokens"], expected, "{}", payload);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_route_max_tokens_cap() {
+        let (orchestrator, captured) = spawn_orchestrator(completion_response(Value::Null)).await;
+        let mut gateway_config = test_config(orchestrator);
+        gateway_config.routes[0].max_tokens_cap = Some(256);
+        let gateway = spawn_gateway(&gateway_config).await;
+
+        for (max_tokens, expected) in [(json!(10_000), 256), (json!(64), 64), (Value::Null, 256)] {
+            let mut payload = json!({"model": "m", "messages": user_messages()});
+            if !max_tokens.is_null() {
+                payload["max_tokens"] = max_tokens;
+            }
+            let response = reqwest::Client::new()
+                .post(format!("http://{}/test/v1/chat/completions", gateway))
+                .json(&payload)
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(
+                captured.lock().unwrap().last().unwrap().1["max_tokens"],
+                expected,
+                "{}",
+                payload
+            );
+        }
+    }
+
+    #[test]
+    fn test_content_from_pointer() {
+        let mut payload = json!({"model": "m", "input": {"prompts": ["hi", "there"]}});
+        content_from_pointer(&mut payload, "/input/prompts/1", CompletionsApi::Chat).unwrap();
+        assert_eq!(
+            payload["messages"],
+            json!([{"role": "user", "content": "there"}])
+        );
+
+        let history = json!([{"role": "user", "content": "hi"}]);
+        let mut payload = json!({"model": "m", "conversation": {"turns": history}});
+        content_from_pointer(&mut payload, "/conversation/turns", CompletionsApi::Chat).unwrap();
+        assert_eq!(payload["messages"], history);
+
+        // Escaped keys as per RFC 6901
+        let mut payload = json!({"a/b": {"c~d": "hi"}});
+        content_from_pointer(&mut payload, "/a~1b/c~0d", CompletionsApi::Chat).unwrap();
+        assert_eq!(
+            payload["messages"],
+            json!([{"role": "user", "content": "hi"}])
+        );
+
+        for pointer in ["/missing", "/model/x", "/conversation"] {
+            let mut payload = json!({"model": "m", "conversation": {"turns": []}});
+            let error =
+                content_from_pointer(&mut payload, pointer, CompletionsApi::Chat).unwrap_err();
+            assert_eq!(
+                error,
+                ApiError::invalid_request(format!(
+                    "no string or message array found at '{}'",
+                    pointer
+                ))
+            );
+        }
+
+        // Text completions take either as the prompt
+        let mut payload = json!({"input": {"prompts": ["hi", "there"]}});
+        content_from_pointer(&mut payload, "/input/prompts", CompletionsApi::Text).unwrap();
+        assert_eq!(payload["prompt"], json!(["hi", "there"]));
+        content_from_pointer(&mut payload, "/input/prompts/0", CompletionsApi::Text).unwrap();
+        assert_eq!(payload["prompt"], "hi");
+        let error = content_from_pointer(&mut payload, "/input", CompletionsApi::Text).unwrap_err();
+        assert_eq!(
+            error,
+            ApiError::invalid_request("no string or prompt array found at '/input'")
+        );
+    }
+
+    #[test]
+    fn test_prepend_system_prompt() {
+        let prompt = json!({"role": "system", "content": "be safe"});
+        let user = json!({"role": "user", "content": "hi"});
+        let client_system = json!({"role": "system", "content": "be brief"});
+        let cases = [
+            (json!([user]), json!([prompt, user])),
+            (
+                json!([client_system, user]),
+                json!([prompt, client_system, user]),
+            ),
+            // Already leading, e.g. a client replaying a previous conversation
+            (json!([prompt, user]), json!([prompt, user])),
+            // Same content with another role is not the prompt
+            (
+                json!([{"role": "user", "content": "be safe"}]),
+                json!([prompt, {"role": "user", "content": "be safe"}]),
+            ),
+            (json!([]), json!([])),
+            (json!("hi"), json!("hi")),
+        ];
+        for (messages, expected) in cases {
+            let mut payload = json!({"model": "m", "messages": messages});
+            prepend_system_prompt(payload.as_object_mut().unwrap(), "be safe");
+            assert_eq!(payload["messages"], expected, "{}", messages);
+        }
+
+        let mut payload = json!({"model": "m"});
+        prepend_system_prompt(payload.as_object_mut().unwrap(), "be safe");
+        assert_eq!(payload, json!({"model": "m"}));
+    }
+
+    #[tokio::test]
+    async fn test_route_system_prompt() {
+        let (orchestrator, captured) = spawn_orchestrator(completion_response(Value::Null)).await;
+        let mut gateway_config = test_config(orchestrator);
+        gateway_config.routes[0].system_prompt = Some("be safe".to_string());
+        let gateway = spawn_gateway(&gateway_config).await;
+
+        let response = reqwest::Client::new()
+            .post(format!("http://{}/test/v1/chat/completions", gateway))
+            .json(&json!({"model": "m", "messages": user_messages()}))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            captured.lock().unwrap()[0].1["messages"],
+            json!([
+                {"role": "system", "content": "be safe"},
+                {"role": "user", "content": "hi"},
+            ])
+        );
+
+        // An empty conversation is still rejected rather than sent with only the prompt
+        let response = reqwest::Client::new()
+            .post(format!("http://{}/test/v1/chat/completions", gateway))
+            .json(&json!({"model": "m", "messages": []}))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        // Without a system prompt the messages are forwarded as sent
+        let (orchestrator, captured) = spawn_orchestrator(completion_response(Value::Null)).await;
+        let gateway = spawn_gateway(&test_config(orchestrator)).await;
+        let response = reqwest::Client::new()
+            .post(format!("http://{}/test/v1/chat/completions", gateway))
+            .json(&json!({"model": "m", "messages": user_messages()}))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(captured.lock().unwrap()[0].1["messages"], user_messages());
+    }
+
+    #[tokio::test]
+    async fn test_content_pointer_forwards_custom_payload_content() {
+        let (orchestrator, captured) = spawn_orchestrator(completion_response(Value::Null)).await;
+        let mut gateway_config = test_config(orchestrator);
+        gateway_config.routes[0].content_pointer = Some("/input/prompt".to_string());
+        let gateway = spawn_gateway(&gateway_config).await;
+
+        let client = reqwest::Client::new();
+        let url = format!("http://{}/test/v1/chat/completions", gateway);
+        let response = client
+            .post(&url)
+            .json(&json!({"model": "m", "input": {"prompt": "hello"}}))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            captured.lock().unwrap()[0].1["messages"],
+            json!([{"role": "user", "content": "hello"}])
+        );
+
+        let response = client
+            .post(&url)
+            .json(&json!({"model": "m", "messages": user_messages()}))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(captured.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_merge_detectors() {
+        let mut detectors = json!({
+            "input": {"hap": {"threshold": 0.5}},
+            "output": {"hap": {"threshold": 0.5}},
+        });
+        merge_detectors(
+            &mut detectors,
+            json!({
+                "input": {"hap": {"threshold": 0.99, "extra": true}, "pii": {}},
+                "output": {"hap": null},
+            }),
+        );
+        assert_eq!(
+            detectors,
+            json!({
+                "input": {"hap": {"threshold": 0.5, "extra": true}, "pii": {}},
+                "output": {"hap": {"threshold": 0.5}},
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_detectors_merge_policies() {
+        let (orchestrator, captured) = spawn_orchestrator(completion_response(Value::Null)).await;
+        let client_detectors = json!({"input": {"pii": {}}, "output": {"regex": null}});
+        let cases = [
+            (DetectorsMergePolicy::Overwrite, StatusCode::OK),
+            (DetectorsMergePolicy::Merge, StatusCode::OK),
+            (DetectorsMergePolicy::Reject, StatusCode::BAD_REQUEST),
+        ];
+        for (policy, status) in cases {
+            let mut gateway_config = GatewayConfig {
+                detectors: vec![regex_detector(true, true)],
+                ..test_config(orchestrator)
+            };
+            gateway_config.routes[0].detectors = vec!["regex".to_string()];
+            gateway_config.routes[0].detectors_merge_policy = policy;
+            let gateway = spawn_gateway(&gateway_config).await;
+
+            let response = reqwest::Client::new()
+                .post(format!("http://{}/test/v1/chat/completions", gateway))
+                .json(&json!({"model": "m", "messages": user_messages(), "detectors": client_detectors}))
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(response.status(), status, "{:?}", policy);
+        }
+
+        let captured = captured.lock().unwrap();
+        assert_eq!(captured.len(), 2);
+        let regex = json!({"regex": ["email"]});
+        assert_eq!(
+            captured[0].1["detectors"],
+            json!({"input": {"regex": regex}, "output": {"regex": regex}})
+        );
+        assert_eq!(
+            captured[1].1["detectors"],
+            json!({"input": {"regex": regex, "pii": {}}, "output": {"regex": regex}})
+        );
+    }
+
+    #[test]
+    fn test_annotate_last_user_message() {
+        let mut detectors = json!({
+            "input": {"regex": {"regex": ["email"]}},
+            "output": {"regex": {"regex": ["email"]}},
+        });
+        let payload = json!({
+            "messages": [
+                {"role": "system", "content": "be nice"},
+                {"role": "user", "content": "first"},
+                {"role": "assistant", "content": "reply"},
+                {"role": "user", "content": "second"},
+                {"role": "assistant", "content": "reply"},
+            ]
+        });
+
+        annotate_last_user_message(&mut detectors, payload.as_object().unwrap());
+
+        assert_eq!(detectors["input"]["regex"]["message_index"], 3);
+        assert!(detectors["output"]["regex"].get("message_index").is_none());
+    }
+
+    #[test]
+    fn test_annotate_last_user_message_without_user_message() {
+        let mut detectors = json!({"input": {"regex": {}}, "output": {}});
+        let payload = json!({"messages": [{"role": "system", "content": "be nice"}]});
+
+        annotate_last_user_message(&mut detectors, payload.as_object().unwrap());
+
+        assert_eq!(detectors, json!({"input": {"regex": {}}, "output": {}}));
+    }
+
+    #[tokio::test]
+    async fn test_detector_params_are_templated() {
+        let (orchestrator, captured) = spawn_orchestrator(completion_response(Value::Null)).await;
+        let mut detector = regex_detector(true, false);
+        detector.detector_params = Some(json!({
+            "model": "${request.model}",
+            "tenant": "${header.x-tenant-id}",
+        }));
+        let mut gateway_config = GatewayConfig {
+            detectors: vec![detector],
+            ..test_config(orchestrator)
+        };
+        gateway_config.routes[0].detectors = vec!["regex".to_string()];
+        let gateway = spawn_gateway(&gateway_config).await;
+
+        reqwest::Client::new()
+            .post(format!("http://{}/test/v1/chat/completions", gateway))
+            .header("x-tenant-id", "acme")
+            .json(&json!({"model": "granite", "messages": user_messages()}))
+            .send()
+            .await
+            .unwrap();
+
+        let captured = captured.lock().unwrap();
+        let (_, body) = &captured[0];
+        assert_eq!(
+            body["detectors"]["input"]["regex"],
+            json!({"model": "granite", "tenant": "acme"})
+        );
+    }
+
+    #[tokio::test]
+    async fn test_detect_last_message_only_is_forwarded() {
+        let (orchestrator, captured) = spawn_orchestrator(completion_response(Value::Null)).await;
+        let mut gateway_config = GatewayConfig {
+            detectors: vec![regex_detector(true, false)],
+            ..test_config(orchestrator)
+        };
+        gateway_config.routes[0].detectors = vec!["regex".to_string()];
+        gateway_config.routes[0].detect_last_message_only = true;
+        let gateway = spawn_gateway(&gateway_config).await;
+
+        reqwest::Client::new()
+            .post(format!("http://{}/test/v1/chat/completions", gateway))
+            .json(&json!({"model": "m", "messages": [
+                {"role": "user", "content": "first"},
+                {"role": "user", "content": "second"},
+            ]}))
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(
+            captured.lock().unwrap()[0].1["detectors"]["input"]["regex"],
+            json!({"regex": ["email"], "message_index": 1})
+        );
+    }
+
+    #[tokio::test]
+    async fn test_streaming_detection_mode_only_sent_for_streaming() {
+        let mut gateway_config = GatewayConfig {
+            detectors: vec![regex_detector(false, true)],
+            ..test_config("127.0.0.1:1".parse().unwrap())
+        };
+        gateway_config.routes[0].detectors = vec!["regex".to_string()];
+        gateway_config.routes[0].streaming_detection_mode = Some(StreamingDetectionMode::Token);
+
+        let (orchestrator, captured) = spawn_orchestrator(completion_response(Value::Null)).await;
+        gateway_config.orchestrator.port = Some(orchestrator.port());
+        let gateway = spawn_gateway(&gateway_config).await;
+        reqwest::Client::new()
+            .post(format!("http://{}/test/v1/chat/completions", gateway))
+            .json(&json!({"model": "m", "messages": user_messages()}))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(
+            captured.lock().unwrap()[0].1["detectors"]["output"]["regex"],
+            json!({"regex": ["email"]})
+        );
+
+        let (orchestrator, captured) = spawn_streaming_orchestrator("data: [DONE]\n\n").await;
+        gateway_config.orchestrator.port = Some(orchestrator.port());
+        let gateway = spawn_gateway(&gateway_config).await;
+        reqwest::Client::new()
+            .post(format!("http://{}/test/v1/chat/completions", gateway))
+            .json(&json!({"model": "m", "messages": user_messages(), "stream": true}))
+            .send()
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+        assert_eq!(
+            captured.lock().unwrap()[0].1["detectors"]["output"]["regex"],
+            json!({"regex": ["email"], "streaming_detection_mode": "token"})
+        );
+    }
+
+    #[test]
+    fn test_select_detectors() {
+        let route_detectors = vec!["pii".to_string(), "toxicity".to_string(), "hap".to_string()];
+
+        assert_eq!(
+            select_detectors(&route_detectors, "hap, pii"),
+            vec!["pii".to_string(), "hap".to_string()]
+        );
+        assert_eq!(
+            select_detectors(&route_detectors, "pii,not-configured"),
+            vec!["pii".to_string()]
+        );
+        assert!(select_detectors(&route_detectors, "not-configured").is_empty());
+        assert!(select_detectors(&route_detectors, "").is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_detector_selection_header() {
+        let detectors = vec![
+            regex_detector(true, false),
+            DetectorConfig {
+                name: "hap".to_string(),
+                server: Some("hap".to_string()),
+                input: true,
+                output: false,
+                detector_params: Some(json!({})),
+                threshold: None,
+            },
+        ];
+        for (allow_detector_selection, expected) in [
+            (true, json!({"hap": {}})),
+            (false, json!({"hap": {}, "regex": {"regex": ["email"]}})),
+        ] {
+            let (orchestrator, captured) =
+                spawn_orchestrator(completion_response(Value::Null)).await;
+            let mut gateway_config = GatewayConfig {
+                detectors: detectors.clone(),
+                ..test_config(orchestrator)
+            };
+            gateway_config.routes[0].detectors = vec!["regex".to_string(), "hap".to_string()];
+            gateway_config.routes[0].allow_detector_selection = allow_detector_selection;
+            let gateway = spawn_gateway(&gateway_config).await;
+
+            reqwest::Client::new()
+                .post(format!("http://{}/test/v1/chat/completions", gateway))
+                .header("X-Detectors", "hap,unknown")
+                .json(&json!({"model": "m", "messages": user_messages()}))
+                .send()
+                .await
+                .unwrap();
+
+            assert_eq!(
+                captured.lock().unwrap()[0].1["detectors"]["input"],
+                expected
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_custom_request_id_header_is_forwarded_and_echoed() {
+        let (orchestrator, captured) = spawn_orchestrator(completion_response(Value::Null)).await;
+        let mut gateway_config = test_config(orchestrator);
+        gateway_config.request_id_header = "x-correlation-id".to_string();
+        let gateway = spawn_gateway(&gateway_config).await;
+
+        let response = reqwest::Client::new()
+            .post(format!("http://{}/test/v1/chat/completions", gateway))
+            .header("x-correlation-id", "abc-123")
+            .header("x-request-id", "ignored")
+            .json(&json!({"model": "test-model", "messages": user_messages()}))
+            .send()
+            .await
+            .unwrap();
+
+        assert!(response.status().is_success());
+        assert_eq!(response.headers()["x-correlation-id"], "abc-123");
+        assert!(response.headers().get("x-request-id").is_none());
+
+        let captured = captured.lock().unwrap();
+        let (headers, _) = &captured[0];
+        assert_eq!(headers["x-correlation-id"], "abc-123");
+        assert!(headers.get("x-request-id").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_skip_detection_for_short_inputs() {
+        let (orchestrator, captured) = spawn_orchestrator(completion_response(Value::Null)).await;
+        let mut gateway_config = test_config(orchestrator);
+        gateway_config.detectors = vec![regex_detector(true, true)];
+        gateway_config.routes[0].detectors = vec!["regex".to_string()];
+        gateway_config.routes[0].skip_detection_if =
+            Some(SkipDetectionConfig { max_chars: Some(6) });
+        let gateway = spawn_gateway(&gateway_config).await;
+
+        for content in ["hi", "hello there"] {
+            reqwest::Client::new()
+                .post(format!("http://{}/test/v1/chat/completions", gateway))
+                .json(&json!({
+                    "model": "test-model",
+                    "messages": [{"role": "user", "content": content}]
+                }))
+                .send()
+                .await
+                .unwrap();
+        }
+
+        let captured = captured.lock().unwrap();
+        assert!(captured[0].1.get("detectors").is_none());
+        assert!(captured[1].1["detectors"]["input"]["regex"].is_object());
+    }
+
+    #[tokio::test]
+    async fn test_identical_concurrent_requests_are_coalesced() {
+        let calls = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let counter = calls.clone();
+        let app = Router::new().route(
+            "/api/v2/chat/completions-detection",
+            post(move || async move {
+                counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+                Json(completion_response(Value::Null))
+            }),
+        );
+        let orchestrator = spawn_server(app).await;
+        let mut gateway_config = test_config(orchestrator);
+        gateway_config.routes[0].coalesce_identical_requests = true;
+        let gateway = spawn_gateway(&gateway_config).await;
+
+        let client = reqwest::Client::new();
+        let requests = (0..5).map(|_| {
+            client
+                .post(format!("http://{}/test/v1/chat/completions", gateway))
+                .json(&json!({"model": "m", "messages": [{"role": "user", "content": "hi"}]}))
+                .send()
+        });
+        for response in futures::future::join_all(requests).await {
+            let body: Value = response.unwrap().json().await.unwrap();
+            assert_eq!(body["id"], completion_response(Value::Null)["id"]);
+        }
+        assert_eq!(calls.load(std::sync::atomic::Ordering::SeqCst), 1);
+
+        // Once the shared call has finished, the next request goes upstream again
+        client
+            .post(format!("http://{}/test/v1/chat/completions", gateway))
+            .json(&json!({"model": "m", "messages": [{"role": "user", "content": "hi"}]}))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(calls.load(std::sync::atomic::Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_every_request_is_logged_once() {
+        let (orchestrator, _) = spawn_orchestrator(completion_response(Value::Null)).await;
+        let mut gateway_config = test_config(orchestrator);
+        gateway_config.routes[0].name = "logged_once".to_string();
+        gateway_config.routes[0].buffered_streaming = true;
+        gateway_config.routes[0].allowed_models = Some(vec!["m".to_string()]);
+        let mut upstream_route = gateway_config.routes[0].clone();
+        upstream_route.name = "logged_once_upstream".to_string();
+        upstream_route.buffer_upstream_stream = true;
+        gateway_config.routes.push(upstream_route);
+        let gateway = spawn_gateway(&gateway_config).await;
+        let client = reqwest::Client::new();
+
+        for (route, body) in [
+            // Buffered streaming asks the orchestrator for a non-streaming response
+            (
+                "logged_once",
+                json!({"model": "m", "messages": user_messages(), "stream": true}),
+            ),
+            ("logged_once", json!({"model": "m", "messages": []})),
+            (
+                "logged_once",
+                json!({"model": "other", "messages": user_messages()}),
+            ),
+            (
+                "logged_once_upstream",
+                json!({"model": "m", "messages": user_messages(), "stream": true}),
+            ),
+        ] {
+            client
+                .post(format!("http://{}/{}/v1/chat/completions", gateway, route))
+                .json(&body)
+                .send()
+                .await
+                .unwrap();
+        }
+
+        let body = reqwest::get(format!("http://{}/metrics", gateway))
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+        for line in [
+            "gateway_requests_total{route=\"logged_once\",streaming=\"true\"} 1",
+            "gateway_requests_total{route=\"logged_once\",streaming=\"false\"} 2",
+            "gateway_requests_total{route=\"logged_once_upstream\",streaming=\"true\"} 1",
+        ] {
+            assert!(body.contains(line), "missing {} in {}", line, body);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_coalesced_requests_are_each_logged() {
+        let app = Router::new().route(
+            "/api/v2/chat/completions-detection",
+            post(|| async {
+                tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+                Json(completion_response(Value::Null))
+            }),
+        );
+        let orchestrator = spawn_server(app).await;
+        let mut gateway_config = test_config(orchestrator);
+        gateway_config.routes[0].name = "coalesced_log".to_string();
+        gateway_config.routes[0].coalesce_identical_requests = true;
+        let gateway = spawn_gateway(&gateway_config).await;
+
+        let client = reqwest::Client::new();
+        let requests = (0..3).map(|_| {
+            client
+                .post(format!(
+                    "http://{}/coalesced_log/v1/chat/completions",
+                    gateway
+                ))
+                .json(&json!({"model": "m", "messages": user_messages()}))
+                .send()
+        });
+        futures::future::join_all(requests).await;
+
+        let body = reqwest::get(format!("http://{}/metrics", gateway))
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+        assert!(
+            body.contains("gateway_requests_total{route=\"coalesced_log\",streaming=\"false\"} 3"),
+            "{}",
+            body
+        );
+    }
+
+    #[tokio::test]
+    async fn test_orchestrator_headers_are_sent() {
+        let (orchestrator, captured) = spawn_orchestrator(completion_response(Value::Null)).await;
+        let mut gateway_config = test_config(orchestrator);
+        gateway_config.orchestrator_headers = HashMap::from([
+            ("accept".to_string(), "application/json".to_string()),
+            ("x-api-version".to_string(), "2".to_string()),
+        ]);
+        let gateway = spawn_gateway(&gateway_config).await;
+
+        reqwest::Client::new()
+            .post(format!("http://{}/test/v1/chat/completions", gateway))
+            .json(&json!({"model": "test-model", "messages": user_messages()}))
+            .send()
+            .await
+            .unwrap();
+
+        let captured = captured.lock().unwrap();
+        let (headers, _) = &captured[0];
+        assert_eq!(headers["accept"], "application/json");
+        assert_eq!(headers["x-api-version"], "2");
+    }
+
+    #[tokio::test]
+    async fn test_orchestrator_headers_replace_forwarded_headers() {
+        let (orchestrator, captured) = spawn_orchestrator(completion_response(Value::Null)).await;
+        let mut gateway_config = test_config(orchestrator);
+        gateway_config.orchestrator_headers = HashMap::from([
+            (
+                "authorization".to_string(),
+                "Bearer orchestrator".to_string(),
+            ),
+            (
+                "content-type".to_string(),
+                "application/json; charset=utf-8".to_string(),
+            ),
+        ]);
+        let gateway = spawn_gateway(&gateway_config).await;
+
+        reqwest::Client::new()
+            .post(format!("http://{}/test/v1/chat/completions", gateway))
+            .header("authorization", "Bearer client")
+            .json(&json!({"model": "test-model", "messages": user_messages()}))
+            .send()
+            .await
+            .unwrap();
+
+        let captured = captured.lock().unwrap();
+        let (headers, _) = &captured[0];
+        let values = |name| headers.get_all(name).iter().collect::<Vec<_>>();
+        assert_eq!(values("authorization"), ["Bearer orchestrator"]);
+        assert_eq!(values("content-type"), ["application/json; charset=utf-8"]);
+    }
+
+    #[tokio::test]
+    async fn test_tenant_claim_is_forwarded() {
+        let (orchestrator, captured) = spawn_orchestrator(completion_response(Value::Null)).await;
+        let mut gateway_config = test_config(orchestrator);
+        gateway_config.tenant_claim = Some(config::TenantClaimConfig {
+            claim: "tenant".to_string(),
+            header: "x-tenant-id".to_string(),
+            hs256_secret: None,
+        });
+        let gateway = spawn_gateway(&gateway_config).await;
+        let token = jwt::tests::token(json!({"tenant": "acme"}), "secret");
+
+        for authorization in [format!("Bearer {}", token), "Bearer opaque".to_string()] {
+            reqwest::Client::new()
+                .post(format!("http://{}/test/v1/chat/completions", gateway))
+                .header("authorization", authorization)
+                .header("x-tenant-id", "spoofed")
+                .json(&json!({"model": "test-model", "messages": user_messages()}))
+                .send()
+                .await
+                .unwrap();
+        }
+
+        let captured = captured.lock().unwrap();
+        assert_eq!(captured[0].0["x-tenant-id"], "acme");
+        assert!(captured[1].0.get("x-tenant-id").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_spoofed_tenant_is_dropped_on_every_route() {
+        let captured: Captured = Arc::new(Mutex::new(Vec::new()));
+        let recorder = captured.clone();
+        let app = Router::new().route(
+            "/api/v2/text/detection/chat",
+            post(
+                move |headers: HeaderMap, Json(body): Json<Value>| async move {
+                    recorder.lock().unwrap().push((headers, body));
+                    Json(json!({"detections": []}))
+                },
+            ),
+        );
+        let mut gateway_config = test_config(spawn_server(app).await);
+        gateway_config.routes[0].expose_verdict = true;
+        gateway_config.tenant_claim = Some(config::TenantClaimConfig {
+            claim: "tenant".to_string(),
+            header: "x-tenant-id".to_string(),
+            hs256_secret: None,
+        });
+        let gateway = spawn_gateway(&gateway_config).await;
+        let token = jwt::tests::token(json!({"tenant": "acme"}), "secret");
+
+        let verdict = |authorization: Option<String>| {
+            let request = reqwest::Client::new()
+                .post(format!("http://{}/test/v1/verdict", gateway))
+                .header("x-tenant-id", "spoofed")
+                .json(&json!({"messages": user_messages()}));
+            match authorization {
+                Some(authorization) => request.header("authorization", authorization),
+                None => request,
+            }
+            .send()
+        };
+        verdict(None).await.unwrap();
+        verdict(Some(format!("Bearer {}", token))).await.unwrap();
+
+        let captured = captured.lock().unwrap();
+        assert!(captured[0].0.get("x-tenant-id").is_none());
+        assert_eq!(captured[1].0["x-tenant-id"], "acme");
+    }
+
+    #[tokio::test]
+    async fn test_forwarded_headers_are_capped() {
+        let (orchestrator, captured) = spawn_orchestrator(completion_response(Value::Null)).await;
+        let gateway = spawn_gateway(&test_config(orchestrator)).await;
+
+        let mut request = reqwest::Client::new()
+            .post(format!("http://{}/test/v1/chat/completions", gateway))
+            .json(&json!({"model": "test-model", "messages": user_messages()}));
+        for i in 0..90 {
+            request = request.header(format!("x-forwarded-{}", i), "value");
+        }
+        let response = request.send().await.unwrap();
+
+        assert!(response.status().is_success());
+        let captured = captured.lock().unwrap();
+        let (headers, _) = &captured[0];
+        let forwarded = headers
+            .keys()
+            .filter(|name| name.as_str().starts_with("x-forwarded-"))
+            .count();
+        assert_eq!(forwarded, 64);
+    }
+
+    #[tokio::test]
+    async fn test_trace_context_is_forwarded() {
+        let traceparent = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+        let (orchestrator, captured) = spawn_orchestrator(completion_response(Value::Null)).await;
+        let (streaming_orchestrator, streaming_captured) =
+            spawn_streaming_orchestrator("data: [DONE]\n\n").await;
+
+        for (orchestrator, stream) in [(orchestrator, false), (streaming_orchestrator, true)] {
+            let gateway = spawn_gateway(&test_config(orchestrator)).await;
+            reqwest::Client::new()
+                .post(format!("http://{}/test/v1/chat/completions", gateway))
+                .header("traceparent", traceparent)
+                .header("tracestate", "vendor=value")
+                .json(&json!({"model": "m", "messages": user_messages(), "stream": stream}))
+                .send()
+                .await
+                .unwrap()
+                .text()
+                .await
+                .unwrap();
+        }
+
+        for captured in [captured, streaming_captured] {
+            let (headers, _) = &captured.lock().unwrap()[0];
+            assert_eq!(headers["traceparent"], traceparent);
+            assert_eq!(headers["tracestate"], "vendor=value");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_traceparent_is_generated_when_absent() {
+        let (orchestrator, captured) = spawn_orchestrator(completion_response(Value::Null)).await;
+        let gateway = spawn_gateway(&test_config(orchestrator)).await;
+
+        reqwest::Client::new()
+            .post(format!("http://{}/test/v1/chat/completions", gateway))
+            .json(&json!({"model": "m", "messages": user_messages()}))
+            .send()
+            .await
+            .unwrap();
+
+        let (headers, _) = &captured.lock().unwrap()[0];
+        let traceparent = headers["traceparent"].to_str().unwrap();
+        let parts: Vec<&str> = traceparent.split('-').collect();
+        assert_eq!(parts.len(), 4, "{}", traceparent);
+        assert_eq!((parts[0], parts[1].len(), parts[2].len()), ("00", 32, 16));
+        assert!(!headers.contains_key("tracestate"));
+    }
+
+    #[tokio::test]
+    async fn test_gateway_metadata_is_injected_when_enabled() {
+        let (orchestrator, captured) = spawn_orchestrator(completion_response(Value::Null)).await;
+        let mut gateway_config = test_config(orchestrator);
+        gateway_config.inject_gateway_metadata = true;
+        let gateway = spawn_gateway(&gateway_config).await;
+
+        reqwest::Client::new()
+            .post(format!("http://{}/test/v1/chat/completions", gateway))
+            .header("x-request-id", "req-1")
+            .json(&json!({"model": "test-model", "messages": user_messages()}))
+            .send()
+            .await
+            .unwrap();
+
+        let captured = captured.lock().unwrap();
+        let (_, body) = &captured[0];
+        assert_eq!(
+            body[GATEWAY_METADATA_KEY],
+            json!({
+                "route": "test",
+                "gateway_version": env!("CARGO_PKG_VERSION"),
+                "request_id": "req-1",
+            })
+        );
+        assert_eq!(body["model"], "test-model");
+        assert!(body.get("detectors").is_some());
+    }
+
+    #[tokio::test]
+    async fn test_gateway_metadata_is_omitted_by_default() {
+        let (orchestrator, captured) = spawn_orchestrator(completion_response(Value::Null)).await;
+        let gateway = spawn_gateway(&test_config(orchestrator)).await;
+
+        reqwest::Client::new()
+            .post(format!("http://{}/test/v1/chat/completions", gateway))
+            .json(&json!({"model": "test-model", "messages": user_messages()}))
+            .send()
+            .await
+            .unwrap();
+
+        let captured = captured.lock().unwrap();
+        assert!(captured[0].1.get(GATEWAY_METADATA_KEY).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_streaming_ignores_data_after_done() {
+        let (orchestrator, _) = spawn_streaming_orchestrator(concat!(
+            "data: {\"id\":\"1\",\"object\":\"chat.completion.chunk\",\"created\":1,\"model\":\"m\",",
+            "\"choices\":[{\"index\":0,\"delta\":{\"content\":\"hi\"}}]}\n\n",
+            "data: [DONE]\n\n",
+            "data: {\"stray\": true}\n\n",
+            "\n\n",
+        ))
+        .await;
+        let gateway = spawn_gateway(&test_config(orchestrator)).await;
+
+        let body = reqwest::Client::new()
+            .post(format!("http://{}/test/v1/chat/completions", gateway))
+            .json(&json!({"model": "m", "messages": user_messages(), "stream": true}))
+            .send()
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+
+        let events = sse_data(&body);
+        assert_eq!(events.len(), 1, "{}", body);
+        let chunk: StreamingResponse = serde_json::from_str(events[0]).unwrap();
+        assert_eq!(chunk.choices[0].delta.content.as_deref(), Some("hi"));
+    }
+
+    #[tokio::test]
+    async fn test_detections_trailer() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        const DETECTED: &str = concat!(
+            "data: {\"id\":\"1\",\"object\":\"chat.completion.chunk\",\"created\":1,",
+            "\"model\":\"m\",\"choices\":[{\"index\":0,\"delta\":{\"content\":\"hi\"}}],",
+            "\"detections\":{\"input\":null,\"output\":[{\"choice_index\":0,\"results\":[",
+            "{\"start\":0,\"end\":2,\"text\":\"hi\",\"detection_type\":\"pii\",",
+            "\"detection\":\"EmailAddress\",\"detector_id\":\"regex\",\"score\":1.0}]}]}}\n\n"
+        );
+        let orchestrator = spawn_chunked_orchestrator(&[DETECTED, "data: [DONE]\n\n"]).await;
+        let mut gateway_config = test_config(orchestrator);
+        gateway_config.routes[0].fallback_message = None;
+        gateway_config.routes[0].detections_trailer = true;
+        let gateway = spawn_gateway(&gateway_config).await;
+
+        // reqwest cannot read trailers, so speak HTTP/1.1 directly
+        let body =
+            r#"{"model": "m", "messages": [{"role": "user", "content": "hi"}], "stream": true}"#;
+        let mut stream = tokio::net::TcpStream::connect(gateway).await.unwrap();
+        stream
+            .write_all(
+                format!(
+                    "POST /test/v1/chat/completions HTTP/1.1\r\nhost: gateway\r\n\
+                     te: trailers\r\nconnection: close\r\ncontent-type: application/json\r\n\
+                     content-length: {}\r\n\r\n{}",
+                    body.len(),
+                    body
+                )
+                .as_bytes(),
+            )
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+
+        assert!(
+            response.contains("trailer: x-gateway-detections\r\n"),
+            "{}",
+            response
+        );
+        let trailer = response
+            .lines()
+            .find_map(|line| line.strip_prefix("x-gateway-detections: "))
+            .unwrap();
+        let summary: DetectionSummary = serde_json::from_str(trailer).unwrap();
+        assert_eq!(
+            summary,
+            DetectionSummary {
+                input_count: 0,
+                output_count: 1,
+                blocked_by: Some("output".to_string()),
+                detection_types: vec!["pii".to_string()],
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_streaming_ends_with_done() {
+        const DETECTED: &str = concat!(
+            "data: {\"id\":\"1\",\"object\":\"chat.completion.chunk\",\"created\":1,",
+            "\"model\":\"m\",\"choices\":[{\"index\":0,\"delta\":{\"content\":\"hi\"}}],",
+            "\"detections\":{\"input\":null,\"output\":[{\"choice_index\":0,\"results\":[",
+            "{\"start\":0,\"end\":2,\"text\":\"hi\",\"detection_type\":\"pii\",",
+            "\"detection\":\"EmailAddress\",\"detector_id\":\"regex\",\"score\":1.0}]}]}}\n\n"
+        );
+        // The orchestrator ends the stream without a [DONE] of its own
+        let orchestrator = spawn_chunked_orchestrator(&[DETECTED, DETECTED]).await;
+        let gateway = spawn_gateway(&test_config(orchestrator)).await;
+
+        let body = reqwest::Client::new()
+            .post(format!("http://{}/test/v1/chat/completions", gateway))
+            .json(&json!({"model": "m", "messages": user_messages(), "stream": true}))
+            .send()
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+
+        let data: Vec<&str> = body
+            .lines()
+            .filter_map(|line| line.strip_prefix("data: "))
+            .collect();
+        // The stream ends at the first blocked frame
+        assert_eq!(data.len(), 2, "{}", body);
+        assert_eq!(data.last(), Some(&"[DONE]"));
+        assert_eq!(data.iter().filter(|data| **data == "[DONE]").count(), 1);
+    }
+
+    /// Builds a raw SSE frame with the given choices and detections.
+    fn detection_frame(choices: Value, detections: Value) -> String {
+        let frame = json!({
+            "id": "1", "object": "chat.completion.chunk", "created": 1, "model": "m",
+            "choices": choices,
+            "detections": detections,
+        });
+        format!("data: {}\n\n", frame)
+    }
+
+    async fn stream_through_gateway(frames: Vec<String>) -> Vec<StreamingResponse> {
+        let orchestrator = spawn_chunked_orchestrator(&frames).await;
+        let gateway = spawn_gateway(&test_config(orchestrator)).await;
+        let body = reqwest::Client::new()
+            .post(format!("http://{}/test/v1/chat/completions", gateway))
+            .json(&json!({"model": "m", "messages": user_messages(), "stream": true}))
+            .send()
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+        sse_data(&body)
+            .into_iter()
+            .map(|data| serde_json::from_str(data).unwrap())
+            .collect()
+    }
+
+    fn content_choice(content: &str) -> Value {
+        json!([{"index": 0, "delta": {"content": content}}])
+    }
+
+    #[tokio::test]
+    async fn test_streaming_input_detection_blocks_at_start() {
+        let input_detections = json!({
+            "input": [{"message_index": 0, "results": [{
+                "start": 0, "end": 2, "text": "hi", "detection_type": "pii",
+                "detection": "EmailAddress", "detector_id": "regex", "score": 1.0
+            }]}],
+            "output": null
+        });
+        let chunks = stream_through_gateway(vec![
+            // Input detections come on a frame of their own, before any choices
+            detection_frame(json!([]), input_detections),
+            detection_frame(content_choice("generated"), Value::Null),
+        ])
+        .await;
+
+        assert_eq!(chunks.len(), 1);
+        assert_eq!(
+            chunks[0].choices[0].delta.content.as_deref(),
+            Some("fallback")
+        );
+        assert_eq!(chunks[0].choices[0].finish_reason.as_deref(), Some("stop"));
+    }
+
+    #[tokio::test]
+    async fn test_streaming_output_detection_blocks_as_generated() {
+        let chunks = stream_through_gateway(vec![
+            detection_frame(content_choice("my email is "), Value::Null),
+            detection_frame(content_choice("a@b.com"), output_detections()),
+            detection_frame(content_choice(" and more"), Value::Null),
+        ])
+        .await;
+
+        let content: Vec<Option<&str>> = chunks
+            .iter()
+            .map(|chunk| chunk.choices[0].delta.content.as_deref())
+            .collect();
+        assert_eq!(content, [Some("my email is "), Some("fallback")]);
+    }
+
+    #[tokio::test]
+    async fn test_streaming_detections_without_results_pass_through() {
+        let chunks = stream_through_gateway(vec![
+            detection_frame(
+                content_choice("clean"),
+                json!({"input": [{"message_index": 0, "results": []}], "output": null}),
+            ),
+            detection_frame(
+                content_choice(" text"),
+                json!({"input": null, "output": [{"choice_index": 0, "results": null}]}),
+            ),
+        ])
+        .await;
+
+        let content: Vec<Option<&str>> = chunks
+            .iter()
+            .map(|chunk| chunk.choices[0].delta.content.as_deref())
+            .collect();
+        assert_eq!(content, [Some("clean"), Some(" text")]);
+    }
+
+    #[tokio::test]
+    async fn test_malformed_frame_policies() {
+        const CHUNK: &str = concat!(
+            "data: {\"id\":\"1\",\"object\":\"chat.completion.chunk\",\"created\":1,\"model\":\"m\",",
+            "\"choices\":[{\"index\":0,\"delta\":{\"content\":\"hi\"}}]}\n\n"
+        );
+        let orchestrator =
+            spawn_chunked_orchestrator(&[CHUNK, "data: not json\n\n", CHUNK, "data: [DONE]\n\n"])
+                .await;
+
+        let cases = [
+            (
+                MalformedFramePolicy::Passthrough,
+                vec!["chunk", "not json", "chunk"],
+            ),
+            (MalformedFramePolicy::Drop, vec!["chunk", "chunk"]),
+            // The open choice is finished before the error, and nothing follows it
+            (MalformedFramePolicy::Error, vec!["chunk", "chunk", "error"]),
+        ];
+        for (policy, expected) in cases {
+            let mut gateway_config = test_config(orchestrator);
+            gateway_config.malformed_frame_policy = policy;
+            let gateway = spawn_gateway(&gateway_config).await;
+
+            let body = reqwest::Client::new()
+                .post(format!("http://{}/test/v1/chat/completions", gateway))
+                .json(&json!({"model": "m", "messages": user_messages(), "stream": true}))
+                .send()
+                .await
+                .unwrap()
+                .text()
+                .await
+                .unwrap();
+
+            let events: Vec<&str> = sse_data(&body)
+                .into_iter()
+                .map(|data| match serde_json::from_str::<Value>(data) {
+                    Ok(value) if value.get("error").is_some() => "error",
+                    Ok(_) => "chunk",
+                    Err(_) => data,
+                })
+                .collect();
+            assert_eq!(events, expected, "{:?}", policy);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stream_error_event() {
+        let chunk = |choices: Value| {
+            let chunk = json!({
+                "id": "1", "object": "chat.completion.chunk", "created": 1, "model": "m",
+                "choices": choices,
+            });
+            format!("data: {}\n\n", chunk).into_bytes()
+        };
+        let frames = vec![
+            chunk(json!([
+                {"index": 0, "delta": {"content": "hi"}, "finish_reason": null},
+                {"index": 1, "delta": {"content": "hello"}, "finish_reason": null},
+            ])),
+            chunk(json!([{"index": 1, "delta": {}, "finish_reason": "stop"}])),
+            // Not UTF-8, which the stream cannot continue past
+            b"data: \xff\xfe\n\n".to_vec(),
+            chunk(json!([{"index": 0, "delta": {"content": "more"}, "finish_reason": null}])),
+            b"data: [DONE]\n\n".to_vec(),
+        ];
+        let app = Router::new().route(
+            "/api/v2/chat/completions-detection",
+            post(move || async move {
+                let body = futures::stream::iter(frames).then(|frame| async move {
+                    tokio::time::sleep(Duration::from_millis(20)).await;
+                    Ok::<_, std::convert::Infallible>(frame)
+                });
+                (
+                    [("content-type", "text/event-stream")],
+                    axum::body::Body::from_stream(body),
+                )
+            }),
+        );
+        let orchestrator = spawn_server(app).await;
+        let gateway = spawn_gateway(&test_config(orchestrator)).await;
+
+        let body = reqwest::Client::new()
+            .post(format!("http://{}/test/v1/chat/completions", gateway))
+            .json(&json!({"model": "m", "messages": user_messages(), "stream": true}))
+            .send()
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+
+        let events = sse_data(&body);
+        assert_eq!(events.len(), 4, "{}", body);
+        // Only the choice still open is finished
+        let finished: StreamingResponse = serde_json::from_str(events[2]).unwrap();
+        assert_eq!(finished.id, "1");
+        assert_eq!(finished.choices.len(), 1);
+        assert_eq!(finished.choices[0].index, 0);
+        assert_eq!(finished.choices[0].finish_reason.as_deref(), Some("error"));
+        let error: Value = serde_json::from_str(events[3]).unwrap();
+        assert_eq!(error["error"]["type"], "upstream_error");
+        assert!(error["error"]["message"].is_string(), "{}", error);
+        // The error is sent as an `error` event, and nothing but [DONE] follows it
+        let error_event = body.find("event: error\n").expect(&body);
+        assert!(body[error_event..].contains(&format!("data: {}\n", events[3])));
+        assert!(!body.contains("more"), "{}", body);
+    }
+
+    #[tokio::test]
+    async fn test_inbound_timeout() {
+        let app = Router::new().route(
+            "/api/v2/chat/completions-detection",
+            post(|| async {
+                tokio::time::sleep(Duration::from_secs(5)).await;
+                Json(completion_response(Value::Null))
+            }),
+        );
+        let orchestrator = spawn_server(app).await;
+        let mut gateway_config = test_config(orchestrator);
+        gateway_config.inbound_timeout_ms = Some(100);
+        let gateway = spawn_gateway(&gateway_config).await;
+
+        let response = reqwest::Client::new()
+            .post(format!("http://{}/test/v1/chat/completions", gateway))
+            .json(&json!({"model": "m", "messages": user_messages()}))
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::GATEWAY_TIMEOUT);
+    }
+
+    #[tokio::test]
+    async fn test_inbound_timeout_skips_streaming_requests() {
+        // The stream starts well after the inbound timeout, and buffered mode, which asks the
+        // orchestrator for the whole completion at once, waits as long for it
+        let app = Router::new().route(
+            "/api/v2/chat/completions-detection",
+            post(|Json(body): Json<Value>| async move {
+                tokio::time::sleep(Duration::from_millis(500)).await;
+                if body["stream"] == true {
+                    (
+                        [("content-type", "text/event-stream")],
+                        detection_frame(content_choice("hi"), Value::Null),
+                    )
+                        .into_response()
+                } else {
+                    Json(completion_response(Value::Null)).into_response()
+                }
+            }),
+        );
+        let orchestrator = spawn_server(app).await;
+
+        for buffered_streaming in [false, true] {
+            let mut gateway_config = test_config(orchestrator);
+            gateway_config.inbound_timeout_ms = Some(100);
+            gateway_config.routes[0].buffered_streaming = buffered_streaming;
+            let gateway = spawn_gateway(&gateway_config).await;
+
+            let response = reqwest::Client::new()
+                .post(format!("http://{}/test/v1/chat/completions", gateway))
+                .json(&json!({"model": "m", "messages": user_messages(), "stream": true}))
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK, "{}", buffered_streaming);
+            let body = response.text().await.unwrap();
+            assert!(!sse_data(&body).is_empty(), "{}", body);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_orchestrator_timeout_is_json_gateway_timeout() {
+        let app = Router::new().route(
+            "/api/v2/chat/completions-detection",
+            post(|| async {
+                tokio::time::sleep(Duration::from_secs(5)).await;
+                Json(completion_response(Value::Null))
+            }),
+        );
+        let orchestrator = spawn_server(app).await;
+        let mut gateway_config = test_config(orchestrator);
+        // The route's timeout takes precedence over the orchestrator default
+        gateway_config.orchestrator.timeout_seconds = Some(60);
+        gateway_config.routes[0].timeout_seconds = Some(1);
+        let gateway = spawn_gateway(&gateway_config).await;
+
+        let started = std::time::Instant::now();
+        let response = reqwest::Client::new()
+            .post(format!("http://{}/test/v1/chat/completions", gateway))
+            .json(&json!({"model": "m", "messages": user_messages()}))
+            .send()
+            .await
+            .unwrap();
+
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(response.status(), StatusCode::GATEWAY_TIMEOUT);
+        let body: Value = response.json().await.unwrap();
+        assert_eq!(body["error"]["type"], "timeout");
+    }
+
+    #[tokio::test]
+    async fn test_streaming_idle_timeout() {
+        let app = Router::new().route(
+            "/api/v2/chat/completions-detection",
+            post(|| async {
+                let first = concat!(
+                    "data: {\"id\":\"1\",\"object\":\"chat.completion.chunk\",\"created\":1,",
+                    "\"model\":\"m\",\"choices\":[{\"index\":0,\"delta\":{\"content\":\"hi\"}}]}\n\n",
+                );
+                // The orchestrator hangs after the first chunk
+                let body = futures::stream::once(async move {
+                    Ok::<_, std::convert::Infallible>(first)
+                })
+                .chain(futures::stream::pending());
+                (
+                    [("content-type", "text/event-stream")],
+                    axum::body::Body::from_stream(body),
+                )
+            }),
+        );
+        let orchestrator = spawn_server(app).await;
+        let mut gateway_config = test_config(orchestrator);
+        gateway_config.orchestrator.timeout_seconds = Some(1);
+        let gateway = spawn_gateway(&gateway_config).await;
+
+        let body = reqwest::Client::new()
+            .post(format!("http://{}/test/v1/chat/completions", gateway))
+            .json(&json!({"model": "m", "messages": user_messages(), "stream": true}))
+            .send()
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+
+        let events = sse_data(&body);
+        assert_eq!(events.len(), 3, "{}", body);
+        let chunk: StreamingResponse = serde_json::from_str(events[0]).unwrap();
+        assert_eq!(chunk.choices[0].delta.content.as_deref(), Some("hi"));
+        let chunk: StreamingResponse = serde_json::from_str(events[1]).unwrap();
+        assert_eq!(chunk.choices[0].finish_reason.as_deref(), Some("error"));
+        assert!(
+            events[2].contains("Orchestrator sent nothing for 1s"),
+            "{}",
+            body
+        );
+    }
+
+    #[tokio::test]
+    async fn test_orchestrator_error_status_is_passed_through() {
+        for (status, body, expected) in [
+            (
+                StatusCode::BAD_REQUEST,
+                r#"{"detail": "unknown model"}"#,
+                json!({"message": "unknown model", "type": "invalid_request_error", "code": null}),
+            ),
+            (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                r#"{"code": 422, "details": "invalid params"}"#,
+                json!({"message": "invalid params", "type": "invalid_request_error", "code": null}),
+            ),
+            (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "overloaded",
+                json!({"message": "overloaded", "type": "upstream_error", "code": null}),
+            ),
+        ] {
+            let app = Router::new().route(
+                "/api/v2/chat/completions-detection",
+                post(move || async move { (status, body) }),
+            );
+            let orchestrator = spawn_server(app).await;
+            let gateway = spawn_gateway(&test_config(orchestrator)).await;
+
+            for stream in [false, true] {
+                let response = reqwest::Client::new()
+                    .post(format!("http://{}/test/v1/chat/completions", gateway))
+                    .json(&json!({"model": "m", "messages": user_messages(), "stream": stream}))
+                    .send()
+                    .await
+                    .unwrap();
+                assert_eq!(response.status(), status, "stream: {}", stream);
+                assert_eq!(
+                    response.json::<Value>().await.unwrap(),
+                    json!({"error": expected}),
+                    "stream: {}",
+                    stream
+                );
+            }
+        }
+    }
+
+    /// Starts a mock orchestrator that answers with `status` for the first `failures` requests and
+    /// with a completion after that, counting every request it receives.
+    async fn spawn_flaky_orchestrator(
+        failures: usize,
+        status: StatusCode,
+    ) -> (SocketAddr, Arc<std::sync::atomic::AtomicUsize>) {
+        let requests = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let counter = requests.clone();
+        let app = Router::new().route(
+            "/api/v2/chat/completions-detection",
+            post(move || async move {
+                if counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst) < failures {
+                    (status, "unavailable").into_response()
+                } else {
+                    Json(completion_response(Value::Null)).into_response()
+                }
+            }),
+        );
+        (spawn_server(app).await, requests)
+    }
+
+    #[tokio::test]
+    async fn test_transient_orchestrator_errors_are_retried() {
+        let send = |gateway: SocketAddr| {
+            reqwest::Client::new()
+                .post(format!("http://{}/test/v1/chat/completions", gateway))
+                .json(&json!({"model": "m", "messages": user_messages()}))
+                .send()
+        };
+        let retrying_config = |orchestrator, max_retries| {
+            let mut gateway_config = test_config(orchestrator);
+            gateway_config.orchestrator.max_retries = max_retries;
+            gateway_config.orchestrator.backoff_ms = 1;
+            gateway_config
+        };
+
+        let (orchestrator, requests) =
+            spawn_flaky_orchestrator(2, StatusCode::SERVICE_UNAVAILABLE).await;
+        let gateway = spawn_gateway(&retrying_config(orchestrator, 2)).await;
+        let response = send(gateway).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(requests.load(std::sync::atomic::Ordering::SeqCst), 3);
+
+        // Once the retries run out the last error is returned
+        let (orchestrator, requests) = spawn_flaky_orchestrator(2, StatusCode::BAD_GATEWAY).await;
+        let gateway = spawn_gateway(&retrying_config(orchestrator, 1)).await;
+        let response = send(gateway).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+        assert_eq!(requests.load(std::sync::atomic::Ordering::SeqCst), 2);
+
+        // Other errors are not retried
+        let (orchestrator, requests) =
+            spawn_flaky_orchestrator(1, StatusCode::INTERNAL_SERVER_ERROR).await;
+        let gateway = spawn_gateway(&retrying_config(orchestrator, 2)).await;
+        let response = send(gateway).await.unwrap();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(requests.load(std::sync::atomic::Ordering::SeqCst), 1);
+
+        // Nor are streaming requests
+        let (orchestrator, requests) =
+            spawn_flaky_orchestrator(1, StatusCode::SERVICE_UNAVAILABLE).await;
+        let gateway = spawn_gateway(&retrying_config(orchestrator, 2)).await;
+        let response = reqwest::Client::new()
+            .post(format!("http://{}/test/v1/chat/completions", gateway))
+            .json(&json!({"model": "m", "messages": user_messages(), "stream": true}))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(requests.load(std::sync::atomic::Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_is_transient() {
+        let status = |code| OrchestratorError::Status {
+            code,
+            body: String::new(),
+        };
+        assert!(is_transient(&status(StatusCode::SERVICE_UNAVAILABLE)));
+        assert!(is_transient(&status(StatusCode::GATEWAY_TIMEOUT)));
+        assert!(!is_transient(&status(StatusCode::TOO_MANY_REQUESTS)));
+        assert!(!is_transient(&OrchestratorError::EmptyBody));
+        assert!(!is_transient(&OrchestratorError::IdleTimeout(
+            Duration::from_secs(1)
+        )));
+
+        // Only errors connecting are retried; a request that cannot even be built is not
+        let unused = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let connect = reqwest::get(format!("http://{}", unused))
+            .await
+            .unwrap_err();
+        let connect = OrchestratorError::from(connect);
+        assert!(matches!(connect, OrchestratorError::Connect(_)));
+        assert!(is_transient(&connect));
+        let builder = reqwest::get("not a url").await.unwrap_err();
+        let builder = OrchestratorError::from(builder);
+        assert!(matches!(builder, OrchestratorError::Request(_)));
+        assert!(!is_transient(&builder));
+        let OrchestratorError::Request(e) = builder else {
+            unreachable!()
+        };
+        assert!(!is_transient(&OrchestratorError::IncompleteBody(e)));
+    }
+
+    #[tokio::test]
+    async fn test_unexpected_orchestrator_response_is_bad_gateway() {
+        let (orchestrator, _) =
+            spawn_orchestrator(json!({"error": {"code": "overloaded", "retry": true}})).await;
+        let gateway = spawn_gateway(&test_config(orchestrator)).await;
+
+        let response = reqwest::Client::new()
+            .post(format!("http://{}/test/v1/chat/completions", gateway))
+            .json(&json!({"model": "m", "messages": user_messages()}))
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+        let body: Value = response.json().await.unwrap();
+        assert_eq!(body["error"]["type"], "upstream_error");
+        let message = body["error"]["message"].as_str().unwrap();
+        assert!(message.contains("unexpected response shape"), "{}", message);
+        assert!(message.contains(r#""code":"overloaded""#), "{}", message);
+    }
+
+    #[test]
+    fn test_body_snippet() {
+        assert_eq!(body_snippet("short"), "short");
+        let long = "é".repeat(300);
+        assert_eq!(body_snippet(&long), format!("{}...", "é".repeat(200)));
+    }
+
+    #[tokio::test]
+    async fn test_orchestrator_closing_mid_body_is_bad_gateway() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let orchestrator = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = [0; 4096];
+            let _ = socket.read(&mut request).await.unwrap();
+            socket
+                .write_all(
+                    b"HTTP/1.1 200 OK\r\ncontent-type: application/json\r\n\
+                      content-length: 1000\r\n\r\n{\"id\": \"1\",",
+                )
+                .await
+                .unwrap();
+            // Dropping the socket closes the connection with most of the body missing
+        });
+        let gateway = spawn_gateway(&test_config(orchestrator)).await;
+
+        let response = reqwest::Client::new()
+            .post(format!("http://{}/test/v1/chat/completions", gateway))
+            .json(&json!({"model": "m", "messages": user_messages()}))
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+        assert!(response
+            .text()
+            .await
+            .unwrap()
+            .contains("closed the connection before the response was complete"));
+    }
+
+    #[tokio::test]
+    async fn test_streaming_reconnects_before_content() {
+        let app = Router::new().route(
+            "/api/v2/chat/completions-detection",
+            post(|| async {
+                (
+                    [("content-type", "text/event-stream")],
+                    concat!(
+                        "data: {\"id\":\"1\",\"object\":\"chat.completion.chunk\",\"created\":1,",
+                        "\"model\":\"m\",\"choices\":[{\"index\":0,\"delta\":{\"content\":\"hi\"}}]}\n\n",
+                        "data: [DONE]\n\n",
+                    ),
+                )
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let orchestrator = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            // The first connection is dropped without a response
+            drop(listener.accept().await.unwrap());
+            axum::serve(listener, app).await.unwrap()
+        });
+        let mut gateway_config = test_config(orchestrator);
+        gateway_config.stream_reconnect_attempts = 1;
+        gateway_config.stream_reconnect_backoff_ms = 10;
+        let gateway = spawn_gateway(&gateway_config).await;
+
+        let body = reqwest::Client::new()
+            .post(format!("http://{}/test/v1/chat/completions", gateway))
+            .json(&json!({"model": "m", "messages": user_messages(), "stream": true}))
+            .send()
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+
+        let events = sse_data(&body);
+        assert_eq!(events.len(), 1, "{}", body);
+        let chunk: StreamingResponse = serde_json::from_str(events[0]).unwrap();
+        assert_eq!(chunk.choices[0].delta.content.as_deref(), Some("hi"));
+    }
+
+    #[tokio::test]
+    async fn test_streaming_reconnects_stop_when_retry_budget_depleted() {
+        // Every connection is dropped without a response
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let orchestrator = listener.local_addr().unwrap();
+        let connections = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let accepted = connections.clone();
+        tokio::spawn(async move {
+            loop {
+                let connection = listener.accept().await.unwrap();
+                accepted.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                drop(connection);
+            }
+        });
+        let mut gateway_config = test_config(orchestrator);
+        gateway_config.stream_reconnect_attempts = 3;
+        gateway_config.stream_reconnect_backoff_ms = 1;
+        gateway_config.retry_budget = Some(config::RetryBudgetConfig {
+            retries_per_second: 0.0,
+            burst: 2,
+        });
+        let gateway = spawn_gateway(&gateway_config).await;
+
+        let mut attempts = Vec::new();
+        for _ in 0..3 {
+            let before = connections.load(std::sync::atomic::Ordering::SeqCst);
+            let response = reqwest::Client::new()
+                .post(format!("http://{}/test/v1/chat/completions", gateway))
+                .json(&json!({"model": "m", "messages": user_messages(), "stream": true}))
+                .send()
+                .await
+                .unwrap();
+            assert!(!response.status().is_success());
+            attempts.push(connections.load(std::sync::atomic::Ordering::SeqCst) - before);
+        }
+
+        // The first request spends the whole budget; later ones fail on their first attempt
+        assert_eq!(attempts, [3, 1, 1]);
+    }
+
+    #[tokio::test]
+    async fn test_streaming_with_crlf_line_endings() {
+        let (orchestrator, _) = spawn_streaming_orchestrator(concat!(
+            "data: {\"id\":\"1\",\"object\":\"chat.completion.chunk\",\"created\":1,\"model\":\"m\",",
+            "\"choices\":[{\"index\":0,\"delta\":{\"content\":\"hi\"}}]}\r\n\r\n",
+            "data: [DONE]\r\n\r\n",
+        ))
+        .await;
+        let gateway = spawn_gateway(&test_config(orchestrator)).await;
+
+        let body = reqwest::Client::new()
+            .post(format!("http://{}/test/v1/chat/completions", gateway))
+            .json(&json!({"model": "m", "messages": user_messages(), "stream": true}))
+            .send()
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+
+        let events = sse_data(&body);
+        assert_eq!(events.len(), 1, "{}", body);
+        let chunk: StreamingResponse = serde_json::from_str(events[0]).unwrap();
+        assert_eq!(chunk.choices[0].delta.content.as_deref(), Some("hi"));
+    }
+
+    #[tokio::test]
+    async fn test_buffered_streaming_sends_validated_content_as_chunks() {
+        let (orchestrator, captured) = spawn_orchestrator(completion_response(Value::Null)).await;
+        let mut gateway_config = test_config(orchestrator);
+        gateway_config.routes[0].buffered_streaming = true;
+        let gateway = spawn_gateway(&gateway_config).await;
+
+        let body = reqwest::Client::new()
+            .post(format!("http://{}/test/v1/chat/completions", gateway))
+            .json(&json!({"model": "m", "messages": user_messages(), "stream": true}))
+            .send()
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+
+        assert_eq!(captured.lock().unwrap()[0].1["stream"], false);
+
+        let chunks: Vec<StreamingResponse> = sse_data(&body)
+            .into_iter()
+            .map(|data| serde_json::from_str(data).unwrap())
+            .collect();
+        let content: String = chunks
+            .iter()
+            .filter_map(|chunk| chunk.choices[0].delta.content.clone())
+            .collect();
+        assert_eq!(content, "generated text");
+        assert_eq!(
+            chunks[0].choices[0].delta.role.as_deref(),
+            Some("assistant")
+        );
+        assert_eq!(
+            chunks.last().unwrap().choices[0].finish_reason.as_deref(),
+            Some("stop")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_buffered_streaming_sends_only_fallback_on_detection() {
+        let (orchestrator, _) = spawn_orchestrator(completion_response(output_detections())).await;
+        let mut gateway_config = test_config(orchestrator);
+        gateway_config.routes[0].buffered_streaming = true;
+        let gateway = spawn_gateway(&gateway_config).await;
+
+        let body = reqwest::Client::new()
+            .post(format!("http://{}/test/v1/chat/completions", gateway))
+            .json(&json!({"model": "m", "messages": user_messages(), "stream": true}))
+            .send()
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+
+        let chunks: Vec<StreamingResponse> = sse_data(&body)
+            .into_iter()
+            .map(|data| serde_json::from_str(data).unwrap())
+            .collect();
+        let content: String = chunks
+            .iter()
+            .filter_map(|chunk| chunk.choices[0].delta.content.clone())
+            .collect();
+        assert_eq!(content, "fallback");
+        assert!(chunks.last().unwrap().detections.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_stream_and_buffering_matrix() {
+        const CHUNK: &str = concat!(
+            "data: {\"id\":\"1\",\"object\":\"chat.completion.chunk\",\"created\":1,\"model\":\"m\",",
+            "\"choices\":[{\"index\":0,\"delta\":{\"content\":\"generated text\"},",
+            "\"finish_reason\":\"stop\"}]}\n\ndata: [DONE]\n\n"
+        );
+        let captured: Captured = Arc::new(Mutex::new(Vec::new()));
+        let recorder = captured.clone();
+        let app = Router::new().route(
+            "/api/v2/chat/completions-detection",
+            post(
+                move |headers: HeaderMap, Json(request): Json<Value>| async move {
+                    let stream = request["stream"] == true;
+                    recorder.lock().unwrap().push((headers, request));
+                    if stream {
+                        ([("content-type", "text/event-stream")], CHUNK).into_response()
+                    } else {
+                        Json(completion_response(Value::Null)).into_response()
+                    }
+                },
+            ),
+        );
+        let orchestrator = spawn_server(app).await;
+
+        // (client stream, buffered_streaming, buffer_upstream_stream) ->
+        // (upstream stream, client receives SSE)
+        let cases = [
+            ((false, false, false), (false, false)),
+            ((false, true, false), (false, false)),
+            ((false, false, true), (false, false)),
+            ((false, true, true), (false, false)),
+            ((true, false, false), (true, true)),
+            ((true, false, true), (true, true)),
+            ((true, true, false), (false, true)),
+            ((true, true, true), (true, true)),
+        ];
+        for ((stream, buffered, buffer_upstream), (upstream_stream, sse)) in cases {
+            let mut gateway_config = test_config(orchestrator);
+            gateway_config.routes[0].buffered_streaming = buffered;
+            gateway_config.routes[0].buffer_upstream_stream = buffer_upstream;
+            let gateway = spawn_gateway(&gateway_config).await;
+
+            let response = reqwest::Client::new()
+                .post(format!("http://{}/test/v1/chat/completions", gateway))
+                .json(&json!({"model": "m", "messages": user_messages(), "stream": stream}))
+                .send()
+                .await
+                .unwrap();
+            let case = format!("{:?}", (stream, buffered, buffer_upstream));
+            assert_eq!(response.status(), StatusCode::OK, "{}", case);
+            let content_type = response.headers()[header::CONTENT_TYPE].clone();
+            let body = response.text().await.unwrap();
+            let content: String = if sse {
+                assert_eq!(content_type, "text/event-stream", "{}", case);
+                sse_data(&body)
+                    .into_iter()
+                    .filter_map(|data| serde_json::from_str::<StreamingResponse>(data).ok())
+                    .filter_map(|chunk| chunk.choices[0].delta.content.clone())
+                    .collect()
+            } else {
+                assert_eq!(content_type, "application/json", "{}", case);
+                let response: OrchestratorResponse = serde_json::from_str(&body).unwrap();
+                response.choices[0].message.content.clone()
+            };
+            assert_eq!(content, "generated text", "{}", case);
+            let upstream = captured.lock().unwrap().pop().unwrap().1;
+            assert_eq!(upstream["stream"], upstream_stream, "{}", case);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fallback_only_on_detection_results() {
+        let cases = [
+            (json!({"input": [], "output": []}), "generated text"),
+            (
+                json!({"input": [{"message_index": 0, "results": null}], "output": null}),
+                "generated text",
+            ),
+            (output_detections(), "fallback"),
+        ];
+        for (detections, expected) in cases {
+            let (orchestrator, _) = spawn_orchestrator(completion_response(detections)).await;
+            let gateway = spawn_gateway(&test_config(orchestrator)).await;
+
+            let response: OrchestratorResponse = reqwest::Client::new()
+                .post(format!("http://{}/test/v1/chat/completions", gateway))
+                .json(&json!({"model": "m", "messages": user_messages()}))
+                .send()
+                .await
+                .unwrap()
+                .json()
+                .await
+                .unwrap();
+            assert_eq!(response.choices[0].message.content, expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_detector_thresholds() {
+        let scored = |detector_id: &str, score: f64| {
+            json!({
+                "start": 0, "end": 9, "text": "generated", "detection_type": "pii",
+                "detection": "EmailAddress", "detector_id": detector_id, "score": score
+            })
+        };
+        let detections = |results: Vec<Value>| json!({"input": null, "output": [{"choice_index": 0, "results": results}]});
+        let cases = [
+            (detections(vec![scored("regex", 0.5)]), "generated text"),
+            (detections(vec![scored("regex", 0.8)]), "fallback"),
+            (
+                detections(vec![scored("regex", 0.5), scored("hap", 0.4)]),
+                "generated text",
+            ),
+            (
+                detections(vec![scored("regex", 0.5), scored("hap", 0.6)]),
+                "fallback",
+            ),
+            // Detectors without a threshold trigger on any score
+            (detections(vec![scored("other", 0.01)]), "fallback"),
+        ];
+
+        for (detections, expected) in cases {
+            for stream in [false, true] {
+                let orchestrator = if stream {
+                    let chunk = json!({
+                        "id": "1", "object": "chat.completion.chunk", "created": 1, "model": "m",
+                        "choices": [{"index": 0, "delta": {"content": "generated text"}, "finish_reason": null}],
+                        "detections": detections,
+                    });
+                    let body = format!("data: {}\n\ndata: [DONE]\n\n", chunk);
+                    let app = Router::new().route(
+                        "/api/v2/chat/completions-detection",
+                        post(move || {
+                            let body = body.clone();
+                            async move { ([("content-type", "text/event-stream")], body) }
+                        }),
+                    );
+                    spawn_server(app).await
+                } else {
+                    spawn_orchestrator(completion_response(detections.clone()))
+                        .await
+                        .0
+                };
+                let mut gateway_config = test_config(orchestrator);
+                gateway_config.detectors =
+                    [("regex", Some(0.8)), ("hap", Some(0.5)), ("other", None)]
+                        .into_iter()
+                        .map(|(name, threshold)| DetectorConfig {
+                            name: name.to_string(),
+                            server: None,
+                            input: false,
+                            output: true,
+                            detector_params: Some(json!({})),
+                            threshold,
+                        })
+                        .collect();
+                gateway_config.routes[0].detectors =
+                    vec!["regex".to_string(), "hap".to_string(), "other".to_string()];
+                let gateway = spawn_gateway(&gateway_config).await;
+
+                let response = reqwest::Client::new()
+                    .post(format!("http://{}/test/v1/chat/completions", gateway))
+                    .json(&json!({"model": "m", "messages": user_messages(), "stream": stream}))
+                    .send()
+                    .await
+                    .unwrap();
+                let content = if stream {
+                    let body = response.text().await.unwrap();
+                    let chunk: Value = serde_json::from_str(sse_data(&body)[0]).unwrap();
+                    chunk["choices"][0]["delta"]["content"].clone()
+                } else {
+                    response.json::<Value>().await.unwrap()["choices"][0]["message"]["content"]
+                        .clone()
+                };
+                assert_eq!(content, expected, "{} stream: {}", detections, stream);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fallback_messages_by_detection_type() {
+        let result = |detection_type: &str, score: f64| {
+            json!({
+                "start": 0, "end": 9, "text": "generated", "detection_type": detection_type,
+                "detection": detection_type, "detector_id": "regex", "score": score
+            })
+        };
+        let detections = |results: Vec<Value>| json!({"input": null, "output": [{"choice_index": 0, "results": results}]});
+        let fallback_messages = HashMap::from([
+            ("pii".to_string(), "no personal data".to_string()),
+            ("jailbreak".to_string(), "nice try".to_string()),
+        ]);
+        let cases = [
+            (
+                Some("fallback"),
+                detections(vec![result("pii", 0.9)]),
+                "no personal data",
+            ),
+            // The highest scoring detection picks the message
+            (
+                Some("fallback"),
+                detections(vec![result("pii", 0.9), result("jailbreak", 0.95)]),
+                "nice try",
+            ),
+            (
+                Some("fallback"),
+                detections(vec![result("hap", 0.9)]),
+                "fallback",
+            ),
+            (None, detections(vec![result("jailbreak", 0.9)]), "nice try"),
+            (None, detections(vec![result("hap", 0.9)]), "generated text"),
+        ];
+
+        for (fallback_message, detections, expected) in cases {
+            for stream in [false, true] {
+                let orchestrator = if stream {
+                    let chunk = json!({
+                        "id": "1", "object": "chat.completion.chunk", "created": 1, "model": "m",
+                        "choices": [{"index": 0, "delta": {"content": "generated text"}, "finish_reason": null}],
+                        "detections": detections,
+                    });
+                    let body = format!("data: {}\n\ndata: [DONE]\n\n", chunk);
+                    let app = Router::new().route(
+                        "/api/v2/chat/completions-detection",
+                        post(move || {
+                            let body = body.clone();
+                            async move { ([("content-type", "text/event-stream")], body) }
+                        }),
+                    );
+                    spawn_server(app).await
+                } else {
+                    spawn_orchestrator(completion_response(detections.clone()))
+                        .await
+                        .0
+                };
+                let mut gateway_config = test_config(orchestrator);
+                gateway_config.routes[0].fallback_message = fallback_message.map(str::to_string);
+                gateway_config.routes[0].fallback_messages = fallback_messages.clone();
+                let gateway = spawn_gateway(&gateway_config).await;
+
+                let response = reqwest::Client::new()
+                    .post(format!("http://{}/test/v1/chat/completions", gateway))
+                    .json(&json!({"model": "m", "messages": user_messages(), "stream": stream}))
+                    .send()
+                    .await
+                    .unwrap();
+                let content = if stream {
+                    let body = response.text().await.unwrap();
+                    let chunk: Value = serde_json::from_str(sse_data(&body)[0]).unwrap();
+                    chunk["choices"][0]["delta"]["content"].clone()
+                } else {
+                    response.json::<Value>().await.unwrap()["choices"][0]["message"]["content"]
+                        .clone()
+                };
+                assert_eq!(content, expected, "{} stream: {}", detections, stream);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fallback_replaces_every_choice() {
+        let mut response = completion_response(output_detections());
+        let choice = response["choices"][0].clone();
+        response["choices"] = json!([0, 1, 2]
+            .into_iter()
+            .map(|index| {
+                let mut choice = choice.clone();
+                choice["index"] = json!(index);
+                choice
+            })
+            .collect::<Vec<_>>());
+        let (orchestrator, _) = spawn_orchestrator(response).await;
+        let gateway = spawn_gateway(&test_config(orchestrator)).await;
+
+        let response: OrchestratorResponse = reqwest::Client::new()
+            .post(format!("http://{}/test/v1/chat/completions", gateway))
+            .json(&json!({"model": "m", "messages": user_messages(), "n": 3}))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+
+        assert_eq!(response.choices.len(), 3);
+        for (index, choice) in response.choices.iter().enumerate() {
+            assert_eq!(choice.index, index as u32);
+            assert_eq!(choice.message.content, "fallback");
+            assert_eq!(choice.finish_reason, "stop");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_streaming_fallback_replaces_every_choice() {
+        let choices = json!([0, 1, 2]
+            .into_iter()
+            .map(|index| json!({"index": index, "delta": {"content": "a@b.com"}}))
+            .collect::<Vec<_>>());
+        let chunks =
+            stream_through_gateway(vec![detection_frame(choices, output_detections())]).await;
+
+        assert_eq!(chunks.len(), 1);
+        assert_eq!(chunks[0].choices.len(), 3);
+        for (index, choice) in chunks[0].choices.iter().enumerate() {
+            assert_eq!(choice.index, index as u32);
+            assert_eq!(choice.delta.content.as_deref(), Some("fallback"));
+            assert_eq!(choice.finish_reason.as_deref(), Some("stop"));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_empty_orchestrator_response_returns_bad_gateway() {
+        let app = Router::new().route(
+            "/api/v2/chat/completions-detection",
+            post(|| async { StatusCode::OK }),
+        );
+        let orchestrator = spawn_server(app).await;
+        let gateway = spawn_gateway(&test_config(orchestrator)).await;
+
+        let response = reqwest::Client::new()
+            .post(format!("http://{}/test/v1/chat/completions", gateway))
+            .json(&json!({"model": "m", "messages": user_messages()}))
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+        assert_eq!(
+            response.json::<Value>().await.unwrap(),
+            json!({"error": {
+                "message": "Orchestrator returned empty response",
+                "type": "upstream_error",
+                "code": null,
+            }})
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unreachable_orchestrator_returns_bad_gateway() {
+        // Bind and immediately drop a listener to get a port nothing is listening on
+        let closed = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let gateway = spawn_gateway(&test_config(closed)).await;
+
+        let response = reqwest::Client::new()
+            .post(format!("http://{}/test/v1/chat/completions", gateway))
+            .json(&json!({"model": "m", "messages": user_messages()}))
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+    }
+
+    #[tokio::test]
+    async fn test_buffered_upstream_stream_cancels_on_detection() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let upstream_cancelled = cancelled.clone();
+        let app = Router::new().route(
+            "/api/v2/chat/completions-detection",
+            post(move || async move {
+                let (mut tx, rx) =
+                    futures::channel::mpsc::channel::<Result<String, std::convert::Infallible>>(8);
+                tokio::spawn(async move {
+                    use futures::SinkExt;
+                    let chunk = |content: &str, detections: Value| {
+                        let chunk = json!({
+                            "id": "1", "object": "chat.completion.chunk", "created": 1,
+                            "model": "m",
+                            "choices": [{"index": 0, "delta": {"content": content}}],
+                            "detections": detections,
+                        });
+                        Ok(format!("data: {}\n\n", chunk))
+                    };
+                    tx.send(chunk("my email is ", Value::Null)).await.unwrap();
+                    tx.send(chunk("a@b.com", output_detections()))
+                        .await
+                        .unwrap();
+                    // Keep generating until the gateway hangs up
+                    for _ in 0..500 {
+                        if tx.is_closed() {
+                            upstream_cancelled.store(true, Ordering::SeqCst);
+                            return;
+                        }
+                        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+                    }
+                    let _ = tx.send(chunk(" and more", Value::Null)).await;
+                });
+                (
+                    [("content-type", "text/event-stream")],
+                    axum::body::Body::from_stream(rx),
+                )
+            }),
+        );
+        let orchestrator = spawn_server(app).await;
+        let mut gateway_config = test_config(orchestrator);
+        gateway_config.routes[0].buffered_streaming = true;
+        gateway_config.routes[0].buffer_upstream_stream = true;
+        let gateway = spawn_gateway(&gateway_config).await;
+
+        let body = reqwest::Client::new()
+            .post(format!("http://{}/test/v1/chat/completions", gateway))
+            .json(&json!({"model": "m", "messages": user_messages(), "stream": true}))
+            .send()
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+
+        let events = sse_data(&body);
+        assert_eq!(events.len(), 1, "{}", body);
+        let chunk: StreamingResponse = serde_json::from_str(events[0]).unwrap();
+        assert_eq!(chunk.choices[0].delta.content.as_deref(), Some("fallback"));
+
+        for _ in 0..200 {
+            if cancelled.load(Ordering::SeqCst) {
+                return;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        panic!("upstream request was not cancelled");
+    }
+
+    #[tokio::test]
+    async fn test_metrics_exemplars_carry_the_trace_id() {
+        let (orchestrator, _) = spawn_orchestrator(completion_response(Value::Null)).await;
+        let mut gateway_config = test_config(orchestrator);
+        gateway_config.routes[0].name = "metrics_exemplars".to_string();
+        let gateway = spawn_gateway(&gateway_config).await;
+        let client = reqwest::Client::new();
+
+        client
+            .post(format!(
+                "http://{}/metrics_exemplars/v1/chat/completions",
+                gateway
+            ))
+            .header(
+                "traceparent",
+                "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            )
+            .json(&json!({"model": "m", "messages": user_messages()}))
+            .send()
+            .await
+            .unwrap();
+
+        let response = client
+            .get(format!("http://{}/metrics", gateway))
+            .header("accept", "application/openmetrics-text; version=1.0.0")
+            .send()
+            .await
+            .unwrap();
+        assert!(response.headers()["content-type"]
+            .to_str()
+            .unwrap()
+            .starts_with("application/openmetrics-text"));
+        let body = response.text().await.unwrap();
+        let exemplars: Vec<&str> = body
+            .lines()
+            .filter(|l| {
+                l.starts_with("gateway_request_duration_seconds_bucket{route=\"metrics_exemplars\"")
+            })
+            .filter(|l| l.contains(" # {trace_id=\"4bf92f3577b34da6a3ce929d0e0e4736\"} "))
+            .collect();
+        assert_eq!(exemplars.len(), 1, "{}", body);
+        assert!(body.ends_with("# EOF\n"));
+    }
+
+    #[tokio::test]
+    async fn test_metrics_count_requests_per_route() {
+        let (orchestrator, _) = spawn_orchestrator(completion_response(Value::Null)).await;
+        let mut gateway_config = test_config(orchestrator);
+        gateway_config.routes[0].name = "metrics_count".to_string();
+        let gateway = spawn_gateway(&gateway_config).await;
+        let client = reqwest::Client::new();
+        let url = format!("http://{}/metrics_count/v1/chat/completions", gateway);
+
+        for _ in 0..2 {
+            let response = client
+                .post(&url)
+                .json(&json!({"model": "m", "messages": user_messages()}))
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+        // Nothing listens here, so the streaming request fails at the orchestrator
+        let mut gateway_config = test_config("127.0.0.1:1".parse().unwrap());
+        gateway_config.routes[0].name = "metrics_count".to_string();
+        let failing_gateway = spawn_gateway(&gateway_config).await;
+        client
+            .post(format!(
+                "http://{}/metrics_count/v1/chat/completions",
+                failing_gateway
+            ))
+            .json(&json!({"model": "m", "messages": user_messages(), "stream": true}))
+            .send()
+            .await
+            .unwrap();
+
+        let response = reqwest::get(format!("http://{}/metrics", gateway))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.headers()["content-type"]
+            .to_str()
+            .unwrap()
+            .starts_with("text/plain"));
+        let body = response.text().await.unwrap();
+        for line in [
+            "gateway_requests_total{route=\"metrics_count\",streaming=\"false\"} 2",
+            "gateway_requests_total{route=\"metrics_count\",streaming=\"true\"} 1",
+            "gateway_orchestrator_errors_total{route=\"metrics_count\"} 1",
+            "gateway_request_duration_seconds_count{route=\"metrics_count\"} 3",
+        ] {
+            assert!(body.lines().any(|l| l == line), "{}\n{}", line, body);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_server_timing_header() {
+        let (orchestrator, _) = spawn_orchestrator(completion_response(Value::Null)).await;
+        let gateway = spawn_gateway(&test_config(orchestrator)).await;
+
+        let response = reqwest::Client::new()
+            .post(format!("http://{}/test/v1/chat/completions", gateway))
+            .json(&json!({"model": "m", "messages": user_messages()}))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let timing: HashMap<&str, f64> = response.headers()["server-timing"]
+            .to_str()
+            .unwrap()
+            .split(", ")
+            .map(|metric| {
+                let (name, duration) = metric.split_once(";dur=").unwrap();
+                (name, duration.parse().unwrap())
+            })
+            .collect();
+        assert_eq!(timing.len(), 2, "{:?}", timing);
+        assert!(timing["orchestrator"] <= timing["total"], "{:?}", timing);
+    }
+
+    #[tokio::test]
+    async fn test_graceful_shutdown_drains_in_flight_requests() {
+        // Shutting down before the request is accepted would refuse it rather than drain it
+        let started = Arc::new(tokio::sync::Notify::new());
+        let handler_started = started.clone();
+        let app = Router::new().route(
+            "/slow",
+            get(move || async move {
+                handler_started.notify_one();
+                tokio::time::sleep(Duration::from_millis(300)).await;
+                "done"
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel::<()>();
+        let server = tokio::spawn(serve(
+            listener,
+            app,
+            None,
+            async {
+                let _ = shutdown_rx.await;
+            },
+            Duration::from_secs(10),
+        ));
+
+        let request = tokio::spawn(reqwest::get(format!("http://{}/slow", addr)));
+        started.notified().await;
+        shutdown_tx.send(()).unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        // New connections are refused while the request in flight completes
+        assert!(tokio::net::TcpStream::connect(addr).await.is_err());
+        let response = request.await.unwrap().unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.text().await.unwrap(), "done");
+        tokio::time::timeout(Duration::from_secs(1), server)
+            .await
+            .unwrap()
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_graceful_shutdown_gives_up_after_grace_period() {
+        let app = Router::new().route(
+            "/hang",
+            get(|| async {
+                tokio::time::sleep(Duration::from_secs(60)).await;
+                "done"
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel::<()>();
+        let server = tokio::spawn(serve(
+            listener,
+            app,
+            None,
+            async {
+                let _ = shutdown_rx.await;
+            },
+            Duration::from_millis(100),
+        ));
+
+        tokio::spawn(reqwest::get(format!("http://{}/hang", addr)));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        shutdown_tx.send(()).unwrap();
+
+        tokio::time::timeout(Duration::from_secs(2), server)
+            .await
+            .unwrap()
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_disabled_route_is_not_registered() {
+        let (orchestrator, _) = spawn_orchestrator(completion_response(Value::Null)).await;
+        let mut gateway_config = test_config(orchestrator);
+        gateway_config.routes[0].expose_verdict = true;
+        gateway_config.routes.push(RouteConfig {
+            name: "disabled".to_string(),
+            enabled: false,
+            expose_verdict: true,
+            ..Default::default()
+        });
+        let gateway = spawn_gateway(&gateway_config).await;
+        let client = reqwest::Client::new();
+
+        for path in ["v1/chat/completions", "v1/verdict"] {
+            let response = client
+                .post(format!("http://{}/disabled/{}", gateway, path))
+                .json(&json!({"model": "m", "messages": user_messages()}))
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::NOT_FOUND, "{}", path);
+        }
+        let response = client
+            .post(format!("http://{}/test/v1/chat/completions", gateway))
+            .json(&json!({"model": "m", "messages": user_messages()}))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_health() {
+        let mut gateway_config = test_config("127.0.0.1:1".parse().unwrap());
+        gateway_config.watchdog = Some(config::WatchdogConfig {
+            interval_ms: 10,
+            stall_threshold_ms: 1000,
+        });
+        let gateway = spawn_gateway(&gateway_config).await;
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+
+        let response = reqwest::get(format!("http://{}/health", gateway))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.json::<Value>().await.unwrap(),
+            json!({"status": "ok"})
+        );
+    }
+
+    #[tokio::test]
+    async fn test_health_does_not_depend_on_orchestrator() {
+        // Nothing listens on the orchestrator port, yet the gateway itself is alive
+        let gateway = spawn_gateway(&test_config("127.0.0.1:1".parse().unwrap())).await;
+
+        let response = reqwest::get(format!("http://{}/health", gateway))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.json::<Value>().await.unwrap(),
+            json!({"status": "ok"})
+        );
+
+        let response = reqwest::get(format!("http://{}/ready", gateway))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(
+            response.json::<Value>().await.unwrap(),
+            json!({"status": "orchestrator unavailable"})
+        );
+    }
+
+    #[tokio::test]
+    async fn test_non_post_returns_json_method_not_allowed() {
+        let gateway = spawn_gateway(&test_config("127.0.0.1:1".parse().unwrap())).await;
+
+        let response = reqwest::get(format!("http://{}/test/v1/chat/completions", gateway))
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(response.headers()["allow"], "POST");
+        let body: Value = response.json().await.unwrap();
+        assert_eq!(body["error"]["type"], "method_not_allowed");
+        assert_eq!(body["error"]["allowed_methods"], json!(["POST"]));
+    }
+
+    #[tokio::test]
+    async fn test_detection_results_are_capped() {
+        let results: Vec<Value> = (0..50)
+            .map(|i| {
+                json!({
+                    "start": i, "end": i + 1, "text": "x", "detection_type": "pii",
+                    "detection": "EmailAddress", "detector_id": "regex",
+                    "score": i as f64 / 100.0,
+                })
+            })
+            .collect();
+        let detections = json!({
+            "input": null,
+            "output": [{"choice_index": 0, "results": results}],
+        });
+        let (orchestrator, _) = spawn_orchestrator(completion_response(detections)).await;
+        let mut gateway_config = test_config(orchestrator);
+        gateway_config.max_detection_results = Some(3);
+        let gateway = spawn_gateway(&gateway_config).await;
+
+        let body: Value = reqwest::Client::new()
+            .post(format!("http://{}/test/v1/chat/completions", gateway))
+            .json(&json!({"model": "m", "messages": user_messages()}))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+
+        let results = body["detections"]["output"][0]["results"]
+            .as_array()
+            .unwrap();
+        let scores: Vec<f64> = results
+            .iter()
+            .map(|r| r["score"].as_f64().unwrap())
+            .collect();
+        assert_eq!(scores, vec![0.47, 0.48, 0.49]);
+    }
+
+    #[tokio::test]
+    async fn test_initial_keep_alive_is_sent_first() {
+        let (orchestrator, _) = spawn_streaming_orchestrator("data: [DONE]\n\n").await;
+        let mut gateway_config = test_config(orchestrator);
+        gateway_config.sse_initial_keep_alive = true;
+        let gateway = spawn_gateway(&gateway_config).await;
+
+        let body = reqwest::Client::new()
+            .post(format!("http://{}/test/v1/chat/completions", gateway))
+            .json(&json!({"model": "m", "messages": user_messages(), "stream": true}))
+            .send()
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+
+        assert!(body.starts_with(": keep-alive\n"), "{:?}", body);
+    }
+
+    #[tokio::test]
+    async fn test_ready_probes_configured_health_path() {
+        let app = Router::new().route("/custom/healthz", get(|| async { "ok" }));
+        let orchestrator = spawn_server(app).await;
+
+        let mut gateway_config = test_config(orchestrator);
+        gateway_config.orchestrator.health_path = "/custom/healthz".to_string();
+        let gateway = spawn_gateway(&gateway_config).await;
+        let response = reqwest::get(format!("http://{}/ready", gateway))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.json::<Value>().await.unwrap(),
+            json!({"status": "ready"})
+        );
+
+        // The default path isn't served by this orchestrator
+        let gateway = spawn_gateway(&test_config(orchestrator)).await;
+        let response = reqwest::get(format!("http://{}/ready", gateway))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[tokio::test]
+    async fn test_streaming_fallback_clears_stop_reason() {
+        let (orchestrator, _) = spawn_streaming_orchestrator(concat!(
+            "data: {\"id\":\"1\",\"object\":\"chat.completion.chunk\",\"created\":1,\"model\":\"m\",",
+            "\"choices\":[{\"index\":0,\"delta\":{\"content\":\"a@b.com\"},",
+            "\"finish_reason\":\"length\",\"stop_reason\":\"</s>\"}],",
+            "\"detections\":{\"input\":null,\"output\":[{\"choice_index\":0,\"results\":[",
+            "{\"start\":0,\"end\":7,\"text\":\"a@b.com\",\"detection_type\":\"pii\",",
+            "\"detection\":\"EmailAddress\",\"detector_id\":\"regex\",\"score\":1.0}]}]}}\n\n",
+            "data: [DONE]\n\n",
+        ))
+        .await;
+        let gateway = spawn_gateway(&test_config(orchestrator)).await;
+
+        let body = reqwest::Client::new()
+            .post(format!("http://{}/test/v1/chat/completions", gateway))
+            .json(&json!({"model": "m", "messages": user_messages(), "stream": true}))
+            .send()
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+
+        let events = sse_data(&body);
+        let chunk: Value = serde_json::from_str(events[0]).unwrap();
+        let choice = &chunk["choices"][0];
+        assert_eq!(choice["delta"]["content"], "fallback");
+        assert_eq!(choice["finish_reason"], "stop");
+        assert_eq!(choice["stop_reason"], Value::Null);
+    }
+
+    #[tokio::test]
+    async fn test_verdict_endpoint() {
+        let app = Router::new().route(
+            "/api/v2/text/detection/chat",
+            post(|Json(body): Json<Value>| async move {
+                let content = body["messages"][0]["content"].as_str().unwrap_or_default();
+                let detections = if content.contains('@') {
+                    json!([{
+                        "detection_type": "pii", "detection": "EmailAddress",
+                        "detector_id": "regex", "score": 0.8
+                    }])
+                } else {
+                    json!([])
+                };
+                assert!(body["detectors"].get("regex").is_some());
+                Json(json!({ "detections": detections }))
+            }),
+        );
+        let orchestrator = spawn_server(app).await;
+        let mut gateway_config = GatewayConfig {
+            detectors: vec![regex_detector(true, false)],
+            ..test_config(orchestrator)
+        };
+        gateway_config.routes[0].detectors = vec!["regex".to_string()];
+        gateway_config.routes[0].expose_verdict = true;
+        gateway_config.routes[0].verdict_threshold = Some(0.5);
+        let gateway = spawn_gateway(&gateway_config).await;
+
+        let verdict = |content: &'static str| {
+            reqwest::Client::new()
+                .post(format!("http://{}/test/v1/verdict", gateway))
+                .json(&json!({"messages": [{"role": "user", "content": content}]}))
+                .send()
+        };
+
+        let blocked: Value = verdict("mail me at a@b.com")
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(blocked["blocked"], true);
+        assert_eq!(blocked["max_score"], 0.8);
+        assert_eq!(blocked["reasons"][0]["detection"], "EmailAddress");
+
+        let clean: Value = verdict("hello").await.unwrap().json().await.unwrap();
+        assert_eq!(
+            clean,
+            json!({"blocked": false, "reasons": [], "max_score": 0.0})
+        );
+
+        // Results below their detector's threshold are left out, as they are on chat
+        gateway_config.detectors[0].threshold = Some(0.9);
+        let gateway = spawn_gateway(&gateway_config).await;
+        let below_threshold: Value = reqwest::Client::new()
+            .post(format!("http://{}/test/v1/verdict", gateway))
+            .json(&json!({"messages": [{"role": "user", "content": "mail me at a@b.com"}]}))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(
+            below_threshold,
+            json!({"blocked": false, "reasons": [], "max_score": 0.0})
+        );
+    }
+
+    /// Serves `response` from the orchestrator's text completions endpoint, recording the
+    /// payloads it receives.
+    async fn spawn_completions_orchestrator(response: Value) -> (SocketAddr, Captured) {
+        let captured: Captured = Arc::new(Mutex::new(Vec::new()));
+        let recorder = captured.clone();
+        let app = Router::new().route(
+            "/api/v2/text/completions-detection",
+            post(
+                move |headers: HeaderMap, Json(body): Json<Value>| async move {
+                    recorder.lock().unwrap().push((headers, body));
+                    Json(response)
+                },
+            ),
+        );
+        (spawn_server(app).await, captured)
+    }
+
+    fn text_completion_response(detections: Value) -> Value {
+        json!({
+            "id": "cmpl-test",
+            "choices": [
+                {"finish_reason": "length", "index": 0, "logprobs": null, "text": "generated text"},
+                {"finish_reason": "length", "index": 1, "logprobs": null, "text": "more text"},
+            ],
+            "created": 1741182909,
+            "model": "test-model",
+            "system_fingerprint": null,
+            "object": "text_completion",
+            "usage": {"completion_tokens": 2, "prompt_tokens": 1, "total_tokens": 3},
+            "detections": detections,
+            "warnings": null
+        })
+    }
+
+    #[tokio::test]
+    async fn test_text_completions() {
+        let (orchestrator, captured) =
+            spawn_completions_orchestrator(text_completion_response(Value::Null)).await;
+        let mut gateway_config = GatewayConfig {
+            detectors: vec![regex_detector(true, true)],
+            ..test_config(orchestrator)
+        };
+        gateway_config.routes[0].detectors = vec!["regex".to_string()];
+        let gateway = spawn_gateway(&gateway_config).await;
+
+        let response = reqwest::Client::new()
+            .post(format!("http://{}/test/v1/completions", gateway))
+            .header("x-request-id", "req-1")
+            .json(&json!({"model": "m", "prompt": "Say something", "max_tokens": 5, "n": 2}))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["x-request-id"], "req-1");
+        let body: Value = response.json().await.unwrap();
+        assert_eq!(body, text_completion_response(Value::Null));
+
+        let (_, sent) = captured.lock().unwrap()[0].clone();
+        assert_eq!(sent["prompt"], "Say something");
+        assert_eq!(sent["n"], 2);
+        assert_eq!(
+            sent["detectors"],
+            json!({"input": {"regex": {"regex": ["email"]}}, "output": {"regex": {"regex": ["email"]}}})
+        );
+    }
+
+    #[tokio::test]
+    async fn test_text_completions_fallback() {
+        let input_detections = json!({
+            "input": [{"message_index": 0, "results": [{
+                "start": 0, "end": 3, "text": "a@b", "detection_type": "pii",
+                "detection": "EmailAddress", "detector_id": "regex-language", "score": 1.0
+            }]}],
+            "output": null
+        });
+        let mut no_generation = text_completion_response(input_detections.clone());
+        no_generation["choices"] = json!([]);
+
+        for (orchestrator_response, choice_count) in [
+            (text_completion_response(output_detections()), 2),
+            (no_generation, 1),
+        ] {
+            let (orchestrator, _) = spawn_completions_orchestrator(orchestrator_response).await;
+            let gateway = spawn_gateway(&test_config(orchestrator)).await;
+
+            let body: Value = reqwest::Client::new()
+                .post(format!("http://{}/test/v1/completions", gateway))
+                .json(&json!({"model": "m", "prompt": ["Say something"]}))
+                .send()
+                .await
+                .unwrap()
+                .json()
+                .await
+                .unwrap();
+
+            let choices = body["choices"].as_array().unwrap();
+            assert_eq!(choices.len(), choice_count, "{}", body);
+            for (index, choice) in choices.iter().enumerate() {
+                assert_eq!(
+                    choice,
+                    &json!({"finish_reason": "stop", "index": index, "logprobs": null, "text": "fallback"})
+                );
+            }
+            assert!(body["detections"].is_object(), "{}", body);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_text_completions_are_validated() {
+        let (orchestrator, captured) =
+            spawn_completions_orchestrator(text_completion_response(Value::Null)).await;
+        let gateway = spawn_gateway(&test_config(orchestrator)).await;
+
+        for (payload, message) in [
+            (json!({"model": "m"}), "'prompt' is required"),
+            (
+                json!({"model": "m", "prompt": ""}),
+                "'prompt' must not be empty",
+            ),
+            (
+                json!({"model": "m", "prompt": [1, 2]}),
+                "'prompt' must be a string or an array of strings",
+            ),
+            (
+                json!({"model": "m", "prompt": "hi", "stream": true}),
+                "streaming is not supported on /v1/completions",
+            ),
+        ] {
+            let response = reqwest::Client::new()
+                .post(format!("http://{}/test/v1/completions", gateway))
+                .json(&payload)
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+            let body: Value = response.json().await.unwrap();
+            assert_eq!(body["error"]["message"], message);
+        }
+        assert!(captured.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_text_completions_are_prepared_like_chat() {
+        let (orchestrator, captured) =
+            spawn_completions_orchestrator(text_completion_response(Value::Null)).await;
+        let mut gateway_config = GatewayConfig {
+            detectors: vec![
+                regex_detector(true, false),
+                DetectorConfig {
+                    name: "hap".to_string(),
+                    server: Some("hap".to_string()),
+                    input: true,
+                    output: false,
+                    detector_params: Some(json!({})),
+                    threshold: None,
+                },
+            ],
+            inject_gateway_metadata: true,
+            ..test_config(orchestrator)
+        };
+        gateway_config.routes[0].detectors = vec!["regex".to_string(), "hap".to_string()];
+        gateway_config.routes[0].allow_detector_selection = true;
+        gateway_config.routes[0].content_pointer = Some("/input/text".to_string());
+        let gateway = spawn_gateway(&gateway_config).await;
+
+        let response = reqwest::Client::new()
+            .post(format!("http://{}/test/v1/completions", gateway))
+            .header("x-request-id", "req-1")
+            .header("X-Detectors", "hap")
+            .json(&json!({"model": "m", "input": {"text": "Say something"}}))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let (_, sent) = captured.lock().unwrap()[0].clone();
+        assert_eq!(sent["prompt"], "Say something");
+        assert_eq!(sent["detectors"]["input"], json!({"hap": {}}));
+        assert_eq!(
+            sent[GATEWAY_METADATA_KEY],
+            json!({
+                "route": "test",
+                "gateway_version": env!("CARGO_PKG_VERSION"),
+                "request_id": "req-1",
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_rejected_text_completions_are_logged() {
+        let mut gateway_config = test_config("127.0.0.1:1".parse().unwrap());
+        gateway_config.routes[0].name = "rejected-text".to_string();
+        let gateway = spawn_gateway(&gateway_config).await;
+
+        let response = reqwest::Client::new()
+            .post(format!("http://{}/rejected-text/v1/completions", gateway))
+            .json(&json!({"model": "m"}))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let rendered = metrics::render(metrics::Format::Prometheus);
+        assert!(
+            rendered
+                .lines()
+                .any(|l| l
+                    == "gateway_requests_total{route=\"rejected-text\",streaming=\"false\"} 1"),
+            "{}",
+            rendered
+        );
+    }
+
+    #[tokio::test]
+    async fn test_models_are_proxied() {
+        let models = json!({
+            "object": "list",
+            "data": [{"id": "granite", "object": "model", "created": 1, "owned_by": "ibm"}]
+        });
+        let listed = models.clone();
+        let app = Router::new()
+            .route(
+                "/v1/models",
+                get(move |headers: HeaderMap| async move {
+                    assert_eq!(headers["authorization"], "Bearer token");
+                    Json(listed)
+                }),
+            )
+            .route(
+                "/models",
+                get(|| async { (StatusCode::NOT_FOUND, "no models here") }),
+            );
+        let orchestrator = spawn_server(app).await;
+        let mut gateway_config = test_config(orchestrator);
+        let gateway = spawn_gateway(&gateway_config).await;
+
+        let response = reqwest::Client::new()
+            .get(format!("http://{}/test/v1/models", gateway))
+            .header("authorization", "Bearer token")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.json::<Value>().await.unwrap(), models);
+
+        // Errors are passed on as the orchestrator sent them
+        gateway_config.orchestrator.models_path = "/models".to_string();
+        let gateway = spawn_gateway(&gateway_config).await;
+        let response = reqwest::get(format!("http://{}/test/v1/models", gateway))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(response.text().await.unwrap(), "no models here");
+    }
+
+    #[tokio::test]
+    async fn test_models_forward_only_the_resolved_tenant() {
+        let captured: Captured = Arc::new(Mutex::new(Vec::new()));
+        let recorder = captured.clone();
+        let app = Router::new().route(
+            "/v1/models",
+            get(move |headers: HeaderMap| async move {
+                recorder.lock().unwrap().push((headers, Value::Null));
+                Json(json!({"object": "list", "data": []}))
+            }),
+        );
+        let mut gateway_config = test_config(spawn_server(app).await);
+        gateway_config.tenant_claim = Some(config::TenantClaimConfig {
+            claim: "tenant".to_string(),
+            header: "x-tenant-id".to_string(),
+            hs256_secret: None,
+        });
+        let gateway = spawn_gateway(&gateway_config).await;
+        let token = jwt::tests::token(json!({"tenant": "acme"}), "secret");
+
+        for authorization in [format!("Bearer {}", token), "Bearer opaque".to_string()] {
+            let response = reqwest::Client::new()
+                .get(format!("http://{}/test/v1/models", gateway))
+                .header("authorization", authorization)
+                .header("x-tenant-id", "spoofed")
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+
+        let captured = captured.lock().unwrap();
+        assert_eq!(captured[0].0["x-tenant-id"], "acme");
+        assert!(captured[1].0.get("x-tenant-id").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_list_routes() {
+        let mut gateway_config = GatewayConfig {
+            detectors: vec![regex_detector(true, true)],
+            ..test_config("127.0.0.1:1".parse().unwrap())
+        };
+        gateway_config.routes[0].detectors = vec!["regex".to_string()];
+        gateway_config.routes.extend([
+            RouteConfig {
+                name: "passthrough".to_string(),
+                ..Default::default()
+            },
+            RouteConfig {
+                name: "typed".to_string(),
+                fallback_messages: HashMap::from([("pii".to_string(), "no".to_string())]),
+                ..Default::default()
+            },
+            RouteConfig {
+                name: "off".to_string(),
+                enabled: false,
+                ..Default::default()
+            },
+        ]);
+        let open_gateway = spawn_gateway(&gateway_config).await;
+        gateway_config.api_keys = vec!["key".to_string()];
+        let gateway = spawn_gateway(&gateway_config).await;
+
+        let get = |gateway: SocketAddr, query: &'static str| {
+            reqwest::Client::new()
+                .get(format!("http://{}/routes{}", gateway, query))
+                .header("x-api-key", "key")
+                .send()
+        };
+        let list = |query: &'static str| async move {
+            let response = get(gateway, query).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            response.json::<Value>().await.unwrap()
+        };
+
+        assert_eq!(
+            list("").await,
+            json!([
+                {"name": "test", "detectors": ["regex"], "fallback": true},
+                {"name": "passthrough", "detectors": [], "fallback": false},
+                {"name": "typed", "detectors": [], "fallback": true},
+            ])
+        );
+        let with_params = list("?include_params=true").await;
+        assert_eq!(
+            with_params[0]["detector_params"],
+            json!({"regex": {"regex": ["email"]}})
+        );
+        assert_eq!(with_params[1]["detector_params"], json!({}));
+        assert_eq!(
+            list("?include_params=false").await[0].get("detector_params"),
+            None
+        );
+
+        // Without api_keys anyone could ask, so params are never listed
+        let response = get(open_gateway, "?include_params=true").await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let body: Value = response.json().await.unwrap();
+        assert_eq!(body["error"]["code"], "include_params_not_allowed");
+        let response = get(open_gateway, "").await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_api_keys() {
+        let (orchestrator, captured) = spawn_orchestrator(completion_response(Value::Null)).await;
+        let mut gateway_config = test_config(orchestrator);
+        let open_gateway = spawn_gateway(&gateway_config).await;
+        gateway_config.api_keys = vec!["key-1".to_string(), "key-2".to_string()];
+        let gateway = spawn_gateway(&gateway_config).await;
+
+        let request = |gateway: SocketAddr, header: Option<(&'static str, &'static str)>| {
+            let mut request = reqwest::Client::new()
+                .post(format!("http://{}/test/v1/chat/completions", gateway))
+                .json(&json!({"model": "m", "messages": user_messages()}));
+            if let Some((name, value)) = header {
+                request = request.header(name, value);
+            }
+            request.send()
+        };
+
+        for header in [("authorization", "Bearer key-2"), ("x-api-key", "key-1")] {
+            let response = request(gateway, Some(header)).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK, "{:?}", header);
+        }
+        // The gateway's key is not passed on to the orchestrator in either header
+        for (headers, _) in captured.lock().unwrap().iter() {
+            assert!(headers.get("authorization").is_none());
+            assert!(headers.get("x-api-key").is_none());
+        }
+        // Any other authorization is still meant for the orchestrator
+        let response = reqwest::Client::new()
+            .post(format!("http://{}/test/v1/chat/completions", gateway))
+            .header("x-api-key", "key-1")
+            .header("authorization", "Bearer orchestrator-token")
+            .json(&json!({"model": "m", "messages": user_messages()}))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            captured.lock().unwrap()[2].0["authorization"],
+            "Bearer orchestrator-token"
+        );
+
+        for header in [
+            None,
+            Some(("authorization", "Bearer key-3")),
+            Some(("authorization", "key-1")),
+            Some(("x-api-key", "key-")),
+        ] {
+            let response = request(gateway, header).await.unwrap();
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED, "{:?}", header);
+            assert_eq!(response.headers()["www-authenticate"], "Bearer");
+            let body: Value = response.json().await.unwrap();
+            assert_eq!(body["error"]["type"], "authentication_error");
+            assert_eq!(body["error"]["code"], "invalid_api_key");
+        }
+        // Rejected requests never reach the orchestrator
+        assert_eq!(captured.lock().unwrap().len(), 3);
+
+        // Probes stay open for the platform to reach
+        let health = reqwest::get(format!("http://{}/health", gateway))
+            .await
+            .unwrap();
+        assert_eq!(health.status(), StatusCode::OK);
+
+        // Without keys the routes are open as before
+        let response = request(open_gateway, None).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_detection_only_route() {
+        // Only the detection endpoint exists, so any attempt to generate would fail
+        let app = Router::new().route(
+            "/api/v2/text/detection/chat",
+            post(|Json(body): Json<Value>| async move {
+                assert!(body["detectors"].get("regex").is_some());
+                assert_eq!(body["messages"][0]["content"], "mail me at a@b.com");
+                Json(json!({"detections": [
+                    {"detection_type": "pii", "detection": "EmailAddress", "detector_id": "regex", "score": 0.8},
+                    {"detection_type": "pii", "detection": "EmailAddress", "detector_id": "regex", "score": 0.2},
+                ]}))
+            }),
+        );
+        let orchestrator = spawn_server(app).await;
+        let mut regex = regex_detector(true, false);
+        regex.threshold = Some(0.5);
+        let mut gateway_config = GatewayConfig {
+            detectors: vec![regex],
+            ..test_config(orchestrator)
+        };
+        gateway_config.routes[0].detectors = vec!["regex".to_string()];
+        gateway_config.routes[0].detection_only = true;
+        let gateway = spawn_gateway(&gateway_config).await;
+
+        for stream in [false, true] {
+            let response = reqwest::Client::new()
+                .post(format!("http://{}/test/v1/chat/completions", gateway))
+                .json(&json!({
+                    "model": "m",
+                    "messages": [{"role": "user", "content": "mail me at a@b.com"}],
+                    "stream": stream,
+                }))
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            let body: Value = response.json().await.unwrap();
+            assert!(body.get("choices").is_none(), "{}", body);
+            assert_eq!(
+                body,
+                json!({"detections": [
+                    {"detection_type": "pii", "detection": "EmailAddress", "detector_id": "regex", "score": 0.8}
+                ]})
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_detection_summary_is_opt_in() {
+        let (orchestrator, _) = spawn_orchestrator(completion_response(output_detections())).await;
+        let mut gateway_config = test_config(orchestrator);
+        let default_gateway = spawn_gateway(&gateway_config).await;
+        gateway_config.routes[0].return_detection_summary = true;
+        let summary_gateway = spawn_gateway(&gateway_config).await;
+
+        let mut summaries = Vec::new();
+        for gateway in [default_gateway, summary_gateway] {
+            let body: Value = reqwest::Client::new()
+                .post(format!("http://{}/test/v1/chat/completions", gateway))
+                .json(&json!({"model": "m", "messages": user_messages()}))
+                .send()
+                .await
+                .unwrap()
+                .json()
+                .await
+                .unwrap();
+            summaries.push(body.get("detection_summary").cloned());
+        }
+
+        assert_eq!(summaries[0], None);
+        let summary = summaries[1].clone().unwrap();
+        assert_eq!(summary["output_count"], 1);
+        assert_eq!(summary["blocked_by"], "output");
+    }
+
+    #[tokio::test]
+    async fn test_expose_detections_header() {
+        let mut detections = output_detections();
+        let result = detections["output"][0]["results"][0].clone();
+        let results = detections["output"][0]["results"].as_array_mut().unwrap();
+        results.push(result);
+        results.push(json!({
+            "start": 10, "end": 14, "text": "text", "detection_type": "hap",
+            "detection": "has_HAP", "detector_id": "hap", "score": 0.9
+        }));
+        let (orchestrator, _) = spawn_orchestrator(completion_response(detections.clone())).await;
+        let mut gateway_config = test_config(orchestrator);
+        let default_gateway = spawn_gateway(&gateway_config).await;
+        gateway_config.routes[0].expose_detections = true;
+        let exposing_gateway = spawn_gateway(&gateway_config).await;
+
+        let mut responses = Vec::new();
+        for gateway in [default_gateway, exposing_gateway] {
+            let response = reqwest::Client::new()
+                .post(format!("http://{}/test/v1/chat/completions", gateway))
+                .json(&json!({"model": "m", "messages": user_messages()}))
+                .send()
+                .await
+                .unwrap();
+            let header = response.headers().get("x-detections").cloned();
+            responses.push((header, response.json::<Value>().await.unwrap()));
+        }
+
+        assert_eq!(responses[0].0, None);
+        let header: Value =
+            serde_json::from_slice(responses[1].0.as_ref().unwrap().as_bytes()).unwrap();
+        assert_eq!(header, json!({"hap": 1, "regex-language": 2}));
+        // The body is the same either way
+        assert_eq!(responses[0].1, responses[1].1);
+        assert_eq!(responses[1].1["detections"], detections);
+
+        // Responses without detections still say that nothing was flagged
+        let (orchestrator, _) = spawn_orchestrator(completion_response(Value::Null)).await;
+        let mut gateway_config = test_config(orchestrator);
+        gateway_config.routes[0].expose_detections = true;
+        let gateway = spawn_gateway(&gateway_config).await;
+        let response = reqwest::Client::new()
+            .post(format!("http://{}/test/v1/chat/completions", gateway))
+            .json(&json!({"model": "m", "messages": user_messages()}))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.headers()["x-detections"], "{}");
+    }
+
+    #[tokio::test]
+    async fn test_detector_group_actions() {
+        let hap_result = json!({
+            "start": 10, "end": 14, "text": "text", "detection_type": "hap",
+            "detection": "has_HAP", "detector_id": "hap", "score": 0.9
+        });
+        let mut mixed = output_detections();
+        mixed["output"][0]["results"]
+            .as_array_mut()
+            .unwrap()
+            .push(hap_result);
+        let cases = [
+            (GroupAction::Redact, output_detections(), "[REDACTED] text"),
+            (GroupAction::Monitor, output_detections(), "generated text"),
+            (GroupAction::Redact, mixed, "fallback"),
+        ];
+
+        for (pii_action, detections, expected) in cases {
+            let (orchestrator, _) = spawn_orchestrator(completion_response(detections)).await;
+            let mut gateway_config = test_config(orchestrator);
+            gateway_config.detectors = ["pii", "hap"]
+                .into_iter()
+                .map(|name| DetectorConfig {
+                    name: name.to_string(),
+                    server: Some(
+                        if name == "pii" {
+                            "regex-language"
+                        } else {
+                            "hap"
+                        }
+                        .to_string(),
+                    ),
+                    input: false,
+                    output: true,
+                    detector_params: Some(json!({})),
+                    threshold: None,
+                })
+                .collect();
+            gateway_config.routes[0].detectors = vec!["pii".to_string(), "hap".to_string()];
+            gateway_config.routes[0].detector_groups = vec![
+                config::DetectorGroupConfig {
+                    detectors: vec!["pii".to_string()],
+                    action: pii_action,
+                },
+                config::DetectorGroupConfig {
+                    detectors: vec!["hap".to_string()],
+                    action: GroupAction::Block,
+                },
+            ];
+            let gateway = spawn_gateway(&gateway_config).await;
+
+            let body: Value = reqwest::Client::new()
+                .post(format!("http://{}/test/v1/chat/completions", gateway))
+                .json(&json!({"model": "m", "messages": user_messages()}))
+                .send()
+                .await
+                .unwrap()
+                .json()
+                .await
+                .unwrap();
+
+            assert_eq!(
+                body["choices"][0]["message"]["content"], expected,
+                "{:?}",
+                pii_action
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_streaming_detector_group_actions() {
+        let cases = [
+            (GroupAction::Block, vec!["fallback"]),
+            (GroupAction::Redact, vec!["[REDACTED] text", " more"]),
+            (GroupAction::Monitor, vec!["generated text", " more"]),
+        ];
+
+        for buffer_upstream_stream in [false, true] {
+            for (action, expected) in &cases {
+                let orchestrator = spawn_chunked_orchestrator(&[
+                    detection_frame(content_choice("generated text"), output_detections()),
+                    detection_frame(content_choice(" more"), Value::Null),
+                ])
+                .await;
+                let mut gateway_config = test_config(orchestrator);
+                gateway_config.detectors = vec![DetectorConfig {
+                    name: "pii".to_string(),
+                    server: Some("regex-language".to_string()),
+                    input: false,
+                    output: true,
+                    detector_params: Some(json!({})),
+                    threshold: None,
+                }];
+                gateway_config.routes[0].detectors = vec!["pii".to_string()];
+                gateway_config.routes[0].detector_groups = vec![config::DetectorGroupConfig {
+                    detectors: vec!["pii".to_string()],
+                    action: *action,
+                }];
+                gateway_config.routes[0].buffered_streaming = buffer_upstream_stream;
+                gateway_config.routes[0].buffer_upstream_stream = buffer_upstream_stream;
+                let gateway = spawn_gateway(&gateway_config).await;
+
+                let body = reqwest::Client::new()
+                    .post(format!("http://{}/test/v1/chat/completions", gateway))
+                    .json(&json!({"model": "m", "messages": user_messages(), "stream": true}))
+                    .send()
+                    .await
+                    .unwrap()
+                    .text()
+                    .await
+                    .unwrap();
+
+                let contents: Vec<String> = sse_data(&body)
+                    .into_iter()
+                    .map(|data| serde_json::from_str::<StreamingResponse>(data).unwrap())
+                    .filter_map(|chunk| chunk.choices.into_iter().next()?.delta.content)
+                    .collect();
+                assert_eq!(
+                    &contents, expected,
+                    "{:?}, buffered upstream: {}",
+                    action, buffer_upstream_stream
+                );
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_streaming_truncate_action() {
+        for buffer_upstream_stream in [false, true] {
+            let orchestrator = spawn_chunked_orchestrator(&[
+                detection_frame(content_choice("my email: "), Value::Null),
+                detection_frame(content_choice("generated text"), output_detections()),
+                detection_frame(content_choice(" more"), Value::Null),
+            ])
+            .await;
+            let mut gateway_config = test_config(orchestrator);
+            gateway_config.routes[0].action = DetectionAction::Truncate;
+            gateway_config.routes[0].truncation_notice = Some("...".to_string());
+            gateway_config.routes[0].buffered_streaming = buffer_upstream_stream;
+            gateway_config.routes[0].buffer_upstream_stream = buffer_upstream_stream;
+            let gateway = spawn_gateway(&gateway_config).await;
+
+            let body = reqwest::Client::new()
+                .post(format!("http://{}/test/v1/chat/completions", gateway))
+                .json(&json!({"model": "m", "messages": user_messages(), "stream": true}))
+                .send()
+                .await
+                .unwrap()
+                .text()
+                .await
+                .unwrap();
+
+            let chunks: Vec<StreamingResponse> = sse_data(&body)
+                .into_iter()
+                .map(|data| serde_json::from_str(data).unwrap())
+                .collect();
+            assert_eq!(chunks.len(), 2, "{}", body);
+            assert_eq!(
+                chunks[0].choices[0].delta.content.as_deref(),
+                Some("my email: ")
+            );
+            assert_eq!(chunks[1].choices[0].delta.content.as_deref(), Some("..."));
+            assert_eq!(chunks[1].choices[0].finish_reason.as_deref(), Some("stop"));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_streaming_ends_when_a_frame_closes_it() {
+        // The orchestrator flags the first frame and then sends nothing more, without ending
+        let app = Router::new().route(
+            "/api/v2/chat/completions-detection",
+            post(|| async {
+                let frame = detection_frame(content_choice("generated text"), output_detections());
+                let body = futures::stream::once(async move {
+                    Ok::<_, std::convert::Infallible>(frame.to_string())
+                })
+                .chain(futures::stream::pending());
+                (
+                    [("content-type", "text/event-stream")],
+                    axum::body::Body::from_stream(body),
+                )
+            }),
+        );
+        let orchestrator = spawn_server(app).await;
+
+        for action in [DetectionAction::Fallback, DetectionAction::Truncate] {
+            let mut gateway_config = test_config(orchestrator);
+            gateway_config.routes[0].action = action;
+            let gateway = spawn_gateway(&gateway_config).await;
+
+            let response = reqwest::Client::new()
+                .post(format!("http://{}/test/v1/chat/completions", gateway))
+                .json(&json!({"model": "m", "messages": user_messages(), "stream": true}))
+                .send()
+                .await
+                .unwrap();
+            let body = tokio::time::timeout(Duration::from_secs(5), response.text())
+                .await
+                .expect("stream was held open after the closing frame")
+                .unwrap();
+
+            let events = sse_data(&body);
+            assert_eq!(events.len(), 1, "{}", body);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_truncate_action() {
+        let (orchestrator, _) = spawn_orchestrator(completion_response(output_detections())).await;
+        let mut gateway_config = test_config(orchestrator);
+        gateway_config.routes[0].action = DetectionAction::Truncate;
+        gateway_config.routes[0].truncation_notice = Some("...".to_string());
+        let gateway = spawn_gateway(&gateway_config).await;
+
+        let body: Value = reqwest::Client::new()
+            .post(format!("http://{}/test/v1/chat/completions", gateway))
+            .json(&json!({"model": "m", "messages": user_messages()}))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+
+        // output_detections() flags "generated" at offset 0
+        assert_eq!(body["choices"][0]["message"]["content"], "...");
+        assert!(body["detections"]["output"].is_array());
+    }
 }
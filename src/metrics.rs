@@ -0,0 +1,335 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Detection results received from the orchestrator, before any cap is applied.
+pub static DETECTION_RESULTS_PRODUCED: AtomicU64 = AtomicU64::new(0);
+/// Detection results forwarded to clients after capping.
+pub static DETECTION_RESULTS_RETURNED: AtomicU64 = AtomicU64::new(0);
+
+pub fn record_detection_results(produced: usize, returned: usize) {
+    DETECTION_RESULTS_PRODUCED.fetch_add(produced as u64, Ordering::Relaxed);
+    DETECTION_RESULTS_RETURNED.fetch_add(returned as u64, Ordering::Relaxed);
+}
+
+/// Whole retries left in the retry budget, if one is configured.
+pub static RETRY_BUDGET_AVAILABLE: AtomicU64 = AtomicU64::new(0);
+/// Retries that were not made because the retry budget was exhausted.
+pub static RETRIES_DENIED: AtomicU64 = AtomicU64::new(0);
+
+/// Upper bounds of the request duration histogram buckets, in seconds.
+const DURATION_BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// A traced observation, linking a histogram bucket to the trace of a request that fell in it.
+#[derive(Debug, Clone)]
+struct Exemplar {
+    trace_id: String,
+    value: f64,
+}
+
+#[derive(Debug, Default)]
+struct RouteMetrics {
+    /// Indexed by whether the request was streaming
+    requests: [u64; 2],
+    orchestrator_errors: u64,
+    duration_buckets: [u64; DURATION_BUCKETS.len()],
+    duration_sum: f64,
+    duration_count: u64,
+    /// The latest traced request in each duration bucket, with `+Inf` last. Cumulative buckets
+    /// only get an exemplar for requests that did not fit a smaller one.
+    duration_exemplars: [Option<Exemplar>; DURATION_BUCKETS.len() + 1],
+}
+
+/// The text formats metrics are served in. Only OpenMetrics can carry exemplars.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Prometheus,
+    OpenMetrics,
+}
+
+impl Format {
+    /// OpenMetrics when the scraper's `Accept` header asks for it, as Prometheus does with
+    /// exemplar storage enabled, and the Prometheus text format otherwise.
+    pub fn negotiate(accept: Option<&str>) -> Self {
+        match accept {
+            Some(accept) if accept.contains("application/openmetrics-text") => Format::OpenMetrics,
+            _ => Format::Prometheus,
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Format::Prometheus => "text/plain; version=0.0.4",
+            Format::OpenMetrics => "application/openmetrics-text; version=1.0.0; charset=utf-8",
+        }
+    }
+}
+
+static ROUTES: Mutex<BTreeMap<String, RouteMetrics>> = Mutex::new(BTreeMap::new());
+
+/// Records a completed chat completion request. Streaming requests last until the stream ends.
+/// A request with a `trace_id` becomes the exemplar of the duration bucket it fell in.
+pub fn record_request(
+    route: &str,
+    streaming: bool,
+    orchestrator_error: bool,
+    duration: Duration,
+    trace_id: Option<&str>,
+) {
+    let mut routes = ROUTES.lock().unwrap();
+    let metrics = routes.entry(route.to_string()).or_default();
+    metrics.requests[streaming as usize] += 1;
+    if orchestrator_error {
+        metrics.orchestrator_errors += 1;
+    }
+    let seconds = duration.as_secs_f64();
+    for (bucket, bound) in metrics.duration_buckets.iter_mut().zip(DURATION_BUCKETS) {
+        if seconds <= bound {
+            *bucket += 1;
+        }
+    }
+    metrics.duration_sum += seconds;
+    metrics.duration_count += 1;
+    if let Some(trace_id) = trace_id {
+        let bucket = DURATION_BUCKETS
+            .iter()
+            .position(|bound| seconds <= *bound)
+            .unwrap_or(DURATION_BUCKETS.len());
+        metrics.duration_exemplars[bucket] = Some(Exemplar {
+            trace_id: trace_id.to_string(),
+            value: seconds,
+        });
+    }
+}
+
+/// Writes the `HELP` and `TYPE` lines of a metric family. OpenMetrics names a counter family
+/// without the `_total` suffix its samples carry.
+fn family(out: &mut String, format: Format, name: &str, kind: &str, help: &str) {
+    let name = match (format, kind) {
+        (Format::OpenMetrics, "counter") => name.strip_suffix("_total").unwrap_or(name),
+        _ => name,
+    };
+    let _ = writeln!(out, "# HELP {name} {help}\n# TYPE {name} {kind}");
+}
+
+/// The exemplar suffix of a histogram bucket sample, in OpenMetrics only.
+fn exemplar(format: Format, exemplar: &Option<Exemplar>) -> String {
+    match (format, exemplar) {
+        (Format::OpenMetrics, Some(exemplar)) => format!(
+            " # {{trace_id=\"{}\"}} {}",
+            escape_label(&exemplar.trace_id),
+            exemplar.value
+        ),
+        _ => String::new(),
+    }
+}
+
+/// Renders every metric in `format`.
+pub fn render(format: Format) -> String {
+    let mut out = String::new();
+    let counter = |out: &mut String, name: &str, help: &str, value: u64| {
+        family(out, format, name, "counter", help);
+        let _ = writeln!(out, "{name} {value}");
+    };
+    counter(
+        &mut out,
+        "gateway_detection_results_produced_total",
+        "Detection results received from the orchestrator.",
+        DETECTION_RESULTS_PRODUCED.load(Ordering::Relaxed),
+    );
+    counter(
+        &mut out,
+        "gateway_detection_results_returned_total",
+        "Detection results forwarded to clients after capping.",
+        DETECTION_RESULTS_RETURNED.load(Ordering::Relaxed),
+    );
+    counter(
+        &mut out,
+        "gateway_retries_denied_total",
+        "Retries not made because the retry budget was exhausted.",
+        RETRIES_DENIED.load(Ordering::Relaxed),
+    );
+    family(
+        &mut out,
+        format,
+        "gateway_retry_budget_available",
+        "gauge",
+        "Whole retries left in the retry budget.",
+    );
+    let _ = writeln!(
+        out,
+        "gateway_retry_budget_available {}",
+        RETRY_BUDGET_AVAILABLE.load(Ordering::Relaxed)
+    );
+
+    let routes = ROUTES.lock().unwrap();
+    family(
+        &mut out,
+        format,
+        "gateway_requests_total",
+        "counter",
+        "Chat completion requests handled.",
+    );
+    for (route, metrics) in routes.iter() {
+        for (streaming, count) in metrics.requests.iter().enumerate() {
+            let _ = writeln!(
+                out,
+                "gateway_requests_total{{route=\"{}\",streaming=\"{}\"}} {}",
+                escape_label(route),
+                streaming == 1,
+                count
+            );
+        }
+    }
+    family(
+        &mut out,
+        format,
+        "gateway_orchestrator_errors_total",
+        "counter",
+        "Requests that failed at the orchestrator.",
+    );
+    for (route, metrics) in routes.iter() {
+        let _ = writeln!(
+            out,
+            "gateway_orchestrator_errors_total{{route=\"{}\"}} {}",
+            escape_label(route),
+            metrics.orchestrator_errors
+        );
+    }
+    family(
+        &mut out,
+        format,
+        "gateway_request_duration_seconds",
+        "histogram",
+        "Time taken to handle a chat completion request.",
+    );
+    for (route, metrics) in routes.iter() {
+        let route = escape_label(route);
+        let exemplars = &metrics.duration_exemplars;
+        for ((count, bound), traced) in metrics
+            .duration_buckets
+            .iter()
+            .zip(DURATION_BUCKETS)
+            .zip(exemplars)
+        {
+            let _ = writeln!(
+                out,
+                "gateway_request_duration_seconds_bucket{{route=\"{route}\",le=\"{bound}\"}} {count}{}",
+                exemplar(format, traced)
+            );
+        }
+        let _ = writeln!(
+            out,
+            "gateway_request_duration_seconds_bucket{{route=\"{route}\",le=\"+Inf\"}} {count}{}\n\
+             gateway_request_duration_seconds_sum{{route=\"{route}\"}} {sum}\n\
+             gateway_request_duration_seconds_count{{route=\"{route}\"}} {count}",
+            exemplar(format, &exemplars[DURATION_BUCKETS.len()]),
+            count = metrics.duration_count,
+            sum = metrics.duration_sum,
+        );
+    }
+    if format == Format::OpenMetrics {
+        out.push_str("# EOF\n");
+    }
+    out
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_route_metrics() {
+        record_request("render\"test", false, true, Duration::from_millis(30), None);
+        record_request("render\"test", true, false, Duration::from_secs(20), None);
+
+        let rendered = render(Format::Prometheus);
+        for line in [
+            "gateway_requests_total{route=\"render\\\"test\",streaming=\"false\"} 1",
+            "gateway_requests_total{route=\"render\\\"test\",streaming=\"true\"} 1",
+            "gateway_orchestrator_errors_total{route=\"render\\\"test\"} 1",
+            "gateway_request_duration_seconds_bucket{route=\"render\\\"test\",le=\"0.025\"} 0",
+            "gateway_request_duration_seconds_bucket{route=\"render\\\"test\",le=\"0.05\"} 1",
+            "gateway_request_duration_seconds_bucket{route=\"render\\\"test\",le=\"10\"} 1",
+            "gateway_request_duration_seconds_bucket{route=\"render\\\"test\",le=\"+Inf\"} 2",
+            "gateway_request_duration_seconds_count{route=\"render\\\"test\"} 2",
+        ] {
+            assert!(
+                rendered.lines().any(|l| l == line),
+                "{}\n{}",
+                line,
+                rendered
+            );
+        }
+    }
+
+    #[test]
+    fn test_render_exemplars() {
+        let trace_id = "4bf92f3577b34da6a3ce929d0e0e4736";
+        record_request("exemplars", false, false, Duration::from_millis(30), None);
+        record_request(
+            "exemplars",
+            false,
+            false,
+            Duration::from_millis(40),
+            Some(trace_id),
+        );
+        record_request(
+            "exemplars",
+            false,
+            false,
+            Duration::from_secs(20),
+            Some("slow"),
+        );
+
+        let rendered = render(Format::OpenMetrics);
+        for line in [
+            "# TYPE gateway_requests counter",
+            "gateway_request_duration_seconds_bucket{route=\"exemplars\",le=\"0.025\"} 0",
+            "gateway_request_duration_seconds_bucket{route=\"exemplars\",le=\"0.05\"} 2 \
+             # {trace_id=\"4bf92f3577b34da6a3ce929d0e0e4736\"} 0.04",
+            "gateway_request_duration_seconds_bucket{route=\"exemplars\",le=\"0.1\"} 2",
+            "gateway_request_duration_seconds_bucket{route=\"exemplars\",le=\"+Inf\"} 3 \
+             # {trace_id=\"slow\"} 20",
+        ] {
+            assert!(
+                rendered.lines().any(|l| l == line),
+                "{}\n{}",
+                line,
+                rendered
+            );
+        }
+        assert!(rendered.ends_with("# EOF\n"));
+
+        // The Prometheus text format has no room for exemplars
+        let rendered = render(Format::Prometheus);
+        assert!(!rendered.contains(trace_id));
+        assert!(rendered
+            .lines()
+            .any(|l| l
+                == "gateway_request_duration_seconds_bucket{route=\"exemplars\",le=\"0.05\"} 2"));
+    }
+
+    #[test]
+    fn test_format_negotiation() {
+        assert_eq!(Format::negotiate(None), Format::Prometheus);
+        assert_eq!(Format::negotiate(Some("text/plain")), Format::Prometheus);
+        assert_eq!(
+            Format::negotiate(Some(
+                "application/openmetrics-text;version=1.0.0,text/plain;version=0.0.4;q=0.5"
+            )),
+            Format::OpenMetrics
+        );
+    }
+}
@@ -0,0 +1,179 @@
+use std::convert::Infallible;
+use std::sync::{Arc, Mutex, RwLock};
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use axum::extract::Request;
+use axum::response::Response;
+use axum::routing::future::RouteFuture;
+use axum::Router;
+use futures::{Stream, StreamExt};
+use tower_service::Service;
+
+/// Sends each request to the router built from the current config. A config reload swaps in a
+/// new router; requests already dispatched keep the one they started with, so they finish on the
+/// config they were accepted under. The handlers only read config captured when their router was
+/// built, so no part of a request can see a newer config than another.
+#[derive(Clone)]
+pub struct ReloadableRouter {
+    current: Arc<RwLock<Router>>,
+    /// Held for the whole of a reload, so reloads run one at a time.
+    reloading: Arc<Mutex<()>>,
+}
+
+impl ReloadableRouter {
+    pub fn new(router: Router) -> Self {
+        ReloadableRouter {
+            current: Arc::new(RwLock::new(router)),
+            reloading: Arc::new(Mutex::new(())),
+        }
+    }
+
+    /// Serves every request from now on with `router`.
+    pub fn swap(&self, router: Router) {
+        *self.current.write().unwrap() = router;
+    }
+
+    /// Builds a router with `build` and swaps it in, unless building fails. A reload started
+    /// while another is still building waits for it to finish, so swaps never interleave and the
+    /// router served is always the one the latest reload built.
+    pub fn reload(&self, build: impl FnOnce() -> Result<Router, String>) -> Result<(), String> {
+        let _reloading = self.reloading.lock().unwrap();
+        self.swap(build()?);
+        Ok(())
+    }
+
+    /// A router to serve that hands every request to the current router.
+    pub fn into_router(self) -> Router {
+        Router::new().fallback_service(self)
+    }
+}
+
+/// Calls `reload` once for each burst of `triggers`: a trigger arriving within `window` of the
+/// previous one joins the same burst, and the reload runs once the burst is over. Returns when
+/// `triggers` ends.
+pub async fn debounce(
+    mut triggers: impl Stream<Item = ()> + Unpin,
+    window: Duration,
+    mut reload: impl FnMut(),
+) {
+    while triggers.next().await.is_some() {
+        let mut ended = false;
+        while let Ok(next) = tokio::time::timeout(window, triggers.next()).await {
+            if next.is_none() {
+                ended = true;
+                break;
+            }
+        }
+        reload();
+        if ended {
+            return;
+        }
+    }
+}
+
+impl Service<Request> for ReloadableRouter {
+    type Response = Response;
+    type Error = Infallible;
+    type Future = RouteFuture<Infallible>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: Request) -> Self::Future {
+        let mut router = self.current.read().unwrap().clone();
+        router.call(request)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+    use std::thread;
+
+    use axum::body::Body;
+    use axum::routing::get;
+
+    use super::*;
+
+    fn answering(body: &'static str) -> Router {
+        Router::new().route("/", get(move || async move { body }))
+    }
+
+    async fn served(router: &mut ReloadableRouter) -> String {
+        let response = router.call(Request::new(Body::empty())).await.unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_reloads_are_serialized() {
+        let mut router = ReloadableRouter::new(answering("initial"));
+        let (started, first_started) = mpsc::channel();
+        let (release, released) = mpsc::channel::<()>();
+        let first = {
+            let router = router.clone();
+            thread::spawn(move || {
+                router.reload(|| {
+                    started.send(()).unwrap();
+                    released.recv().unwrap();
+                    Ok(answering("first"))
+                })
+            })
+        };
+        first_started.recv().unwrap();
+
+        let (built, second_built) = mpsc::channel();
+        let second = {
+            let router = router.clone();
+            thread::spawn(move || {
+                router.reload(|| {
+                    built.send(()).unwrap();
+                    Ok(answering("second"))
+                })
+            })
+        };
+        // The second reload waits for the first rather than building alongside it
+        assert!(second_built
+            .recv_timeout(Duration::from_millis(100))
+            .is_err());
+        assert_eq!(served(&mut router).await, "initial");
+
+        release.send(()).unwrap();
+        first.join().unwrap().unwrap();
+        second.join().unwrap().unwrap();
+        assert_eq!(served(&mut router).await, "second");
+
+        // A reload that fails to build leaves the current router in place
+        let e = router.reload(|| Err("invalid".to_string())).unwrap_err();
+        assert_eq!(e, "invalid");
+        assert_eq!(served(&mut router).await, "second");
+    }
+
+    #[tokio::test]
+    async fn test_debounce_reloads_once_per_burst() {
+        let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+        let triggers = futures::stream::unfold(receiver, |mut receiver| async move {
+            receiver.recv().await.map(|()| ((), receiver))
+        });
+        let producer = tokio::spawn(async move {
+            for _ in 0..5 {
+                sender.send(()).unwrap();
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+            tokio::time::sleep(Duration::from_millis(500)).await;
+            sender.send(()).unwrap();
+        });
+
+        let mut reloads = 0;
+        debounce(Box::pin(triggers), Duration::from_millis(200), || {
+            reloads += 1
+        })
+        .await;
+        producer.await.unwrap();
+        assert_eq!(reloads, 2);
+    }
+}
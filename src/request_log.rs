@@ -0,0 +1,191 @@
+use std::time::{Duration, Instant};
+
+use crate::api::Detections;
+use crate::metrics;
+
+/// What the gateway did with a response, ordered from least to most intrusive.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Decision {
+    #[default]
+    Passthrough,
+    Monitor,
+    Redact,
+    Truncate,
+    Fallback,
+    Error,
+}
+
+/// What came of an orchestrator call. It is kept apart from the [`RequestLog`] so a call shared
+/// by coalesced requests can be recorded once and merged into the log of each of them.
+#[derive(Debug, Clone, Default)]
+pub struct Outcome {
+    orchestrator_latency: Option<Duration>,
+    decision: Decision,
+    detector: Option<(String, f64)>,
+    orchestrator_error: bool,
+}
+
+impl Outcome {
+    /// Marks the orchestrator as having responded after `latency`.
+    pub fn orchestrator_responded(&mut self, latency: Duration) {
+        self.orchestrator_latency = Some(latency);
+    }
+
+    /// Marks the request as failed by the orchestrator, either on the request itself or mid
+    /// stream.
+    pub fn orchestrator_failed(&mut self) {
+        self.orchestrator_error = true;
+        self.record(Decision::Error, None);
+    }
+
+    /// Records a decision, keeping the most intrusive one and the highest scoring detection seen
+    /// so far.
+    pub fn record(&mut self, decision: Decision, detections: Option<&Detections>) {
+        self.decision = self.decision.max(decision);
+        if let Some((detector, score)) = detections.and_then(Detections::top_result) {
+            self.record_detector(detector, score);
+        }
+    }
+
+    fn record_detector(&mut self, detector: &str, score: f64) {
+        if self.detector.as_ref().is_none_or(|(_, best)| score > *best) {
+            self.detector = Some((detector.to_string(), score));
+        }
+    }
+}
+
+/// Collects the outcome of a chat completion request and logs it as a single INFO line when
+/// dropped, so a request that ends early (error or client disconnect) is still reported. Only
+/// metadata is logged, never message content. The request is counted in the route metrics at
+/// the same time.
+#[derive(Debug)]
+pub struct RequestLog {
+    route: String,
+    model: String,
+    streaming: bool,
+    started: Instant,
+    outcome: Outcome,
+    trace_id: Option<String>,
+}
+
+impl RequestLog {
+    pub fn new(route: &str, model: Option<&str>, streaming: bool) -> Self {
+        RequestLog {
+            route: route.to_string(),
+            model: model.unwrap_or("unknown").to_string(),
+            streaming,
+            started: Instant::now(),
+            outcome: Outcome::default(),
+            trace_id: None,
+        }
+    }
+
+    /// Links the request's duration in the metrics to its trace, as an exemplar.
+    pub fn with_trace_id(mut self, trace_id: Option<&str>) -> Self {
+        self.trace_id = trace_id.map(str::to_string);
+        self
+    }
+
+    /// Marks the orchestrator as having responded: the full response for non-streaming
+    /// requests, the start of the stream for streaming ones.
+    pub fn orchestrator_responded(&mut self) {
+        self.outcome.orchestrator_responded(self.started.elapsed());
+    }
+
+    /// See [`Outcome::orchestrator_failed`].
+    pub fn orchestrator_failed(&mut self) {
+        self.outcome.orchestrator_failed();
+    }
+
+    /// See [`Outcome::record`].
+    pub fn record(&mut self, decision: Decision, detections: Option<&Detections>) {
+        self.outcome.record(decision, detections);
+    }
+
+    /// Takes on the outcome of an orchestrator call made for this request, which may have been
+    /// shared with others.
+    pub fn merge(&mut self, outcome: &Outcome) {
+        let own = &mut self.outcome;
+        own.orchestrator_latency = own.orchestrator_latency.or(outcome.orchestrator_latency);
+        own.decision = own.decision.max(outcome.decision);
+        own.orchestrator_error |= outcome.orchestrator_error;
+        if let Some((detector, score)) = &outcome.detector {
+            own.record_detector(detector, *score);
+        }
+    }
+}
+
+impl Drop for RequestLog {
+    fn drop(&mut self) {
+        let outcome = &self.outcome;
+        let (detector, score) = match &outcome.detector {
+            Some((detector, score)) => (Some(detector.as_str()), Some(*score)),
+            None => (None, None),
+        };
+        tracing::info!(
+            route = %self.route,
+            model = %self.model,
+            streaming = self.streaming,
+            orchestrator_latency_ms = outcome.orchestrator_latency.map(|l| l.as_millis() as u64),
+            decision = ?outcome.decision,
+            detector,
+            score,
+            "request completed"
+        );
+        metrics::record_request(
+            &self.route,
+            self.streaming,
+            outcome.orchestrator_error,
+            self.started.elapsed(),
+            self.trace_id.as_deref(),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn detections(detector_id: &str, score: f64) -> Detections {
+        serde_json::from_value(json!({
+            "input": null,
+            "output": [{"choice_index": 0, "results": [{
+                "start": 0, "end": 1, "text": "a", "detection_type": "pii",
+                "detection": "EmailAddress", "detector_id": detector_id, "score": score
+            }]}]
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_record_keeps_most_intrusive_decision_and_top_score() {
+        let mut log = RequestLog::new("route", Some("model"), true);
+        log.record(Decision::Fallback, Some(&detections("hap", 0.6)));
+        log.record(Decision::Passthrough, Some(&detections("regex", 0.9)));
+        log.record(Decision::Passthrough, Some(&detections("pii", 0.2)));
+        log.record(Decision::Passthrough, None);
+
+        assert_eq!(log.outcome.decision, Decision::Fallback);
+        assert_eq!(log.outcome.detector, Some(("regex".to_string(), 0.9)));
+    }
+
+    #[test]
+    fn test_merge_shared_outcome() {
+        let mut shared = Outcome::default();
+        shared.orchestrator_responded(Duration::from_millis(5));
+        shared.record(Decision::Redact, Some(&detections("hap", 0.6)));
+
+        let mut log = RequestLog::new("route", Some("model"), false);
+        log.record(Decision::Passthrough, Some(&detections("regex", 0.4)));
+        log.merge(&shared);
+
+        assert_eq!(log.outcome.decision, Decision::Redact);
+        assert_eq!(log.outcome.detector, Some(("hap".to_string(), 0.6)));
+        assert_eq!(
+            log.outcome.orchestrator_latency,
+            Some(Duration::from_millis(5))
+        );
+        assert!(!log.outcome.orchestrator_error);
+    }
+}
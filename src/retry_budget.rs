@@ -0,0 +1,86 @@
+use std::sync::atomic::Ordering;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use crate::config::RetryBudgetConfig;
+use crate::metrics;
+
+/// Limits orchestrator retries across all requests to a steady rate with some burst capacity,
+/// so that a widespread outage doesn't turn every failing request into several more.
+#[derive(Debug)]
+pub struct RetryBudget {
+    rate: f64,
+    burst: f64,
+    state: Mutex<Bucket>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl RetryBudget {
+    pub fn new(config: &RetryBudgetConfig) -> Self {
+        let burst = f64::from(config.burst);
+        metrics::RETRY_BUDGET_AVAILABLE.store(config.burst as u64, Ordering::Relaxed);
+        RetryBudget {
+            rate: config.retries_per_second.max(0.0),
+            burst,
+            state: Mutex::new(Bucket {
+                tokens: burst,
+                refilled: Instant::now(),
+            }),
+        }
+    }
+
+    /// Takes one retry from the budget, returning false if it is exhausted.
+    pub fn try_acquire(&self) -> bool {
+        self.try_acquire_at(Instant::now())
+    }
+
+    fn try_acquire_at(&self, now: Instant) -> bool {
+        let mut bucket = self.state.lock().unwrap();
+        let elapsed = now.saturating_duration_since(bucket.refilled).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.rate).min(self.burst);
+        bucket.refilled = now;
+        let acquired = bucket.tokens >= 1.0;
+        if acquired {
+            bucket.tokens -= 1.0;
+        } else {
+            metrics::RETRIES_DENIED.fetch_add(1, Ordering::Relaxed);
+        }
+        metrics::RETRY_BUDGET_AVAILABLE.store(bucket.tokens as u64, Ordering::Relaxed);
+        acquired
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_budget_depletes_and_refills() {
+        let budget = RetryBudget::new(&RetryBudgetConfig {
+            retries_per_second: 2.0,
+            burst: 2,
+        });
+        let start = budget.state.lock().unwrap().refilled;
+
+        assert!(budget.try_acquire_at(start));
+        assert!(budget.try_acquire_at(start));
+        assert!(!budget.try_acquire_at(start));
+
+        // Half a second at two per second buys back a single retry
+        let later = start + Duration::from_millis(500);
+        assert!(budget.try_acquire_at(later));
+        assert!(!budget.try_acquire_at(later));
+
+        // Refilling never exceeds the burst
+        let much_later = later + Duration::from_secs(60);
+        assert!(budget.try_acquire_at(much_later));
+        assert!(budget.try_acquire_at(much_later));
+        assert!(!budget.try_acquire_at(much_later));
+    }
+}
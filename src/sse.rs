@@ -0,0 +1,134 @@
+/// Reassembles server-sent events from a byte stream split at arbitrary offsets. Bytes are
+/// buffered until a line is complete, and the `data` fields of an event are emitted once the
+/// blank line ending the event arrives. Lines may end in `\n`, `\r\n` or `\r`, and the single
+/// space after the colon is optional, as the SSE spec allows.
+#[derive(Debug, Default)]
+pub struct SseDecoder {
+    buffer: Vec<u8>,
+    data: Vec<String>,
+}
+
+impl SseDecoder {
+    /// Feeds the next chunk of the stream, returning the data of every event it completes.
+    /// Multiple `data` lines within one event are joined with `\n`.
+    pub fn push(&mut self, chunk: &[u8]) -> Result<Vec<String>, std::str::Utf8Error> {
+        self.buffer.extend_from_slice(chunk);
+        let mut events = Vec::new();
+        let mut consumed = 0;
+        while let Some(pos) = self.buffer[consumed..]
+            .iter()
+            .position(|&b| b == b'\n' || b == b'\r')
+        {
+            let end = consumed + pos;
+            let terminator = match (self.buffer[end], self.buffer.get(end + 1)) {
+                (b'\r', Some(b'\n')) => 2,
+                // A trailing \r may be the first half of a \r\n split across chunks
+                (b'\r', None) => break,
+                _ => 1,
+            };
+            let line = std::str::from_utf8(&self.buffer[consumed..end]);
+            consumed = end + terminator;
+            if let Some(event) = take_line(&mut self.data, line?) {
+                events.push(event);
+            }
+        }
+        self.buffer.drain(..consumed);
+        Ok(events)
+    }
+
+    /// Ends the stream, returning the data of an event that was not followed by a blank line.
+    pub fn finish(&mut self) -> Result<Option<String>, std::str::Utf8Error> {
+        let rest = std::mem::take(&mut self.buffer);
+        let rest = std::str::from_utf8(&rest)?;
+        let line = rest.strip_suffix('\r').unwrap_or(rest);
+        if !line.is_empty() {
+            take_line(&mut self.data, line);
+        }
+        Ok(take_line(&mut self.data, ""))
+    }
+}
+
+/// Adds a line to the data of the event being assembled, returning the event's data once the
+/// blank line ending it arrives.
+fn take_line(data: &mut Vec<String>, line: &str) -> Option<String> {
+    if line.is_empty() {
+        if data.is_empty() {
+            return None;
+        }
+        let event = data.join("\n");
+        data.clear();
+        return Some(event);
+    }
+    if let Some(field) = line.strip_prefix("data:") {
+        data.push(field.strip_prefix(' ').unwrap_or(field).to_string());
+    }
+    // Comments and other fields (event, id, retry) carry nothing the gateway forwards
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Feeds `stream` to a decoder in chunks split at `offsets`, collecting every event.
+    fn decode_split(stream: &[u8], offsets: &[usize]) -> Vec<String> {
+        let mut decoder = SseDecoder::default();
+        let mut events = Vec::new();
+        let mut start = 0;
+        for &offset in offsets.iter().chain([stream.len()].iter()) {
+            events.extend(decoder.push(&stream[start..offset]).unwrap());
+            start = offset;
+        }
+        events.extend(decoder.finish().unwrap());
+        events
+    }
+
+    #[test]
+    fn test_event_spanning_three_chunks() {
+        let stream = b"data: {\"content\": \"hello\"}\n\n";
+        assert_eq!(decode_split(stream, &[3, 15]), ["{\"content\": \"hello\"}"]);
+    }
+
+    #[test]
+    fn test_two_events_in_one_chunk() {
+        let stream = b"data: {\"a\": 1}\n\ndata: {\"b\": 2}\n\n";
+        assert_eq!(decode_split(stream, &[]), ["{\"a\": 1}", "{\"b\": 2}"]);
+    }
+
+    #[test]
+    fn test_every_split_offset() {
+        let stream =
+            "data: {\"a\": \"é\"}\r\n\r\n: keep-alive\r\ndata: x\rdata: y\n\ndata: [DONE]\n\n";
+        let expected = ["{\"a\": \"é\"}", "x\ny", "[DONE]"];
+        // Every single split, including inside the multibyte character and between \r and \n
+        for offset in 0..=stream.len() {
+            assert_eq!(
+                decode_split(stream.as_bytes(), &[offset]),
+                expected,
+                "{}",
+                offset
+            );
+        }
+        // One byte at a time
+        let offsets: Vec<usize> = (1..stream.len()).collect();
+        assert_eq!(decode_split(stream.as_bytes(), &offsets), expected);
+    }
+
+    #[test]
+    fn test_line_endings() {
+        let stream = b"data: a\r\ndata:b\rdata: c\n: comment\r\n\r\n";
+        assert_eq!(decode_split(stream, &[]), ["a\nb\nc"]);
+    }
+
+    #[test]
+    fn test_unterminated_event_is_flushed_at_end() {
+        assert_eq!(decode_split(b"data: a\n\ndata: b", &[]), ["a", "b"]);
+        assert_eq!(decode_split(b"data: a\r", &[]), ["a"]);
+    }
+
+    #[test]
+    fn test_invalid_utf8() {
+        let mut decoder = SseDecoder::default();
+        assert!(decoder.push(b"data: \xff\n").is_err());
+    }
+}
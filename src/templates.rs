@@ -0,0 +1,106 @@
+use axum::http::HeaderMap;
+use serde_json::{Map, Value};
+
+/// Resolves request context placeholders in every string within detector params:
+///
+/// - `${request.<field>}`: a top level string, number or boolean field of the request payload,
+///   e.g. `${request.model}`
+/// - `${header.<name>}`: a request header, e.g. `${header.x-tenant-id}`
+///
+/// Placeholders whose value is missing resolve to an empty string. Anything else in `${...}` is
+/// left untouched.
+pub fn resolve(params: &mut Value, payload: &Map<String, Value>, headers: &HeaderMap) {
+    match params {
+        Value::String(s) if s.contains("${") => *s = resolve_str(s, payload, headers),
+        Value::Array(values) => values.iter_mut().for_each(|v| resolve(v, payload, headers)),
+        Value::Object(values) => values
+            .values_mut()
+            .for_each(|v| resolve(v, payload, headers)),
+        _ => {}
+    }
+}
+
+fn resolve_str(s: &str, payload: &Map<String, Value>, headers: &HeaderMap) -> String {
+    let mut resolved = String::new();
+    let mut rest = s;
+    while let Some(start) = rest.find("${") {
+        let Some(end) = rest[start..].find('}') else {
+            break;
+        };
+        resolved.push_str(&rest[..start]);
+        let placeholder = &rest[start..start + end + 1];
+        match lookup(&placeholder[2..placeholder.len() - 1], payload, headers) {
+            Some(Some(value)) => resolved.push_str(&value),
+            Some(None) => tracing::debug!("No value for detector param {}", placeholder),
+            None => resolved.push_str(placeholder),
+        }
+        rest = &rest[start + end + 1..];
+    }
+    resolved.push_str(rest);
+    resolved
+}
+
+/// `None` if the variable isn't a request context variable, `Some(None)` if it is but has no
+/// value in this request.
+fn lookup(
+    variable: &str,
+    payload: &Map<String, Value>,
+    headers: &HeaderMap,
+) -> Option<Option<String>> {
+    if let Some(field) = variable.strip_prefix("request.") {
+        return Some(payload.get(field).and_then(|value| match value {
+            Value::String(s) => Some(s.clone()),
+            Value::Number(_) | Value::Bool(_) => Some(value.to_string()),
+            _ => None,
+        }));
+    }
+    if let Some(name) = variable.strip_prefix("header.") {
+        return Some(
+            headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string),
+        );
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+    use serde_json::json;
+
+    #[test]
+    fn test_resolve() {
+        let payload = json!({"model": "granite", "temperature": 0.5, "messages": []});
+        let headers = HeaderMap::from_iter([(
+            "x-tenant-id".parse().unwrap(),
+            HeaderValue::from_static("acme"),
+        )]);
+        let mut params = json!({
+            "model": "${request.model}",
+            "nested": [{"label": "${header.x-tenant-id}/${request.model}"}],
+            "temperature": "${request.temperature}",
+            "missing": "${request.user}${header.x-user-id}",
+            "messages": "${request.messages}",
+            "other": "${HOME} and ${unterminated",
+            "threshold": 0.5,
+        });
+
+        resolve(&mut params, payload.as_object().unwrap(), &headers);
+
+        assert_eq!(
+            params,
+            json!({
+                "model": "granite",
+                "nested": [{"label": "acme/granite"}],
+                "temperature": "0.5",
+                "missing": "",
+                "messages": "",
+                "other": "${HOME} and ${unterminated",
+                "threshold": 0.5,
+            })
+        );
+    }
+}
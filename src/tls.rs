@@ -0,0 +1,205 @@
+use std::fs;
+use std::future::Future;
+
+use axum::Router;
+use hyper::server::conn::http1;
+use hyper_util::rt::TokioIo;
+use hyper_util::service::TowerToHyperService;
+use openssl::pkcs12::Pkcs12;
+use openssl::pkey::PKey;
+use openssl::x509::X509;
+use tokio::net::TcpListener;
+use tokio::sync::watch;
+use tokio_native_tls::TlsAcceptor;
+
+use crate::config::ListenerTlsConfig;
+
+/// Bundles a PEM certificate and private key into an unencrypted PKCS#12 archive, the format
+/// native-tls expects identities in.
+pub fn pkcs12_der_from_pem(cert_pem: &[u8], key_pem: &[u8]) -> Result<Vec<u8>, anyhow::Error> {
+    let cert = X509::from_pem(cert_pem)?;
+    let key = PKey::private_key_from_pem(key_pem)?;
+
+    let mut builder = Pkcs12::builder();
+    builder.name("identity");
+    builder.pkey(&key);
+    builder.cert(&cert);
+    Ok(builder.build2("")?.to_der()?)
+}
+
+/// Builds the inbound TLS acceptor from PEM certificate and key contents.
+pub fn acceptor_from_pem(cert_pem: &[u8], key_pem: &[u8]) -> Result<TlsAcceptor, anyhow::Error> {
+    let pkcs12_der = pkcs12_der_from_pem(cert_pem, key_pem)?;
+    let identity = native_tls::Identity::from_pkcs12(&pkcs12_der, "")?;
+    Ok(TlsAcceptor::from(native_tls::TlsAcceptor::new(identity)?))
+}
+
+/// Builds the inbound TLS acceptor from the configured certificate and key files. The files are
+/// read once; a rotated certificate is only picked up on restart.
+pub fn acceptor(config: &ListenerTlsConfig) -> Result<TlsAcceptor, anyhow::Error> {
+    let cert_pem = fs::read(&config.cert_path)?;
+    let key_pem = fs::read(&config.key_path)?;
+    acceptor_from_pem(&cert_pem, &key_pem)
+}
+
+/// Serves the router over HTTPS (HTTP/1.1). Each connection is handshaked on its own task so a
+/// slow or failing client cannot hold up the accept loop. Once `shutdown` resolves no new
+/// connections are accepted, and this returns when the open ones have finished their requests.
+pub async fn serve(
+    listener: TcpListener,
+    app: Router,
+    acceptor: TlsAcceptor,
+    shutdown: impl Future<Output = ()>,
+) {
+    // Connections hold a receiver each, so the sender sees the channel close once all are done
+    let (signal_tx, signal_rx) = watch::channel(());
+    tokio::pin!(shutdown);
+    loop {
+        let (stream, peer) = tokio::select! {
+            conn = listener.accept() => match conn {
+                Ok(conn) => conn,
+                Err(e) => {
+                    tracing::error!("Failed to accept connection: {}", e);
+                    continue;
+                }
+            },
+            _ = &mut shutdown => break,
+        };
+        let acceptor = acceptor.clone();
+        let app = app.clone();
+        let mut signal = signal_rx.clone();
+        tokio::spawn(async move {
+            let stream = match acceptor.accept(stream).await {
+                Ok(stream) => stream,
+                Err(e) => {
+                    tracing::warn!("TLS handshake with {} failed: {}", peer, e);
+                    return;
+                }
+            };
+            let conn = http1::Builder::new()
+                .serve_connection(TokioIo::new(stream), TowerToHyperService::new(app))
+                .with_upgrades();
+            tokio::pin!(conn);
+            let result = tokio::select! {
+                result = conn.as_mut() => result,
+                _ = signal.changed() => {
+                    // Finishes the request in progress, then closes the connection
+                    conn.as_mut().graceful_shutdown();
+                    conn.await
+                }
+            };
+            if let Err(e) = result {
+                tracing::debug!("Connection with {} closed with error: {}", peer, e);
+            }
+        });
+    }
+
+    drop(listener);
+    drop(signal_rx);
+    let _ = signal_tx.send(());
+    signal_tx.closed().await;
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use axum::routing::get;
+    use openssl::asn1::Asn1Time;
+    use openssl::bn::BigNum;
+    use openssl::hash::MessageDigest;
+    use openssl::rsa::Rsa;
+    use openssl::x509::X509NameBuilder;
+
+    /// PEM certificate and private key of a fresh self-signed certificate for `localhost`.
+    pub(crate) fn self_signed() -> (Vec<u8>, Vec<u8>) {
+        let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_text("CN", "localhost").unwrap();
+        let name = name.build();
+
+        let mut cert = X509::builder().unwrap();
+        cert.set_version(2).unwrap();
+        cert.set_serial_number(&BigNum::from_u32(1).unwrap().to_asn1_integer().unwrap())
+            .unwrap();
+        cert.set_subject_name(&name).unwrap();
+        cert.set_issuer_name(&name).unwrap();
+        cert.set_pubkey(&key).unwrap();
+        cert.set_not_before(&Asn1Time::days_from_now(0).unwrap())
+            .unwrap();
+        cert.set_not_after(&Asn1Time::days_from_now(1).unwrap())
+            .unwrap();
+        cert.sign(&key, MessageDigest::sha256()).unwrap();
+
+        (
+            cert.build().to_pem().unwrap(),
+            key.private_key_to_pem_pkcs8().unwrap(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_serves_https() {
+        let (cert_pem, key_pem) = self_signed();
+        let acceptor = acceptor_from_pem(&cert_pem, &key_pem).unwrap();
+        let app = Router::new().route("/health", get(|| async { "ok" }));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve(listener, app, acceptor, std::future::pending()));
+
+        let client = reqwest::Client::builder()
+            .danger_accept_invalid_certs(true)
+            .build()
+            .unwrap();
+        let body = client
+            .get(format!("https://{}/health", addr))
+            .send()
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+        assert_eq!(body, "ok");
+
+        let plain = reqwest::Client::new()
+            .get(format!("http://{}/health", addr))
+            .send()
+            .await;
+        assert!(plain.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_drains_connections() {
+        let (cert_pem, key_pem) = self_signed();
+        let acceptor = acceptor_from_pem(&cert_pem, &key_pem).unwrap();
+        let started = std::sync::Arc::new(tokio::sync::Notify::new());
+        let handler_started = started.clone();
+        let app = Router::new().route(
+            "/slow",
+            get(move || async move {
+                handler_started.notify_one();
+                tokio::time::sleep(std::time::Duration::from_millis(300)).await;
+                "done"
+            }),
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel::<()>();
+        let server = tokio::spawn(serve(listener, app, acceptor, async {
+            let _ = shutdown_rx.await;
+        }));
+
+        let client = reqwest::Client::builder()
+            .danger_accept_invalid_certs(true)
+            .build()
+            .unwrap();
+        let request = tokio::spawn(client.get(format!("https://{}/slow", addr)).send());
+        started.notified().await;
+        shutdown_tx.send(()).unwrap();
+
+        let body = request.await.unwrap().unwrap().text().await.unwrap();
+        assert_eq!(body, "done");
+        tokio::time::timeout(std::time::Duration::from_secs(1), server)
+            .await
+            .unwrap()
+            .unwrap();
+    }
+}
@@ -0,0 +1,106 @@
+use axum::extract::Request;
+use axum::http::{HeaderMap, HeaderValue};
+
+pub const TRACEPARENT: &str = "traceparent";
+pub const TRACESTATE: &str = "tracestate";
+
+/// Gives requests arriving without a W3C `traceparent` a new one, so the orchestrator's spans
+/// can still be correlated with each other and with the gateway's logs. A `traceparent` sent by
+/// the client is left as is. The trace id is recorded as `trace_id` on the request span.
+pub async fn ensure_traceparent(mut request: Request) -> Request {
+    let headers = request.headers_mut();
+    if !headers.contains_key(TRACEPARENT) {
+        if let Ok(value) = HeaderValue::from_str(&generate()) {
+            headers.insert(TRACEPARENT, value);
+        }
+    }
+    if let Some(trace_id) = request_trace_id(headers) {
+        tracing::Span::current().record("trace_id", trace_id);
+    }
+    request
+}
+
+/// The trace id of the request's `traceparent`, which every request has once it went through
+/// [`ensure_traceparent`].
+pub fn request_trace_id(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(TRACEPARENT)
+        .and_then(|value| value.to_str().ok())
+        .and_then(trace_id)
+}
+
+/// A version 00 `traceparent` with random trace and parent ids, flagged as sampled so
+/// downstream services record it.
+fn generate() -> String {
+    let mut trace_id = [0u8; 16];
+    let mut parent_id = [0u8; 8];
+    if let Err(e) = openssl::rand::rand_bytes(&mut trace_id)
+        .and_then(|_| openssl::rand::rand_bytes(&mut parent_id))
+    {
+        tracing::warn!("Failed to generate trace ids: {}", e);
+    }
+    format!(
+        "00-{:032x}-{:016x}-01",
+        u128::from_be_bytes(trace_id),
+        u64::from_be_bytes(parent_id)
+    )
+}
+
+/// The trace id of a well-formed `traceparent`.
+fn trace_id(traceparent: &str) -> Option<&str> {
+    let is_hex = |s: &str, len: usize| {
+        s.len() == len && s.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+    };
+    let mut parts = traceparent.split('-');
+    let (Some(version), Some(trace_id), Some(parent_id), Some(flags)) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return None;
+    };
+    let valid = is_hex(version, 2)
+        && version != "ff"
+        && is_hex(trace_id, 32)
+        && trace_id.bytes().any(|b| b != b'0')
+        && is_hex(parent_id, 16)
+        && parent_id.bytes().any(|b| b != b'0')
+        && is_hex(flags, 2)
+        // Later versions may append fields, version 00 may not
+        && (version != "00" || parts.next().is_none());
+    valid.then_some(trace_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generated_traceparent_is_valid() {
+        let traceparent = generate();
+        assert!(trace_id(&traceparent).is_some(), "{}", traceparent);
+        assert!(traceparent.starts_with("00-") && traceparent.ends_with("-01"));
+        assert_ne!(generate(), traceparent);
+    }
+
+    #[test]
+    fn test_trace_id() {
+        assert_eq!(
+            trace_id("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"),
+            Some("4bf92f3577b34da6a3ce929d0e0e4736")
+        );
+        assert_eq!(
+            trace_id("01-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-extra"),
+            Some("4bf92f3577b34da6a3ce929d0e0e4736")
+        );
+        for invalid in [
+            "",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7",
+            "00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01",
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01",
+            "ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-extra",
+        ] {
+            assert_eq!(trace_id(invalid), None, "{}", invalid);
+        }
+    }
+}
@@ -0,0 +1,75 @@
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use axum::body::{Body, Bytes};
+use axum::http::{header, HeaderMap, HeaderValue};
+use axum::response::Response;
+use http_body::Frame;
+
+use crate::api::DetectionSummary;
+
+pub const DETECTIONS_TRAILER: &str = "x-gateway-detections";
+
+/// Sends the detection summary collected while streaming as an `x-gateway-detections` trailer
+/// once the body completes. The trailer is declared in the `trailer` response header; HTTP/1.1
+/// only sends it to clients that asked for trailers with `te: trailers`.
+pub fn with_detections_trailer(
+    response: Response,
+    summary: Arc<Mutex<DetectionSummary>>,
+) -> Response {
+    let (mut parts, body) = response.into_parts();
+    parts.headers.insert(
+        header::TRAILER,
+        HeaderValue::from_static(DETECTIONS_TRAILER),
+    );
+    let body = DetectionsTrailerBody {
+        inner: body,
+        summary,
+        done: false,
+    };
+    Response::from_parts(parts, Body::new(body))
+}
+
+struct DetectionsTrailerBody {
+    inner: Body,
+    summary: Arc<Mutex<DetectionSummary>>,
+    done: bool,
+}
+
+impl http_body::Body for DetectionsTrailerBody {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, axum::Error>>> {
+        let this = self.get_mut();
+        if this.done {
+            return Poll::Ready(None);
+        }
+        match Pin::new(&mut this.inner).poll_frame(cx) {
+            Poll::Ready(None) => {
+                this.done = true;
+                let summary = this.summary.lock().unwrap();
+                let mut trailers = HeaderMap::new();
+                match serde_json::to_string(&*summary)
+                    .map_err(anyhow::Error::from)
+                    .and_then(|json| Ok(HeaderValue::from_str(&json)?))
+                {
+                    Ok(value) => {
+                        trailers.insert(DETECTIONS_TRAILER, value);
+                    }
+                    Err(e) => tracing::error!("Failed to encode detections trailer: {}", e),
+                }
+                Poll::Ready(Some(Ok(Frame::trailers(trailers))))
+            }
+            other => other,
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.done
+    }
+}
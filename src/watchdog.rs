@@ -0,0 +1,97 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::config::WatchdogConfig;
+
+/// Periodically probes how long the async runtime takes to schedule a freshly spawned task.
+/// A runtime starved by blocking work schedules late (or not at all), which a liveness probe
+/// answered by an idle worker would otherwise not reveal.
+#[derive(Debug)]
+pub struct Watchdog {
+    started: Instant,
+    interval: Duration,
+    threshold: Duration,
+    last_beat_ms: AtomicU64,
+    lag_ms: AtomicU64,
+}
+
+impl Watchdog {
+    fn new(config: &WatchdogConfig) -> Self {
+        Watchdog {
+            started: Instant::now(),
+            interval: Duration::from_millis(config.interval_ms),
+            threshold: Duration::from_millis(config.stall_threshold_ms),
+            last_beat_ms: AtomicU64::new(0),
+            lag_ms: AtomicU64::new(0),
+        }
+    }
+
+    pub fn spawn(config: &WatchdogConfig) -> Arc<Self> {
+        let watchdog = Arc::new(Watchdog::new(config));
+        // The probe stops once the watchdog is dropped
+        let probe = Arc::downgrade(&watchdog);
+        let interval = watchdog.interval;
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(interval).await;
+                let Some(probe) = probe.upgrade() else {
+                    break;
+                };
+                let spawned = Instant::now();
+                let scheduled = tokio::spawn(async { Instant::now() }).await;
+                if let Ok(scheduled) = scheduled {
+                    probe.record_beat(scheduled.duration_since(spawned));
+                }
+            }
+        });
+        watchdog
+    }
+
+    fn record_beat(&self, lag: Duration) {
+        if lag > self.threshold {
+            tracing::warn!("Runtime scheduling delay of {:?} exceeds threshold", lag);
+        }
+        self.lag_ms.store(lag.as_millis() as u64, Ordering::Relaxed);
+        self.last_beat_ms
+            .store(self.started.elapsed().as_millis() as u64, Ordering::Relaxed);
+    }
+
+    /// The runtime is considered stalled if the last probe was scheduled too late, or if no probe
+    /// has completed for longer than the interval plus threshold.
+    pub fn is_stalled(&self) -> bool {
+        let lag = Duration::from_millis(self.lag_ms.load(Ordering::Relaxed));
+        let last_beat = Duration::from_millis(self.last_beat_ms.load(Ordering::Relaxed));
+        let since_beat = self.started.elapsed().saturating_sub(last_beat);
+        lag > self.threshold || since_beat > self.interval + self.threshold
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> WatchdogConfig {
+        WatchdogConfig {
+            interval_ms: 1000,
+            stall_threshold_ms: 500,
+        }
+    }
+
+    #[test]
+    fn test_stalled_on_late_scheduling() {
+        let watchdog = Watchdog::new(&config());
+        assert!(!watchdog.is_stalled());
+        watchdog.record_beat(Duration::from_millis(600));
+        assert!(watchdog.is_stalled());
+        watchdog.record_beat(Duration::from_millis(1));
+        assert!(!watchdog.is_stalled());
+    }
+
+    #[test]
+    fn test_stalled_when_probe_stops() {
+        let mut watchdog = Watchdog::new(&config());
+        watchdog.started = Instant::now() - Duration::from_secs(2);
+        assert!(watchdog.is_stalled());
+    }
+}